@@ -1,7 +1,15 @@
+use clap::Args;
 use clap::Parser;
 use clap::ValueEnum;
+use regex::Regex;
 
 use std::path::PathBuf;
+use std::time::SystemTime;
+
+use crate::services::inkscape::SUPPORTED_READ_FORMATS;
+use crate::services::usb_drive::normalize_usb_path;
+use crate::utils::colors::Theme;
+use crate::utils::{parse_duration, parse_utc, SanitizeCase};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None, after_help = "\n\
@@ -14,21 +22,44 @@ For more details, use --help with any command")]
 pub struct Cli {
     #[command(subcommand)]
     pub command: Option<Commands>,
+    /// Whether the terminal has a dark or light background. Overrides the
+    /// `theme` setting; by default, this is guessed from the environment
+    #[arg(long, global = true, value_enum)]
+    pub theme: Option<Theme>,
+    /// The Inkscape executable to use, instead of searching for one.
+    /// Overrides the `inkscape_path` setting
+    #[arg(long, global = true, value_name = "PATH")]
+    pub inkscape_path: Option<PathBuf>,
 }
 
 #[derive(Parser)]
 pub enum Commands {
     /// Watch directory and convert files
-    Watch {
-        /// Directory to watch for new DST files
-        #[arg(short, long)]
-        dir: Option<PathBuf>,
+    Watch(Box<WatchArgs>),
+    /// Convert a design for a machine and copy it to the USB drive
+    Send(SendArgs),
+    /// Convert every design in a directory and its subdirectories
+    Batch {
+        /// Directory to convert
+        dir: PathBuf,
         /// Output format (e.g., 'jef', 'pes')
         #[arg(short, long)]
         output_format: Option<String>,
-        /// Target machine (determines accepted formats)
+        /// Directory for converted files (default: next to each original)
+        #[arg(long)]
+        output: Option<PathBuf>,
+        /// Number of conversions to run at once (default: number of CPUs)
         #[arg(short, long)]
-        machine: Option<String>,
+        jobs: Option<usize>,
+        /// Don't show a progress bar
+        #[arg(short, long)]
+        quiet: bool,
+        /// Only convert files modified since this time: a duration such as
+        /// '2h' or '3d', a UTC date such as '2024-05-01' or '2024-05-01
+        /// 14:30', or 'last' for the start of the last run of this directory
+        /// that converted every file
+        #[arg(long, value_name = "TIME", value_parser = parse_since)]
+        since: Option<Since>,
     },
     /// Set default machine (alias for 'config set machine')
     Set {
@@ -36,6 +67,9 @@ pub enum Commands {
         what: String,
         /// Value to set (if not provided, will prompt for input)
         value: Option<String>,
+        /// Minimum similarity (0.0-1.0) for suggesting a machine when the name doesn't match exactly [default: 0.8]
+        #[arg(long, value_name = "THRESHOLD", value_parser = parse_match_threshold)]
+        match_threshold: Option<f64>,
     },
     /// Machine-related commands
     Machine {
@@ -43,26 +77,90 @@ pub enum Commands {
         command: MachineCommand,
     },
     /// List all supported machines (alias for 'machine list')
+    #[command(args_conflicts_with_subcommands = true)]
     Machines {
+        #[command(subcommand)]
+        command: Option<MachinesCommand>,
         /// Filter by file format
         #[arg(short, long)]
         format: Option<String>,
+        /// Filter by manufacturer, e.g. 'brother'
+        #[arg(long)]
+        manufacturer: Option<String>,
         /// Verbose output
         #[arg(short, long)]
         verbose: bool,
+        /// Show summary statistics instead of the list of machines
+        #[arg(long, conflicts_with = "verbose")]
+        stats: bool,
     },
     /// List supported file formats
-    Formats,
+    Formats {
+        /// Show only the format with this extension, e.g. 'jef'
+        #[arg(short, long)]
+        extension: Option<String>,
+    },
+    /// List the formats that a design file can be converted to
+    ConvertOptions {
+        /// The design file
+        file: PathBuf,
+    },
+    /// Show which of each machine's formats ink/stitch can write
+    Compat {
+        /// Show only this machine
+        #[arg(short, long)]
+        machine: Option<String>,
+        /// Show only machines that read this format, e.g. 'jef'
+        #[arg(short, long)]
+        format: Option<String>,
+    },
+    /// List the USB drives that stitch-sync detects
+    Drives {
+        #[command(subcommand)]
+        command: Option<DrivesCommand>,
+    },
+    /// Check that Inkscape and ink/stitch are installed
+    Doctor {
+        /// Also convert a small sample design, to check that conversions work
+        #[arg(long)]
+        test_convert: bool,
+        /// Print the results as a JSON object
+        #[arg(long, conflicts_with = "test_convert")]
+        json: bool,
+    },
     /// Configuration commands
     Config {
         #[command(subcommand)]
         command: ConfigCommand,
     },
+    /// Show recent conversions and copies
+    History {
+        /// Number of entries to show
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: usize,
+        /// Print entries as JSON lines
+        #[arg(long)]
+        json: bool,
+    },
     /// Update stitch-sync to the latest version
     Update {
         /// Check for updates but don't install them
         #[arg(long)]
         dry_run: bool,
+        /// Print the result as a JSON object, for scripts
+        #[arg(long)]
+        json: bool,
+        /// Exit with status 10 if an update was installed, or with --dry-run
+        /// if one is available, instead of 0
+        #[arg(long)]
+        exit_code: bool,
+    },
+    /// Show where stitch-sync keeps its configuration, cache, and history
+    #[command(visible_alias = "reveal")]
+    Dirs {
+        /// Also open the configuration directory in the file manager
+        #[arg(long)]
+        open: bool,
     },
     /// Open the project homepage
     Homepage,
@@ -70,6 +168,221 @@ pub enum Commands {
     ReportBug,
     /// Show version and build information
     Version,
+    /// Print machine names that start with PREFIX, for use by shell completion scripts
+    #[command(name = "__complete-machines", hide = true)]
+    CompleteMachines {
+        /// Only print names that start with this (case-insensitive)
+        prefix: Option<String>,
+    },
+}
+
+#[derive(Args, Default)]
+pub struct WatchArgs {
+    /// Directory to watch for new DST files. Repeat to watch several
+    #[arg(short, long)]
+    pub dir: Vec<PathBuf>,
+    /// Output format (e.g., 'jef', 'pes')
+    #[arg(short, long)]
+    pub output_format: Option<String>,
+    /// Target machine (determines accepted formats)
+    #[arg(short, long)]
+    pub machine: Option<String>,
+    /// Minimum similarity (0.0-1.0) for suggesting a machine when the name doesn't match exactly [default: 0.8]
+    #[arg(long, value_name = "THRESHOLD", value_parser = parse_match_threshold)]
+    pub match_threshold: Option<f64>,
+    /// Show the machines most similar to the machine name, with their scores
+    #[arg(short, long)]
+    pub verbose: bool,
+    /// Replace existing files instead of saving as "name (1)", "name (2)", etc.
+    #[arg(long)]
+    pub overwrite: bool,
+    /// At startup, delete files that stitch-sync converted or copied more than this many days ago
+    #[arg(long, value_name = "DAYS")]
+    pub prune_older_than: Option<u64>,
+    /// Create the watch directory if it doesn't exist
+    #[arg(long, conflicts_with = "wait_for_dir")]
+    pub create_dir: bool,
+    /// Wait for the watch directory to be created, instead of exiting
+    #[arg(long)]
+    pub wait_for_dir: bool,
+    /// Copy files to the root of the USB drive, instead of the machine's designs directory
+    #[arg(long)]
+    pub usb_root: bool,
+    /// Copy files into a folder named after the machine, inside the USB
+    /// directory, creating it if needed. Keeps one drive organized when it's
+    /// shared by several machines
+    #[arg(long)]
+    pub machine_subdir: bool,
+    /// Eject the USB drive after copying a file to it
+    #[arg(long)]
+    pub eject_after: bool,
+    /// After a file has been copied to the USB drive, move its source into an "archive" subdirectory
+    #[arg(long, conflicts_with = "delete_source")]
+    pub move_source: bool,
+    /// After a file has been copied to the USB drive, delete its source
+    #[arg(long)]
+    pub delete_source: bool,
+    /// Copy files that are already in the machine's formats, without converting other files
+    #[arg(long)]
+    pub no_convert: bool,
+    /// Of the machine's formats, copy only these as-is, e.g. 'jef'. Files in
+    /// its other formats are converted to the output format
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        value_delimiter = ',',
+        conflicts_with = "no_convert"
+    )]
+    pub copy_only_format: Vec<String>,
+    /// Copy files to the USB drive even if an identical copy is already there
+    #[arg(long)]
+    pub force_copy: bool,
+    /// When a file is converted, also copy the original to a "source"
+    /// subdirectory of the USB folder
+    #[arg(long, conflicts_with = "no_convert")]
+    pub copy_source_too: bool,
+    /// Treat files whose format isn't recognized from their extension or
+    /// content as this format, e.g. 'dst' for DST files named '.bin'
+    #[arg(long, value_name = "FORMAT", value_parser = parse_input_format)]
+    pub input_format: Option<String>,
+    /// Only process files whose names match this regular expression, e.g.
+    /// '^JOB-'. Other files are ignored
+    #[arg(long, value_name = "REGEX", value_parser = parse_regex)]
+    pub watch_pattern: Option<Regex>,
+    /// Also process hidden files (names starting with '.' or '~') and
+    /// partial downloads ('.crdownload', '.part', '.tmp'), which are
+    /// otherwise ignored
+    #[arg(long)]
+    pub include_hidden: bool,
+    /// At startup, list the N most recently modified files already in the
+    /// watch directory, marking those that would be converted or copied.
+    /// Nothing is converted
+    #[arg(long, value_name = "N")]
+    pub show_recent: Option<usize>,
+    /// When a .zip file arrives, extract it and process the first design
+    /// inside, instead of ignoring it
+    #[arg(long)]
+    pub extract_archives: bool,
+    /// Also convert vector artwork (SVG, PDF and AI files) to stitches with
+    /// ink/stitch's default settings. Otherwise these are ignored
+    #[arg(long)]
+    pub import_vectors: bool,
+    /// Scale converted designs by this factor, e.g. 0.75 for designs drawn
+    /// at 72 DPI [default: the machine's configured scale, or 1]
+    #[arg(long, value_name = "FACTOR", value_parser = parse_scale)]
+    pub scale: Option<f64>,
+    /// The case of letters in output filenames [default: the
+    /// `sanitize_case` setting, or lower]
+    #[arg(long, value_name = "CASE")]
+    pub sanitize_case: Option<SanitizeCase>,
+    /// Warn before copying a DST or JEF design with more than this many
+    /// stitches, e.g. 200000 for a machine that can't handle larger designs
+    #[arg(long, value_name = "N")]
+    pub max_stitches: Option<u32>,
+    /// Don't copy designs with more than --max-stitches stitches
+    #[arg(long, requires = "max_stitches")]
+    pub skip_over_max_stitches: bool,
+    /// How often to check for new files and key presses, in milliseconds.
+    /// Higher values use less CPU; lower values respond faster [default: 100]
+    #[arg(long, value_name = "MS", value_parser = clap::value_parser!(u64).range(10..=5000))]
+    pub poll_interval_ms: Option<u64>,
+    /// Scan the directory for new files every second, instead of relying on
+    /// file system events. Use this for network shares (SMB, NFS), which often
+    /// don't deliver events
+    #[arg(long)]
+    pub poll: bool,
+    /// Also watch subdirectories of the watch directory. Files converted
+    /// from a subdirectory are written to the same subdirectory of the output
+    /// directory and the USB drive
+    #[arg(short, long)]
+    pub recursive: bool,
+    /// With --recursive, write files from subdirectories directly into the
+    /// output directory and USB folder, instead of into subdirectories
+    #[arg(long, requires = "recursive")]
+    pub flatten: bool,
+    /// With --recursive, ignore files more than this many subdirectories
+    /// below the watch directory. 1 includes files in subdirectories of the
+    /// watch directory, but not in their subdirectories
+    #[arg(long, value_name = "N", requires = "recursive")]
+    pub max_depth: Option<usize>,
+    /// With --recursive, also watch the directories that symlinks in the
+    /// watch directory point to. Symlinks that lead back to a directory that
+    /// is already watched are skipped
+    #[arg(long, requires = "recursive")]
+    pub follow_symlinks: bool,
+    /// How to report progress on stdout: 'text', or 'ndjson' for one JSON
+    /// object per line for each step in handling a file (detected,
+    /// converting, done, failed, copied). With 'ndjson', the spinner is
+    /// hidden and other messages are written to stderr
+    #[arg(long, value_enum, default_value_t, value_name = "FORMAT")]
+    pub stdout_format: StdoutFormat,
+    /// Print one line for each file that is converted or copied, and hide
+    /// the spinner and conversion progress, so that the terminal keeps a
+    /// readable record of the session after quitting
+    #[arg(long, conflicts_with = "stdout_format")]
+    pub transcript: bool,
+    /// Publish events for each file (detected, converting, done, failed,
+    /// copied) as lines of JSON to clients of a Unix domain socket at this path
+    #[arg(long, value_name = "PATH")]
+    pub event_socket: Option<PathBuf>,
+    /// Only copy to the USB drive with this name or mount point. Repeat to
+    /// allow several drives. Overrides the `usb_allowlist` setting
+    #[arg(long = "usb-name", value_name = "NAME")]
+    pub usb_names: Vec<String>,
+    /// Copy to this directory on the USB drive with this name or mount
+    /// point, e.g. 'JANOME-USB:Embf', or 'JANOME-USB:' for the drive's root.
+    /// Overrides the machine's USB directory and the `usb_allowlist` setting
+    #[arg(long, value_name = "DRIVE:DIR", value_parser = parse_usb_target, conflicts_with_all = ["usb_names", "usb_root"])]
+    pub usb_target: Option<UsbTarget>,
+    /// While no files arrive, print a "Still watching" line this often, in
+    /// minutes. 0 turns these messages off [default: 5]
+    #[arg(long, value_name = "MINUTES")]
+    pub heartbeat: Option<u64>,
+    /// Don't print the periodic "Still watching" line
+    #[arg(short, long)]
+    pub quiet: bool,
+    /// Stop watching, and print a summary, once no new file has arrived for
+    /// this many minutes
+    #[arg(long, visible_alias = "watch-timeout", value_name = "MINUTES", value_parser = clap::value_parser!(u64).range(1..))]
+    pub idle_timeout: Option<u64>,
+    /// Answer yes to confirmation prompts, such as before ejecting a drive
+    #[arg(short, long)]
+    pub yes: bool,
+}
+
+#[derive(Args, Default)]
+pub struct SendArgs {
+    /// The design file to send
+    pub file: PathBuf,
+    /// Target machine (determines accepted formats and the USB directory)
+    #[arg(short, long)]
+    pub machine: Option<String>,
+    /// Output format (e.g., 'jef', 'pes'; default: the machine's first format)
+    #[arg(short, long)]
+    pub output_format: Option<String>,
+    /// Minimum similarity (0.0-1.0) for suggesting a machine when the name doesn't match exactly [default: 0.8]
+    #[arg(long, value_name = "THRESHOLD", value_parser = parse_match_threshold)]
+    pub match_threshold: Option<f64>,
+    /// Show the machines most similar to the machine name, with their scores
+    #[arg(short, long)]
+    pub verbose: bool,
+    /// Copy the file to the root of the USB drive, instead of the machine's designs directory
+    #[arg(long)]
+    pub usb_root: bool,
+    /// Scale the converted design by this factor [default: the machine's
+    /// configured scale, or 1]
+    #[arg(long, value_name = "FACTOR", value_parser = parse_scale)]
+    pub scale: Option<f64>,
+    /// The case of letters in the copied file's name [default: the
+    /// `sanitize_case` setting, or lower]
+    #[arg(long, value_name = "CASE")]
+    pub sanitize_case: Option<SanitizeCase>,
+    /// Eject the USB drive after copying the file to it
+    #[arg(long)]
+    pub eject: bool,
+    /// Answer yes to confirmation prompts, such as creating the USB directory
+    #[arg(short, long)]
+    pub yes: bool,
 }
 
 #[derive(Parser)]
@@ -79,14 +392,45 @@ pub enum MachineCommand {
         /// Filter by file format
         #[arg(short, long)]
         format: Option<String>,
+        /// Filter by manufacturer, e.g. 'brother'
+        #[arg(long)]
+        manufacturer: Option<String>,
         /// Verbose output
         #[arg(short, long)]
         verbose: bool,
     },
+    /// Open your own machine list in $EDITOR. Its machines are added to the
+    /// built-in ones
+    Edit,
     /// Show detailed information for a specific machine
     Info {
         /// Name of the machine
         name: String,
+        /// Minimum similarity (0.0-1.0) for suggesting a machine when the name doesn't match exactly [default: 0.8]
+        #[arg(long, value_name = "THRESHOLD", value_parser = parse_match_threshold)]
+        match_threshold: Option<f64>,
+        /// Show the machines most similar to the name, with their scores
+        #[arg(short, long)]
+        verbose: bool,
+    },
+}
+
+#[derive(Parser)]
+pub enum MachinesCommand {
+    /// Open your own machine list in $EDITOR (same as 'machine edit')
+    Edit,
+}
+
+#[derive(Parser)]
+pub enum DrivesCommand {
+    /// Write a test file to a USB drive, read it back, delete it, and eject
+    /// the drive, timing each step. Helps tell a failing drive from a
+    /// stitch-sync bug
+    Test {
+        /// The drive to test, by name or mount point [default: the only
+        /// USB drive]
+        #[arg(short, long)]
+        drive: Option<String>,
     },
 }
 
@@ -100,12 +444,24 @@ pub enum ConfigCommand {
         key: ConfigKey,
         /// Value to set (if not provided, will prompt for input)
         value: Option<String>,
+        /// Minimum similarity (0.0-1.0) for suggesting a machine when the name doesn't match exactly [default: 0.8]
+        #[arg(long, value_name = "THRESHOLD", value_parser = parse_match_threshold)]
+        match_threshold: Option<f64>,
     },
     /// Clear a configuration value
     Clear {
         #[arg(value_enum)]
         key: ConfigKey,
     },
+    /// Open the configuration file in $EDITOR
+    Edit,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, ValueEnum)]
+pub enum StdoutFormat {
+    #[default]
+    Text,
+    Ndjson,
 }
 
 #[derive(Debug, Clone, PartialEq, ValueEnum)]
@@ -114,3 +470,79 @@ pub enum ConfigKey {
     WatchDir,
     Machine,
 }
+
+/// The time given to `batch --since`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Since {
+    Time(SystemTime),
+    /// The start of the last complete run of the directory
+    LastRun,
+}
+
+fn parse_since(s: &str) -> Result<Since, String> {
+    if s == "last" {
+        return Ok(Since::LastRun);
+    }
+    parse_duration(s)
+        .and_then(|duration| SystemTime::now().checked_sub(duration))
+        .or_else(|| parse_utc(s))
+        .map(Since::Time)
+        .ok_or_else(|| {
+            "expected a duration such as '2h', a date such as '2024-05-01', or 'last'".to_string()
+        })
+}
+
+/// A USB drive, and a directory on it, given as `DRIVE:DIR`
+#[derive(Debug, Clone, PartialEq)]
+pub struct UsbTarget {
+    /// The drive's name (volume label) or mount point
+    pub drive: String,
+    /// A directory relative to the drive's root, or "" for the root
+    pub path: String,
+}
+
+fn parse_usb_target(s: &str) -> Result<UsbTarget, String> {
+    // Split at the last colon, so that a Windows mount point such as 'E:'
+    // can name the drive. Directories can't contain colons.
+    let (drive, path) = s
+        .rsplit_once(':')
+        .filter(|(drive, _)| !drive.is_empty())
+        .ok_or("expected DRIVE:DIR, e.g. 'JANOME-USB:Embf'")?;
+    Ok(UsbTarget {
+        drive: drive.to_string(),
+        path: normalize_usb_path(path).map_err(|e| e.to_string())?,
+    })
+}
+
+fn parse_regex(s: &str) -> Result<Regex, String> {
+    Regex::new(s).map_err(|e| e.to_string())
+}
+
+/// Reads a format that ink/stitch can read, such as 'dst' or '.DST', as a
+/// lowercase extension.
+fn parse_input_format(s: &str) -> Result<String, String> {
+    let format = s.trim_start_matches('.').to_lowercase();
+    if SUPPORTED_READ_FORMATS.contains(&format.as_str()) {
+        Ok(format)
+    } else {
+        Err(format!("'{}' is not a format that can be read", s))
+    }
+}
+
+fn parse_scale(s: &str) -> Result<f64, String> {
+    let scale: f64 = s.parse().map_err(|_| format!("'{}' is not a number", s))?;
+    if scale > 0.0 && scale.is_finite() {
+        Ok(scale)
+    } else {
+        Err("must be greater than 0".to_string())
+    }
+}
+
+fn parse_match_threshold(s: &str) -> Result<f64, String> {
+    let threshold: f64 = s.parse().map_err(|_| format!("'{}' is not a number", s))?;
+    if (0.0..=1.0).contains(&threshold) {
+        Ok(threshold)
+    } else {
+        Err("must be between 0.0 and 1.0".to_string())
+    }
+}
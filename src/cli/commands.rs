@@ -1,45 +1,206 @@
-use anyhow::Result;
-use colored::Colorize as Colorize;
+use anyhow::{Context, Result};
+use colored::Colorize;
 use crossterm::style::Stylize;
-use reqwest;
+use serde::Serialize;
 
 use std::env;
+use std::fmt;
 use std::fs;
-use std::io::Write;
-use std::path::PathBuf;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
 use std::process;
+use std::time::Duration;
 
 use crate::config::defaults::DEFAULT_FORMAT;
+use crate::config::manager::{find_project_config, open_in_editor};
+use crate::config::types::{scale_for, Config};
 use crate::config::ConfigManager;
 use crate::print_error;
-use crate::write_notice;
 use crate::services;
+use crate::services::event_socket::EventPublisher;
 use crate::services::find_usb_containing_path;
+use crate::services::history;
 use crate::services::inkscape;
+use crate::services::usb_drive::{check_writable, normalize_usb_path, UsbDrive};
+use crate::services::ConversionOptions;
 use crate::services::Inkscape;
+use crate::services::OutputManifest;
+use crate::services::SourceAction;
+use crate::services::WatchOptions;
+use crate::services::HEARTBEAT_INTERVAL;
+use crate::types::machine::{self, DEFAULT_MATCH_THRESHOLD};
+use crate::types::DesignSize;
+use crate::types::FileFormat;
 use crate::types::Machine;
+use crate::types::MatchOptions;
 use crate::types::FILE_FORMATS;
 use crate::types::MACHINES;
 use crate::utils;
-use crate::utils::version;
+use crate::utils::app_dirs::AppDir;
 use crate::utils::prompt_yes_no;
-use crate::services::usb_drive::UsbDrive;
+use crate::utils::version;
+use crate::utils::version::UpdateUrls;
+use crate::utils::DIRECTORY_POLL_INTERVAL;
+use crate::utils::WATCH_POLL_INTERVAL;
+use crate::write_error;
+use crate::write_notice;
+use crate::write_warning;
+
+use super::{
+    Cli, Commands, ConfigCommand, ConfigKey, DrivesCommand, MachineCommand, MachinesCommand,
+    SendArgs, Since, StdoutFormat, WatchArgs,
+};
+
+impl Cli {
+    /// Colors output for the theme given by --theme, or else the configured
+    /// theme.
+    pub fn apply_theme(&self) {
+        let theme = self.theme.or_else(|| {
+            ConfigManager::new()
+                .and_then(|manager| manager.load_merged())
+                .ok()
+                .and_then(|config| config.theme)
+        });
+        utils::colors::set_theme(theme.unwrap_or_default());
+    }
+
+    /// Uses the Inkscape given by --inkscape-path, or else by the
+    /// `inkscape_path` setting, if either is set. Returns an error if it
+    /// isn't an executable file. The path is made absolute, since the
+    /// ink/stitch extension is looked for relative to it.
+    pub fn apply_inkscape_path(&self) -> Result<()> {
+        if let Some(path) = &self.inkscape_path {
+            inkscape::check_inkscape_path(path)
+                .map_err(|e| anyhow::anyhow!("--inkscape-path {}: {}", path.display(), e))?;
+            inkscape::set_inkscape_path(fs::canonicalize(path)?);
+            return Ok(());
+        }
+        // The config commands are how a bad setting gets fixed
+        if matches!(self.command, Some(Commands::Config { .. })) {
+            return Ok(());
+        }
+        let path = ConfigManager::new()
+            .and_then(|manager| manager.load_merged())
+            .ok()
+            .and_then(|config| config.inkscape_path);
+        if let Some(path) = path {
+            inkscape::check_inkscape_path(&path).map_err(|e| {
+                anyhow::anyhow!(
+                    "Can't use the inkscape_path setting, {}: {}. Run 'stitch-sync config edit' to change it.",
+                    path.display(),
+                    e
+                )
+            })?;
+            inkscape::set_inkscape_path(fs::canonicalize(&path)?);
+        }
+        Ok(())
+    }
 
-use super::{Commands, ConfigCommand, ConfigKey, MachineCommand};
+    /// Adds the machines from the configured `machines_url`, if there is one,
+    /// and from the user's machine list, for commands that resolve or list
+    /// machines. If they can't be loaded, only the built-in machines are used.
+    /// Shell completion only uses a list that's already been downloaded, so
+    /// that it never waits for the network.
+    pub fn load_machines(&self) {
+        if !self.uses_machines() {
+            return;
+        }
+        let Ok(config_manager) = ConfigManager::new() else {
+            return;
+        };
+        let mut machines = Vec::new();
+        if let Some(url) = config_manager
+            .load_merged()
+            .ok()
+            .and_then(|config| config.machines_url)
+        {
+            if matches!(self.command, Some(Commands::CompleteMachines { .. })) {
+                machines
+                    .extend(services::remote_machines::cached_machines(&url).unwrap_or_default());
+            } else {
+                match services::remote_machines::load_remote_machines(&url) {
+                    Ok(remote_machines) => machines.extend(remote_machines),
+                    Err(e) => eprintln!(
+                        "Warning: Couldn't load machines from {}: {}. Using the built-in machine list.",
+                        url, e
+                    ),
+                }
+            }
+        }
+        // Listed last, so that the user's own machines take precedence
+        machines.extend(load_user_machines(&config_manager.machines_path()));
+        if !machines.is_empty() {
+            machine::add_machines(machines);
+        }
+    }
+
+    /// Whether the command resolves a machine name or lists machines.
+    /// Editing the user's list isn't included, since it reports the list's
+    /// errors once it's saved.
+    pub(super) fn uses_machines(&self) -> bool {
+        match &self.command {
+            None
+            | Some(
+                Commands::Watch(_)
+                | Commands::Send(_)
+                | Commands::Set { .. }
+                | Commands::Compat { .. }
+                | Commands::CompleteMachines { .. },
+            ) => true,
+            Some(Commands::Machine { command }) => !matches!(command, MachineCommand::Edit),
+            Some(Commands::Machines { command, .. }) => command.is_none(),
+            Some(Commands::Config { command }) => matches!(
+                command,
+                ConfigCommand::Set {
+                    key: ConfigKey::Machine,
+                    ..
+                }
+            ),
+            _ => false,
+        }
+    }
+}
+
+/// Reads the user's machine list, if there is one. Rows that can't be parsed
+/// are reported and skipped.
+fn load_user_machines(path: &Path) -> Vec<Machine> {
+    let Ok(csv) = fs::read_to_string(path) else {
+        return Vec::new();
+    };
+    let (machines, errors) = machine::parse_machines(&csv);
+    for error in errors {
+        eprintln!(
+            "Warning: skipped a machine in {}: {}",
+            path.display(),
+            error
+        );
+    }
+    machines
+}
 
 impl Commands {
     pub fn execute<W: Write>(self, writer: &mut W) -> Result<()> {
         match self {
-            Commands::Watch {
+            Commands::Watch(args) => watch_command(*args, writer),
+            Commands::Send(args) => send_command(args, writer),
+            Commands::Batch {
                 dir,
                 output_format,
-                machine,
-            } => watch_command(dir, output_format, machine, writer),
-            Commands::Set { what, value } => {
+                output,
+                jobs,
+                quiet,
+                since,
+            } => batch_command(dir, output_format, output, jobs, quiet, since, writer),
+            Commands::Set {
+                what,
+                value,
+                match_threshold,
+            } => {
                 if what == "machine" {
                     ConfigCommand::Set {
                         key: ConfigKey::Machine,
                         value,
+                        match_threshold,
                     }
                     .execute(writer)
                 } else {
@@ -52,15 +213,54 @@ impl Commands {
                 }
             }
             Commands::Machine { command } => command.execute(writer),
-            Commands::Machines { format, verbose } => {
-                list_machines_command(format, verbose, writer)
+            Commands::Machines {
+                command: Some(MachinesCommand::Edit),
+                ..
+            } => edit_machines_command(writer),
+            Commands::Machines {
+                command: None,
+                format,
+                manufacturer,
+                verbose,
+                stats,
+            } => {
+                let machines = filter_machines(format, manufacturer);
+                if stats {
+                    machine_stats_command(&machines, writer)
+                } else {
+                    list_machines_command(&machines, verbose, writer)
+                }
             }
-            Commands::Formats => Self::list_formats(writer),
+            Commands::Formats { extension: None } => Self::list_formats(writer),
+            Commands::Formats {
+                extension: Some(extension),
+            } => Self::show_format(&extension, writer),
+            Commands::ConvertOptions { file } => Self::convert_options(&file, writer),
+            Commands::Compat { machine, format } => compat_command(machine, format, writer),
+            Commands::Drives { command: None } => drives_command(writer),
+            Commands::Drives {
+                command: Some(DrivesCommand::Test { drive }),
+            } => drives_test_command(drive, writer),
+            Commands::Doctor {
+                test_convert: _,
+                json: true,
+            } => doctor_json_command(writer),
+            Commands::Doctor {
+                test_convert,
+                json: false,
+            } => doctor_command(test_convert, writer),
             Commands::Config { command } => command.execute(writer),
-            Commands::Update { dry_run } => update_command(dry_run, writer),
+            Commands::History { limit, json } => history_command(limit, json, writer),
+            Commands::Update {
+                dry_run,
+                json,
+                exit_code,
+            } => update_command(dry_run, json, exit_code, writer),
+            Commands::Dirs { open } => dirs_command(open, writer),
             Commands::Homepage => homepage_command(writer),
             Commands::ReportBug => report_bug_command(writer),
             Commands::Version => version_command(writer),
+            Commands::CompleteMachines { prefix } => complete_machines_command(prefix, writer),
         }
     }
 
@@ -69,12 +269,76 @@ impl Commands {
         formats.sort_by_key(|format| format.extension.to_owned());
 
         for format in formats {
-            write!(writer, "{}: {}", format.extension, format.manufacturer)?;
-            if let Some(notes) = format.notes {
-                write!(writer, " -- {}", notes)?;
+            Self::write_format(&format, writer)?;
+        }
+        Ok(())
+    }
+
+    fn show_format<W: Write>(extension: &str, writer: &mut W) -> Result<()> {
+        let extension = extension.trim_start_matches('.');
+        match FileFormat::find_by_extension(extension) {
+            Some(format) => Self::write_format(format, writer),
+            None => anyhow::bail!(
+                "Unknown format: {}. Run 'stitch-sync formats' to list the supported formats.",
+                extension
+            ),
+        }
+    }
+
+    fn convert_options<W: Write>(file: &Path, writer: &mut W) -> Result<()> {
+        if !file.is_file() {
+            anyhow::bail!("File not found: {}", file.display());
+        }
+        let format = services::file_format(file);
+        if !inkscape::SUPPORTED_READ_FORMATS.contains(&format.as_str()) {
+            anyhow::bail!(
+                "{} can't be converted: ink/stitch can't read {} files",
+                file.display(),
+                if format.is_empty() { "these" } else { &format }
+            );
+        }
+
+        writeln!(
+            writer,
+            "{} ({}) can be converted to:",
+            file.display(),
+            format
+        )?;
+        let mut targets = inkscape::SUPPORTED_WRITE_FORMATS
+            .iter()
+            .filter(|target| **target != format)
+            .collect::<Vec<_>>();
+        targets.sort();
+        for target in targets {
+            write!(writer, "  ")?;
+            match FileFormat::find_by_extension(target) {
+                Some(target) => Self::write_format(target, writer)?,
+                None => writeln!(writer, "{}", target)?,
             }
-            writeln!(writer)?;
         }
+        writeln!(
+            writer,
+            "Image previews: {}",
+            inkscape::IMAGE_EXPORT_FORMATS.join(", ")
+        )?;
+
+        if !Inkscape::find_app().is_some_and(|inkscape| inkscape.has_inkstitch) {
+            write_warning!(
+                writer,
+                "Converting requires Inkscape and the ink/stitch extension. Download them from {} and {}",
+                inkscape::INKSCAPE_DOWNLOAD_URL,
+                inkscape::INKSTITCH_INSTALL_URL
+            );
+        }
+        Ok(())
+    }
+
+    fn write_format<W: Write>(format: &FileFormat, writer: &mut W) -> Result<()> {
+        write!(writer, "{}: {}", format.extension, format.manufacturer)?;
+        if let Some(notes) = &format.notes {
+            write!(writer, " -- {}", notes)?;
+        }
+        writeln!(writer)?;
         Ok(())
     }
 }
@@ -84,16 +348,62 @@ impl ConfigCommand {
         let config_manager = ConfigManager::new()?;
         match self {
             ConfigCommand::Show => {
-                let config = config_manager.load()?;
-                if let Some(dir) = &config.watch_dir {
+                let config = config_manager.load_merged()?;
+                for dir in config.watch_dirs() {
                     writeln!(writer, "Watch directory: {}", dir.display())?;
                 }
                 if let Some(machine) = &config.machine {
                     writeln!(writer, "Default machine: {}", machine)?;
                 }
+                if let Some(format) = &config.output_format {
+                    writeln!(writer, "Output format: {}", format)?;
+                }
+                if let Some(dir) = &config.output_dir {
+                    writeln!(writer, "Output directory: {}", dir.display())?;
+                }
+                if let Some(theme) = &config.theme {
+                    writeln!(writer, "Theme: {}", theme)?;
+                }
+                if let Some(names) = &config.usb_allowlist {
+                    writeln!(writer, "USB drives: {}", names.join(", "))?;
+                }
+                if let Some(case) = &config.sanitize_case {
+                    writeln!(writer, "Filename case: {}", case)?;
+                }
+                if let Some(path) = &config.inkscape_path {
+                    writeln!(writer, "Inkscape: {}", path.display())?;
+                }
+                if let Some(path) = &config.usb_subpath {
+                    writeln!(writer, "USB subpath (all drives): {}", path)?;
+                }
+                for (format, args) in config.presets.iter().flatten() {
+                    writeln!(writer, "Preset for {}: {}", format, args.join(" "))?;
+                }
+                for (machine, scale) in config.scales.iter().flatten() {
+                    writeln!(writer, "Scale for {}: {}", machine, scale)?;
+                }
+                if let Some(url) = &config.update_api_url {
+                    writeln!(writer, "Update API URL: {}", url)?;
+                }
+                if let Some(url) = &config.update_base_url {
+                    writeln!(writer, "Update download URL: {}", url)?;
+                }
+                if let Some(url) = &config.machines_url {
+                    writeln!(writer, "Machines URL: {}", url)?;
+                }
+                if let Some(path) = env::current_dir()
+                    .ok()
+                    .and_then(|dir| find_project_config(&dir))
+                {
+                    writeln!(writer, "Project config: {}", path.display())?;
+                }
                 Ok(())
             }
-            ConfigCommand::Set { key, value } => match key {
+            ConfigCommand::Set {
+                key,
+                value,
+                match_threshold,
+            } => match key {
                 ConfigKey::WatchDir => {
                     let path = PathBuf::from(value.expect("Watch directory path is required"));
                     config_manager.set_watch_dir(path)?;
@@ -101,7 +411,7 @@ impl ConfigCommand {
                     Ok(())
                 }
                 ConfigKey::Machine => {
-                    let machine = Self::select_machine(value);
+                    let machine = Self::select_machine(value, &match_options(match_threshold));
                     if let Some(machine) = machine {
                         config_manager.set_machine(machine.name)?;
                         writeln!(writer, "Default machine set")?;
@@ -123,12 +433,22 @@ impl ConfigCommand {
                     Ok(())
                 }
             },
+            ConfigCommand::Edit => {
+                config_manager.edit().map_err(|e| {
+                    anyhow::anyhow!(
+                        "{}. Run 'stitch-sync config edit' again to fix it.",
+                        format!("{:#}", e).trim_end()
+                    )
+                })?;
+                writeln!(writer, "Configuration saved")?;
+                Ok(())
+            }
         }
     }
 
-    pub fn select_machine(value: Option<String>) -> Option<Machine> {
+    pub fn select_machine(value: Option<String>, options: &MatchOptions) -> Option<Machine> {
         if let Some(name) = value {
-            Machine::interactive_find_by_name(&name)
+            Machine::interactive_find_by_name(&name, options)
         } else {
             // Show list of all machines and let user choose
             println!("Select your embroidery machine:");
@@ -151,31 +471,41 @@ impl ConfigCommand {
 impl MachineCommand {
     pub fn execute<W: Write>(self, writer: &mut W) -> Result<()> {
         match self {
-            MachineCommand::List { format, verbose } => {
-                list_machines_command(format, verbose, writer)
+            MachineCommand::List {
+                format,
+                manufacturer,
+                verbose,
+            } => list_machines_command(&filter_machines(format, manufacturer), verbose, writer),
+            MachineCommand::Edit => edit_machines_command(writer),
+            MachineCommand::Info {
+                name,
+                match_threshold,
+                verbose,
+            } => {
+                let options = MatchOptions {
+                    verbose,
+                    ..match_options(match_threshold)
+                };
+                Self::show_info(name, &options, writer)
             }
-            MachineCommand::Info { name } => Self::show_info(name, writer),
         }
     }
 
-    fn show_info<W: Write>(name: String, writer: &mut W) -> Result<()> {
-        match Machine::interactive_find_by_name(&name) {
+    fn show_info<W: Write>(name: String, options: &MatchOptions, writer: &mut W) -> Result<()> {
+        if options.verbose {
+            write_match_trace(&name, options, writer)?;
+        }
+        match Machine::interactive_find_by_name(&name, options) {
             Some(info) => {
                 writeln!(writer, "{}", info.name)?;
-                if let Some(notes) = &info.notes {
-                    writeln!(writer, "  Notes: {}", notes)?;
-                }
-                if !info.synonyms.is_empty() {
-                    writeln!(writer, "  Synonyms: {}", info.synonyms.join(", "))?;
-                }
-                if !info.file_formats.is_empty() {
-                    writeln!(writer, "  Formats: {}", info.file_formats.join(", "))?;
-                }
-                if let Some(design_size) = &info.design_size {
-                    writeln!(writer, "  Design size: {}", design_size)?;
-                }
-                if let Some(path) = &info.usb_path {
-                    writeln!(writer, "  USB path: {}", path)?;
+                write_machine_details(&info, writer)?;
+                let config = ConfigManager::new()?.load_merged()?;
+                if let Some(scale) = scale_for(config.scales.as_ref(), &info.name) {
+                    writeln!(
+                        writer,
+                        "  Scale: {} (from the [scales] config; designs are resized when converted)",
+                        scale
+                    )?;
                 }
             }
             None => writeln!(writer, "Machine '{}' not found", name)?,
@@ -184,31 +514,134 @@ impl MachineCommand {
     }
 }
 
+/// Writes the machine's notes, formats, design sizes, and USB path, indented
+/// to go under its name.
+fn write_machine_details<W: Write>(machine: &Machine, writer: &mut W) -> Result<()> {
+    if let Some(notes) = machine.notes.as_ref().filter(|notes| !notes.is_empty()) {
+        writeln!(writer, "  Notes: {}", notes)?;
+    }
+    if !machine.synonyms.is_empty() {
+        writeln!(writer, "  Synonyms: {}", machine.synonyms.join(", "))?;
+    }
+    if !machine.file_formats.is_empty() {
+        writeln!(writer, "  Formats: {}", machine.file_formats.join(", "))?;
+    }
+    if let Some(format) = &machine.preferred_format {
+        writeln!(writer, "  Preferred format: {}", format)?;
+    }
+    match machine.design_sizes.as_slice() {
+        [] => (),
+        [size] => writeln!(writer, "  Design size: {}", size)?,
+        sizes => writeln!(writer, "  Design sizes: {}", join_sizes(sizes))?,
+    }
+    if let Some(path) = machine.usb_path.as_ref().filter(|path| !path.is_empty()) {
+        writeln!(writer, "  USB path: {}", path)?;
+    }
+    Ok(())
+}
+
+fn match_options(threshold: Option<f64>) -> MatchOptions {
+    MatchOptions {
+        threshold: threshold.unwrap_or(DEFAULT_MATCH_THRESHOLD),
+        ..Default::default()
+    }
+}
+
+/// The number of candidates that `--verbose` shows for a machine name
+const MATCH_TRACE_CANDIDATES: usize = 5;
+
+/// Writes the machines whose names are most similar to `name`, with their
+/// scores, so that an unexpected match can be understood and reported.
+fn write_match_trace<W: Write>(name: &str, options: &MatchOptions, writer: &mut W) -> Result<()> {
+    match Machine::find_by_name(name) {
+        Some(machine) => writeln!(
+            writer,
+            "'{}' is an exact match for {}, ignoring case, spaces, and punctuation",
+            name, machine.name
+        )?,
+        None => writeln!(
+            writer,
+            "'{}' isn't an exact match. Closest machines (suggested at {:.2} or above):",
+            name, options.threshold
+        )?,
+    }
+    for (score, machine) in Machine::similarity_scores(name)
+        .into_iter()
+        .take(MATCH_TRACE_CANDIDATES)
+    {
+        writeln!(writer, "  {:.3}  {}", score, machine.name)?;
+    }
+    Ok(())
+}
+
+fn join_sizes(sizes: &[DesignSize]) -> String {
+    sizes
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn filter_machines(format: Option<String>, manufacturer: Option<String>) -> Vec<&'static Machine> {
+    MACHINES
+        .iter()
+        .filter(|m| {
+            format
+                .as_ref()
+                .is_none_or(|format| m.file_formats.contains(&format.to_lowercase()))
+        })
+        .filter(|m| {
+            manufacturer
+                .as_ref()
+                .is_none_or(|manufacturer| m.is_made_by(manufacturer))
+        })
+        .collect()
+}
+
+/// Prints every machine name and synonym that starts with `prefix`, one per
+/// line. Shell completion scripts call this, so that completions stay current
+/// as the machine list grows.
+fn complete_machines_command<W: Write>(prefix: Option<String>, writer: &mut W) -> Result<()> {
+    let prefix = prefix.unwrap_or_default().to_lowercase();
+    let mut names = MACHINES
+        .iter()
+        .flat_map(|m| std::iter::once(&m.name).chain(&m.synonyms))
+        .filter(|name| !name.is_empty() && name.to_lowercase().starts_with(&prefix))
+        .collect::<Vec<_>>();
+    names.sort();
+    names.dedup();
+    for name in names {
+        writeln!(writer, "{}", name)?;
+    }
+    Ok(())
+}
+
 fn list_machines_command<W: Write>(
-    format: Option<String>,
+    machines: &[&Machine],
     verbose: bool,
     writer: &mut W,
 ) -> Result<()> {
-    let machines = if let Some(format) = format {
-        MACHINES
-            .iter()
-            .filter(|m| m.file_formats.contains(&format.to_lowercase()))
-            .collect::<Vec<_>>()
-    } else {
-        MACHINES.iter().collect()
-    };
-
     for machine in machines {
         if verbose {
             writeln!(writer, "{}", machine.name.clone().bold())?;
             if !machine.synonyms.is_empty() {
-                writeln!(writer, "  {} {}", "Synonyms:".stylize().blue(), machine.synonyms.join(", "))?;
+                writeln!(
+                    writer,
+                    "  {} {}",
+                    "Synonyms:".stylize().blue(),
+                    machine.synonyms.join(", ")
+                )?;
             }
             if let Some(notes) = &machine.notes {
                 writeln!(writer, "  {}: {}", "Note".stylize().blue(), notes)?;
             }
-            if let Some(design_size) = &machine.design_size {
-                writeln!(writer, "  {}: {}", "Design size".stylize().blue(), design_size)?;
+            if !machine.design_sizes.is_empty() {
+                writeln!(
+                    writer,
+                    "  {}: {}",
+                    "Design size".stylize().blue(),
+                    join_sizes(&machine.design_sizes)
+                )?;
             }
             if let Some(usb_path) = &machine.usb_path {
                 writeln!(writer, "  {}: {}", "USB path".stylize().blue(), usb_path)?;
@@ -225,88 +658,1174 @@ fn list_machines_command<W: Write>(
     Ok(())
 }
 
-fn watch_command<W: Write>(
-    watch_dir: Option<PathBuf>,
+fn machine_stats_command<W: Write>(machines: &[&Machine], writer: &mut W) -> Result<()> {
+    writeln!(writer, "{} machines", machines.len())?;
+
+    let manufacturers = count_by(machines.iter().map(|m| m.manufacturer.as_str()));
+    writeln!(writer)?;
+    writeln!(writer, "{}", "By manufacturer:".stylize().blue())?;
+    write_counts(&manufacturers, writer)?;
+
+    let formats = count_by(
+        machines
+            .iter()
+            .flat_map(|m| m.file_formats.iter().map(String::as_str)),
+    );
+    writeln!(writer)?;
+    writeln!(writer, "{}", "By format:".stylize().blue())?;
+    write_counts(&formats, writer)?;
+    Ok(())
+}
+
+/// Opens the user's machine list in an editor, creating it with a header row
+/// if it doesn't exist, and then reports rows that can't be parsed.
+fn edit_machines_command<W: Write>(writer: &mut W) -> Result<()> {
+    let path = ConfigManager::new()?.machines_path();
+    if !path.exists() {
+        fs::write(&path, format!("{}\n", machine::machines_csv_header()))?;
+        writeln!(writer, "Created {}", path.display())?;
+    }
+    open_in_editor(&path)?;
+
+    let (machines, errors) = machine::parse_machines(&fs::read_to_string(&path)?);
+    for error in &errors {
+        write_error!(writer, "{}", error);
+    }
+    writeln!(
+        writer,
+        "{} machine{} in {}",
+        machines.len(),
+        if machines.len() == 1 { "" } else { "s" },
+        path.display()
+    )?;
+    if !errors.is_empty() {
+        writeln!(
+            writer,
+            " → Rows with errors are skipped. Run '{}' again to fix them.",
+            "stitch-sync machines edit".bright_green()
+        )?;
+    }
+    Ok(())
+}
+
+/// Counts the occurrences of each key, most frequent first.
+fn count_by<'a>(keys: impl Iterator<Item = &'a str>) -> Vec<(&'a str, usize)> {
+    let mut counts: Vec<(&str, usize)> = Vec::new();
+    for key in keys {
+        match counts.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((key, 1)),
+        }
+    }
+    counts.sort_by(|(a, m), (b, n)| n.cmp(m).then(a.cmp(b)));
+    counts
+}
+
+fn write_counts<W: Write>(counts: &[(&str, usize)], writer: &mut W) -> Result<()> {
+    let width = counts.iter().map(|(key, _)| key.len()).max().unwrap_or(0);
+    for (key, count) in counts {
+        writeln!(writer, "  {:<width$}  {:>4}", key, count, width = width)?;
+    }
+    Ok(())
+}
+
+/// Prints a row for each machine, with each of its formats marked by
+/// whether ink/stitch can write it, can only read it, or doesn't support it.
+fn compat_command<W: Write>(
+    machine: Option<String>,
+    format: Option<String>,
+    writer: &mut W,
+) -> Result<()> {
+    let machines: Vec<&Machine> = match &machine {
+        Some(name) => match Machine::find_by_name(name) {
+            Some(machine) => MACHINES.iter().filter(|m| m.name == machine.name).collect(),
+            None => anyhow::bail!(
+                "Unknown machine: {}. Run 'stitch-sync machines' to list the supported machines.",
+                name
+            ),
+        },
+        None => MACHINES.iter().collect(),
+    };
+    let machines = machines
+        .into_iter()
+        .filter(|m| format.as_ref().is_none_or(|format| m.reads_format(format)))
+        .collect::<Vec<_>>();
+    if machines.is_empty() {
+        writeln!(writer, "No machines found")?;
+        return Ok(());
+    }
+
+    let width = machines.iter().map(|m| m.name.len()).max().unwrap_or(0);
+    for machine in &machines {
+        write!(writer, "{:<width$}", machine.name, width = width)?;
+        for format in &machine.file_formats {
+            write!(writer, "  {} {}", format, compat_mark(format))?;
+        }
+        writeln!(writer)?;
+    }
+
+    let writable = machines
+        .iter()
+        .filter(|m| m.file_formats.iter().any(|f| inkstitch_writes(f)))
+        .count();
+    writeln!(writer)?;
+    writeln!(
+        writer,
+        "ink/stitch can write a format for {} of {} machines",
+        writable,
+        machines.len()
+    )?;
+    writeln!(
+        writer,
+        "{} can write  {} can only read  {} not supported",
+        "✓".bright_green(),
+        "~".bright_yellow(),
+        "✗".bright_red()
+    )?;
+    Ok(())
+}
+
+/// Returns true if ink/stitch can write `format`. Machines that read "jef+"
+/// are sent "jef", as `output_formats` does.
+fn inkstitch_writes(format: &str) -> bool {
+    inkscape::SUPPORTED_WRITE_FORMATS.contains(&format.trim_end_matches('+'))
+}
+
+fn compat_mark(format: &str) -> colored::ColoredString {
+    if inkstitch_writes(format) {
+        "✓".bright_green()
+    } else if inkscape::SUPPORTED_READ_FORMATS.contains(&format) {
+        "~".bright_yellow()
+    } else {
+        "✗".bright_red()
+    }
+}
+
+fn batch_command<W: Write>(
+    dir: PathBuf,
     output_format: Option<String>,
-    machine_name: Option<String>,
+    output: Option<PathBuf>,
+    jobs: Option<usize>,
+    quiet: bool,
+    since: Option<Since>,
     writer: &mut W,
 ) -> Result<()> {
+    if !dir.is_dir() {
+        anyhow::bail!("Directory '{}' does not exist", dir.display());
+    }
+    let Some(inkscape) = Inkscape::find_app() else {
+        anyhow::bail!(
+            "Inkscape is not installed. Download it from {}",
+            inkscape::INKSCAPE_DOWNLOAD_URL
+        );
+    };
+    let config = ConfigManager::new()?.load_merged()?;
+    let output_format = output_format
+        .or(config.output_format)
+        .unwrap_or_else(|| DEFAULT_FORMAT.to_string())
+        .to_lowercase();
+    let jobs = jobs.unwrap_or_else(|| {
+        std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+    });
+    let modified_since = match since {
+        Some(Since::Time(time)) => Some(time),
+        Some(Since::LastRun) => Some(services::batch::last_run(&dir).with_context(|| {
+            format!(
+                "No complete batch run of {} is recorded. Use a time with --since instead.",
+                dir.display()
+            )
+        })?),
+        None => None,
+    };
+    let start = std::time::SystemTime::now();
+
+    let report = services::batch::batch_convert(
+        &dir,
+        &inkscape,
+        &output_format,
+        output.as_deref(),
+        jobs,
+        quiet,
+        &ConversionOptions {
+            presets: config.presets.unwrap_or_default(),
+            sanitize_case: config.sanitize_case.unwrap_or_default(),
+            modified_since,
+            // The directory was named on the command line, so its artwork is
+            // meant to be converted
+            import_vectors: true,
+            ..Default::default()
+        },
+    )?;
+    if report.failed.is_empty() {
+        if let Err(e) = services::batch::record_run(&dir, start) {
+            write_warning!(writer, "Couldn't record this run for --since last: {}", e);
+        }
+    }
+
+    writeln!(writer)?;
+    for (source, output) in &report.converted {
+        writeln!(writer, "{} → {}", source.display(), output.display())?;
+    }
+    for (source, error) in &report.failed {
+        write_error!(writer, "{}: {}", source.display(), error);
+    }
+    writeln!(
+        writer,
+        "{} converted, {} failed, {} already in {} format",
+        report.converted.len().to_string().bright_green(),
+        report.failed.len().to_string().bright_red(),
+        report.skipped.len(),
+        output_format
+    )?;
+    if let Some(since) = modified_since {
+        writeln!(
+            writer,
+            "{} not modified since {} UTC",
+            report.unchanged.len(),
+            utils::format_utc(since)
+        )?;
+    }
+    if !report.failed.is_empty() {
+        return Err(ExitStatus {
+            code: BATCH_FAILED_EXIT_CODE,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+fn drives_command<W: Write>(writer: &mut W) -> Result<()> {
+    let usb_allowlist = ConfigManager::new()?
+        .load_merged()?
+        .usb_allowlist
+        .unwrap_or_default();
+    let drives = UsbDrive::list();
+    if drives.is_empty() {
+        writeln!(writer, "No USB drives detected")?;
+    }
+    for drive in &drives {
+        write!(
+            writer,
+            "{} {}",
+            drive.name.clone().bold(),
+            drive.mount_point.display().to_string().dimmed()
+        )?;
+        if !drive.is_allowed(&usb_allowlist) {
+            write!(
+                writer,
+                " {}",
+                "(ignored: not in usb_allowlist)".bright_yellow()
+            )?;
+        }
+        writeln!(writer)?;
+        if let Some(fs_type) = drive.filesystem_type() {
+            writeln!(writer, "  {}: {}", "Filesystem".bright_blue(), fs_type)?;
+        }
+        if let Some(free) = drive.free_space() {
+            writeln!(
+                writer,
+                "  {}: {}",
+                "Free space".bright_blue(),
+                utils::format_size(free)
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// The name of the file that `drives test` writes. It's hidden, so that a
+/// machine that lists the drive doesn't show it if the test is interrupted.
+const DRIVE_TEST_FILE: &str = ".stitch-sync-test";
+
+/// The size of the file that `drives test` writes: large enough to take
+/// measurable time, and small enough to fit on a nearly full drive
+const DRIVE_TEST_BYTES: usize = 256 * 1024;
+
+/// Writes a test file to a USB drive, reads it back, deletes it, and ejects
+/// the drive, reporting how long each step takes. Stops at the first step
+/// that fails.
+fn drives_test_command<W: Write>(name: Option<String>, writer: &mut W) -> Result<()> {
+    let drives = match &name {
+        Some(name) => UsbDrive::list_allowed(std::slice::from_ref(name)),
+        None => UsbDrive::list(),
+    };
+    let drive = match drives.as_slice() {
+        [] => match name {
+            Some(name) => anyhow::bail!(
+                "No USB drive named {} detected. Run 'stitch-sync drives' to list the drives.",
+                name
+            ),
+            None => anyhow::bail!("No USB drives detected"),
+        },
+        [drive] => drive,
+        _ => anyhow::bail!(
+            "Several USB drives are connected ({}). Choose one with --drive.",
+            drives
+                .iter()
+                .map(|drive| drive.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+    writeln!(
+        writer,
+        "Testing {} {}",
+        drive.name.clone().bold(),
+        drive.mount_point.display().to_string().dimmed()
+    )?;
+
+    let path = drive.mount_point.join(DRIVE_TEST_FILE);
+    let content: Vec<u8> = (0..DRIVE_TEST_BYTES).map(|i| (i % 251) as u8).collect();
+    let result = drive_test_step("Write", writer, || {
+        let mut file = fs::File::create(&path)?;
+        file.write_all(&content)?;
+        // Time the drive, rather than the operating system's cache
+        file.sync_all()
+    })
+    .and_then(|()| {
+        drive_test_step("Read back", writer, || {
+            if fs::read(&path)? == content {
+                Ok(())
+            } else {
+                Err(io::Error::other(
+                    "the file that was read back differs from the one written",
+                ))
+            }
+        })
+    })
+    .and_then(|()| drive_test_step("Delete", writer, || fs::remove_file(&path)))
+    .and_then(|()| drive_test_step("Eject", writer, || drive.eject().map_err(io::Error::other)));
+    if let Err(e) = result {
+        // Don't leave the test file behind if a later step failed
+        let _ = fs::remove_file(&path);
+        return Err(e.context(format!("Testing {} failed", drive.name)));
+    }
+    writeln!(writer, "{} passed the test, and can be removed", drive.name)?;
+    Ok(())
+}
+
+/// Runs a step of `drives test`, and reports whether it succeeded and how
+/// long it took.
+fn drive_test_step<W: Write>(
+    step: &str,
+    writer: &mut W,
+    run: impl FnOnce() -> io::Result<()>,
+) -> Result<()> {
+    let start = std::time::Instant::now();
+    let result = run();
+    let mark = if result.is_ok() {
+        "✓".bright_green()
+    } else {
+        "✗".bright_red()
+    };
+    writeln!(
+        writer,
+        "  {} {:<10} {:.2}s",
+        mark,
+        step,
+        start.elapsed().as_secs_f32()
+    )?;
+    result.with_context(|| format!("{} failed", step))
+}
+
+fn doctor_command<W: Write>(test_convert: bool, writer: &mut W) -> Result<()> {
+    let Some(inkscape) = Inkscape::find_app() else {
+        anyhow::bail!(
+            "Inkscape is not installed. Download it from {}",
+            inkscape::INKSCAPE_DOWNLOAD_URL
+        );
+    };
+    match inkscape.version {
+        Some(version) => writeln!(
+            writer,
+            "Inkscape: {} (version {})",
+            inkscape.path.display(),
+            version
+        )?,
+        None => writeln!(
+            writer,
+            "Inkscape: {} (version unknown)",
+            inkscape.path.display()
+        )?,
+    }
+    if inkscape.has_inkstitch {
+        match inkscape.inkstitch_version() {
+            Some(version) => writeln!(writer, "ink/stitch: installed (version {})", version)?,
+            None => writeln!(writer, "ink/stitch: installed")?,
+        }
+    } else {
+        write_warning!(
+            writer,
+            "ink/stitch was not found. Download it from {}",
+            inkscape::INKSTITCH_INSTALL_URL
+        );
+    }
+
+    if !test_convert {
+        return Ok(());
+    }
+    write!(writer, "Converting a sample design to DST...")?;
+    writer.flush()?;
+    match inkscape.test_conversion() {
+        Ok(elapsed) => {
+            writeln!(writer, "done ({:.2}s elapsed time)", elapsed.as_secs_f32())?;
+            Ok(())
+        }
+        Err(e) => {
+            writeln!(writer)?;
+            anyhow::bail!("The test conversion failed: {}", e)
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct DoctorReport {
+    inkscape: InkscapeReport,
+    inkstitch: InkstitchReport,
+    usb_drives: Vec<DriveReport>,
+    config: Config,
+}
+
+#[derive(Serialize)]
+struct InkscapeReport {
+    found: bool,
+    path: Option<PathBuf>,
+    version: Option<String>,
+}
+
+#[derive(Serialize)]
+struct InkstitchReport {
+    found: bool,
+    version: Option<String>,
+}
+
+#[derive(Serialize)]
+struct DriveReport {
+    name: String,
+    mount_point: PathBuf,
+    /// False if the drive isn't in `usb_allowlist`
+    allowed: bool,
+}
+
+/// Prints the environment that `doctor` checks, as JSON. Unlike
+/// `doctor_command`, a missing Inkscape is reported rather than an error.
+fn doctor_json_command<W: Write>(writer: &mut W) -> Result<()> {
+    let config = ConfigManager::new()?.load_merged()?;
+    let usb_allowlist = config.usb_allowlist.clone().unwrap_or_default();
+    let inkscape = Inkscape::find_app();
+    let report = DoctorReport {
+        inkscape: InkscapeReport {
+            found: inkscape.is_some(),
+            path: inkscape.as_ref().map(|inkscape| inkscape.path.clone()),
+            version: inkscape
+                .as_ref()
+                .and_then(|inkscape| inkscape.version)
+                .map(|version| version.to_string()),
+        },
+        inkstitch: InkstitchReport {
+            found: inkscape
+                .as_ref()
+                .is_some_and(|inkscape| inkscape.has_inkstitch),
+            version: inkscape
+                .as_ref()
+                .and_then(|inkscape| inkscape.inkstitch_version()),
+        },
+        usb_drives: UsbDrive::list()
+            .into_iter()
+            .map(|drive| DriveReport {
+                allowed: drive.is_allowed(&usb_allowlist),
+                name: drive.name,
+                mount_point: drive.mount_point,
+            })
+            .collect(),
+        config,
+    };
+    writeln!(writer, "{}", serde_json::to_string_pretty(&report)?)?;
+    Ok(())
+}
+
+fn history_command<W: Write>(limit: usize, json: bool, writer: &mut W) -> Result<()> {
+    let entries = history::read_recent(limit)?;
+    if json {
+        for entry in &entries {
+            writeln!(writer, "{}", serde_json::to_string(entry)?)?;
+        }
+        return Ok(());
+    }
+    if entries.is_empty() {
+        writeln!(writer, "No conversions recorded yet")?;
+    }
+    for entry in &entries {
+        let status = if entry.success {
+            "✓".bright_green()
+        } else {
+            "✗".bright_red()
+        };
+        write!(
+            writer,
+            "{} {} {}",
+            utils::format_utc(entry.timestamp).dimmed(),
+            status,
+            entry.source.display()
+        )?;
+        if let Some(output) = &entry.output {
+            write!(writer, " → {}", output.display())?;
+        }
+        if let Some(destination) = &entry.destination {
+            write!(writer, " → {}", destination.display())?;
+        }
+        if let Some(machine) = &entry.machine {
+            write!(writer, " ({})", machine)?;
+        }
+        writeln!(writer)?;
+        if let Some(error) = &entry.error {
+            writeln!(writer, "    {}", error.clone().red())?;
+        }
+    }
+    Ok(())
+}
+
+fn watch_command<W: Write>(args: WatchArgs, writer: &mut W) -> Result<()> {
+    // With NDJSON, stdout is reserved for events, so messages go to stderr
+    match args.stdout_format {
+        StdoutFormat::Text => run_watch(args, writer),
+        StdoutFormat::Ndjson => {
+            utils::messages::reserve_stdout();
+            run_watch(args, &mut io::stderr())
+        }
+    }
+}
+
+fn run_watch<W: Write>(args: WatchArgs, writer: &mut W) -> Result<()> {
     // Check for updates, but use cache
-    if let Ok(Some(latest_version)) = version::get_latest_version(false) {
-        write_notice!(writer, "🔄 A new version of stitch-sync {} is available.", format!("({})", latest_version).dim());
-        writeln!(writer, " → Run '{}' to upgrade.", "stitch-sync update".bright_green())?;
+    if let Ok(Some(latest_version)) = version::get_latest_version(false, &update_urls()) {
+        write_notice!(
+            writer,
+            "🔄 A new version of stitch-sync {} is available.",
+            format!("({})", latest_version).dim()
+        );
+        writeln!(
+            writer,
+            " → Run '{}' to upgrade.",
+            "stitch-sync update".bright_green()
+        )?;
     }
 
     let config_manager = ConfigManager::new()?;
-    let config = config_manager.load()?;
+    let config = config_manager.load_merged()?;
 
     let inkscape = Inkscape::find_app();
     let has_inkscape = inkscape.is_some();
-    let has_inkstitch = inkscape.as_ref().map_or(false, |i| i.has_inkstitch);
+    let has_inkstitch = inkscape.as_ref().is_some_and(|i| i.has_inkstitch);
 
-    if !has_inkscape {
-        println!(
+    if args.no_convert {
+        // Conversion is disabled, so there's no need for Inkscape
+    } else if !has_inkscape {
+        writeln!(
+            writer,
             "Warning: Inkscape is not installed. Files will be copied to USB drives but not converted. For file conversion, please download Inkscape from {} and install it.",
             inkscape::INKSCAPE_DOWNLOAD_URL
-        );
+        )?;
     } else if !has_inkstitch {
-        println!(
+        writeln!(
+            writer,
             "Warning: The ink/stitch extension is not installed. Files will be copied to USB drives but not converted. For file conversion, please download ink/stitch from {} and install it.",
             inkscape::INKSTITCH_INSTALL_URL
-        );
+        )?;
     }
 
-    let watch_dir = watch_dir.or(config.watch_dir).unwrap_or_else(|| {
-        dirs::home_dir()
-            .expect("Could not find home directory")
-            .join("Downloads")
+    let config_watch_dirs = config.watch_dirs();
+    let machine = match resolve_machine(
+        args.machine.or(config.machine).as_deref(),
+        &MatchOptions {
+            verbose: args.verbose,
+            ..match_options(args.match_threshold)
+        },
+        args.yes,
+        writer,
+    ) {
+        Ok(machine) => machine,
+        Err(e) => {
+            print_error!("🚨 {}", e);
+            return Ok(());
+        }
+    };
+
+    let scale = args.scale.or_else(|| {
+        machine
+            .as_ref()
+            .and_then(|m| scale_for(config.scales.as_ref(), &m.name))
     });
+    let mut output_format = args.output_format.or(config.output_format);
+    if output_format.is_none()
+        && machine.is_none()
+        && !args.yes
+        && args.stdout_format == StdoutFormat::Text
+        && io::stdin().is_terminal()
+    {
+        output_format = prompt_output_format(writer)?;
+    }
+    let (accepted_formats, preferred_format) =
+        output_formats(machine.as_ref(), output_format, inkscape.as_ref());
 
-    let machine_name = machine_name.or(config.machine);
-    let machine = machine_name
-        .as_ref()
-        .and_then(|m| Machine::interactive_find_by_name(m));
-    if machine_name.is_some() && machine.is_none() {
-        print_error!("🚨 Machine '{}' not found", machine_name.unwrap());
-        return Ok(());
+    // Previews are for people rather than machines, so they aren't copied to
+    // USB drives
+    let image_output = inkscape::IMAGE_EXPORT_FORMATS.contains(&preferred_format.as_str());
+    if !image_output {
+        warn_if_unreadable(machine.as_ref(), &preferred_format, writer);
     }
 
-    let usb_target_path = machine
-        .as_ref()
-        .and_then(|m| m.usb_path.as_deref())
+    let copy_formats = (!args.copy_only_format.is_empty()).then(|| {
+        args.copy_only_format
+            .iter()
+            .map(|format| format.trim_start_matches('.').to_lowercase())
+            .collect::<Vec<_>>()
+    });
+    if let Some(format) = copy_formats
+        .iter()
+        .flatten()
+        .find(|format| !accepted_formats.contains(format))
+    {
+        anyhow::bail!(
+            "--copy-only-format {} is not one of the accepted formats ({})",
+            format,
+            accepted_formats.join(", ")
+        );
+    }
+
+    // The usb_subpath setting is where the user wants files on every drive,
+    // so it's created without asking
+    let use_usb_subpath =
+        config.usb_subpath.is_some() && !args.usb_root && args.usb_target.is_none();
+    let usb_path = config
+        .usb_subpath
+        .as_deref()
+        .or_else(|| machine.as_ref().and_then(|m| m.usb_path.as_deref()))
+        .filter(|_| !args.usb_root && args.usb_target.is_none())
+        .map(normalize_usb_path)
+        .transpose()?
+        .or_else(|| args.usb_target.as_ref().map(|target| target.path.clone()))
         .unwrap_or_default();
+    let usb_path = if args.machine_subdir {
+        let Some(machine) = &machine else {
+            anyhow::bail!(
+                "--machine-subdir requires a machine. Use --machine or set a default machine."
+            );
+        };
+        Path::new(&usb_path)
+            .join(machine.usb_subdir())
+            .to_string_lossy()
+            .into_owned()
+    } else {
+        usb_path
+    };
+    let mut usb_target_path = usb_path.as_str();
+
+    // The arguments have all been checked, so nothing is deleted or created
+    // for a run that would stop with an error
+    if let Some(days) = args.prune_older_than {
+        prune_outputs(days, writer)?;
+    }
 
-        let usb_drives = UsbDrive::list();
+    let mut watch_dirs = if args.dir.is_empty() {
+        config_watch_dirs
+    } else {
+        args.dir
+    };
+    if watch_dirs.is_empty() {
+        let can_prompt =
+            !args.yes && args.stdout_format == StdoutFormat::Text && io::stdin().is_terminal();
+        watch_dirs.push(default_watch_dir(can_prompt));
+    }
+    for watch_dir in &watch_dirs {
+        if !ensure_watch_dir(
+            watch_dir,
+            args.create_dir || args.yes,
+            args.wait_for_dir,
+            writer,
+        )? {
+            return Ok(());
+        }
+        if !args.poll && utils::is_network_path(watch_dir) {
+            write_warning!(
+                writer,
+                "{} appears to be on a network share, which may not report new files. If new files aren't noticed, use {}.",
+                watch_dir.display(),
+                "--poll".bright_blue()
+            );
+        }
+    }
 
-        if usb_drives.is_empty() {
-        println!("Warning: No USB drives detected. Files will be converted but not copied.");
+    let usb_allowlist = if let Some(target) = args.usb_target {
+        vec![target.drive]
+    } else if args.usb_names.is_empty() {
+        config.usb_allowlist.unwrap_or_default()
     } else {
-        let target_exists = usb_drives.iter().any(|drive| {
-            let full_path = drive.mount_point.join(usb_target_path);
-                full_path.exists()
-            });
-
-            if !target_exists {
-                if let Some(first_drive) = usb_drives.first() {
-                    let full_path = first_drive.mount_point.join(usb_target_path);
-                    println!("Target path '{}' does not exist on any USB drives.", usb_target_path);
-                    if prompt_yes_no(&format!("Create it on {}? ", first_drive.name), None) {
-                        std::fs::create_dir_all(&full_path)
-                            .expect("Failed to create target directory on USB drive");
-                    } else {
-                        println!("Target path '{}' not created. Files will be converted but not copied.", usb_target_path);
-                    }
+        args.usb_names
+    };
+    let usb_drives = if image_output {
+        Vec::new()
+    } else {
+        UsbDrive::list_allowed(&usb_allowlist)
+    };
+
+    if image_output {
+        // There's no USB target to check
+    } else if usb_drives.is_empty() && !usb_allowlist.is_empty() {
+        writeln!(
+            writer,
+            "Warning: No USB drives named {} detected. Files will be converted but not copied.",
+            usb_allowlist.join(", ")
+        )?;
+    } else if usb_drives.is_empty() {
+        writeln!(
+            writer,
+            "Warning: No USB drives detected. Files will be converted but not copied."
+        )?;
+    } else {
+        // The machine's folder was asked for, so it's created without asking
+        usb_target_path = prepare_usb_target(
+            usb_target_path,
+            &usb_drives,
+            args.yes || args.machine_subdir || use_usb_subpath,
+            writer,
+        )?;
+        warn_if_read_only(&usb_drives, usb_target_path, writer);
+    }
+
+    if let (Some(machine), Some(inkscape)) = (&machine, &inkscape) {
+        let writable = accepted_formats
+            .iter()
+            .any(|format| inkscape.supported_write_formats.contains(&format.as_str()));
+        if !writable && !args.no_convert {
+            write_warning!(
+                writer,
+                "ink/stitch can't write any of the formats that the {} accepts ({}), so files will not be converted. Use {} to copy {} files without trying to convert them.",
+                machine.name,
+                machine.file_formats.join(", "),
+                "--no-convert".bright_green(),
+                machine.file_formats.join("/")
+            );
+        }
+    }
+
+    if let Some(ref machine) = machine {
+        writeln!(
+            writer,
+            "{} {}",
+            "🧵 Machine:".bright_blue(),
+            machine.name.clone().bold()
+        )?;
+        if !machine.design_sizes.is_empty() {
+            writeln!(
+                writer,
+                " {} {}",
+                if machine.design_sizes.len() == 1 {
+                    "→ Design size:"
+                } else {
+                    "→ Design sizes:"
                 }
+                .bright_blue(),
+                join_sizes(&machine.design_sizes).bold()
+            )?;
+        }
+    }
+    for watch_dir in &watch_dirs {
+        writeln!(
+            writer,
+            "{} {}",
+            "📁 Watch directory:".bright_blue(),
+            watch_dir.display().to_string().bold()
+        )?;
+    }
+    let usb_target_dirs = if image_output {
+        Vec::new()
+    } else {
+        find_usb_containing_path(usb_target_path, &usb_allowlist)
+    };
+    for usb_target_dir in &usb_target_dirs {
+        writeln!(
+            writer,
+            "{} {}",
+            "💾 USB target directory:".bright_blue(),
+            usb_target_dir.display().to_string().bold()
+        )?;
+    }
+    match accepted_formats.len() {
+        _ if args.no_convert => writeln!(
+            writer,
+            " {} {}",
+            "→ Files will be copied, but not converted, if they are in".bright_blue(),
+            accepted_formats.join(", ").bold()
+        )?,
+        1 => writeln!(
+            writer,
+            " {} {}",
+            "→ Files will be converted to".bright_blue(),
+            accepted_formats[0].clone().bold()
+        )?,
+        _ => writeln!(
+            writer,
+            " {} {}",
+            "→ Files will be converted to one of:".bright_blue(),
+            accepted_formats.join(", ").bold()
+        )?,
+    }
+    if let Some(copy_formats) = &copy_formats {
+        writeln!(
+            writer,
+            " {} {} {} {}",
+            "→ Only".bright_blue(),
+            copy_formats.join(", ").bold(),
+            "files will be copied as-is; other files will be converted to".bright_blue(),
+            preferred_format.clone().bold()
+        )?;
+    }
+    if image_output {
+        writeln!(
+            writer,
+            " {}",
+            "→ Previews will not be copied to USB drives".bright_blue()
+        )?;
+    } else {
+        writeln!(
+            writer,
+            " {} {} {}",
+            "→ Files will be copied into the".bright_blue(),
+            if usb_target_path.is_empty() {
+                "root"
+            } else {
+                usb_target_path
             }
+            .stylize()
+            .bold(),
+            "directory on a mounted USB drive".bright_blue()
+        )?;
+    }
+    if let Some(count) = args.show_recent {
+        let is_convertible = |format: &str| {
+            accepted_formats.iter().any(|accepted| accepted == format)
+                || (!args.no_convert
+                    && inkscape
+                        .as_ref()
+                        .is_some_and(|inkscape| inkscape.supported_read_formats.contains(&format)))
+        };
+        show_recent_files(&watch_dirs, count, is_convertible, writer)?;
+    }
+    writeln!(
+        writer,
+        "\n{}",
+        "Press 'q' to quit, 'x' to cancel a conversion, or 'r' to process every file again"
+            .bright_black()
+            .italic()
+    )?;
+
+    let events = args
+        .event_socket
+        .as_deref()
+        .and_then(|path| match EventPublisher::bind(path) {
+            Ok(events) => Some(events),
+            Err(e) => {
+                write_warning!(
+                    writer,
+                    "Could not listen for event clients on {}: {}",
+                    path.display(),
+                    e
+                );
+                None
+            }
+        });
+
+    let text_output = args.stdout_format == StdoutFormat::Text;
+    let events = if !text_output {
+        let events = events.unwrap_or_default();
+        events.add_client(Box::new(io::stdout()));
+        Some(events)
+    } else if args.transcript {
+        let events = events.unwrap_or_default();
+        events.add_transcript_client(Box::new(io::stdout()));
+        Some(events)
+    } else {
+        events
+    };
+
+    let options = WatchOptions {
+        conversion: ConversionOptions {
+            overwrite: args.overwrite,
+            eject_after: args.eject_after,
+            no_convert: args.no_convert,
+            output_dir: config.output_dir,
+            machine: machine.as_ref().map(|m| m.name.clone()),
+            // The transcript replaces the progress messages
+            quiet: !text_output || args.transcript,
+            cancel: None,
+            copy_formats,
+            force_copy: args.force_copy,
+            copy_source_too: args.copy_source_too,
+            events,
+            source_roots: if args.recursive && !args.flatten {
+                watch_dirs.clone()
+            } else {
+                Vec::new()
+            },
+            usb_subdir: None,
+            usb_allowlist,
+            scale,
+            watch_pattern: args.watch_pattern,
+            modified_since: None,
+            include_hidden: args.include_hidden,
+            extract_archives: args.extract_archives,
+            import_vectors: args.import_vectors,
+            sanitize_case: args
+                .sanitize_case
+                .or(config.sanitize_case)
+                .unwrap_or_default(),
+            max_stitches: args.max_stitches,
+            skip_over_max_stitches: args.skip_over_max_stitches,
+            presets: config.presets.unwrap_or_default(),
+            input_format: args.input_format,
+            source_action: if args.move_source {
+                SourceAction::Archive
+            } else if args.delete_source {
+                SourceAction::Delete
+            } else {
+                SourceAction::Keep
+            },
+        },
+        assume_yes: args.yes,
+        poll_interval: args
+            .poll_interval_ms
+            .map_or(WATCH_POLL_INTERVAL, Duration::from_millis),
+        scan_directory: args.poll,
+        recursive: args.recursive,
+        max_depth: args.max_depth,
+        follow_symlinks: args.follow_symlinks,
+        transcript: args.transcript,
+        heartbeat: match (args.quiet, args.heartbeat) {
+            (true, _) | (_, Some(0)) => None,
+            (false, Some(minutes)) => Some(Duration::from_secs(minutes * 60)),
+            (false, None) => Some(HEARTBEAT_INTERVAL),
+        },
+        idle_timeout: args
+            .idle_timeout
+            .map(|minutes| Duration::from_secs(minutes * 60)),
+    };
+    services::watch_dir(
+        &watch_dirs,
+        &(!image_output).then_some(usb_target_path),
+        &accepted_formats
+            .iter()
+            .map(|s| s.as_str())
+            .collect::<Vec<_>>(),
+        &preferred_format,
+        inkscape,
+        &options,
+    );
+    Ok(())
+}
+
+fn send_command<W: Write>(args: SendArgs, writer: &mut W) -> Result<()> {
+    if !args.file.is_file() {
+        anyhow::bail!("File not found: {}", args.file.display());
+    }
+    let config = ConfigManager::new()?.load_merged()?;
+    let machine = resolve_machine(
+        args.machine.or(config.machine).as_deref(),
+        &MatchOptions {
+            verbose: args.verbose,
+            ..match_options(args.match_threshold)
+        },
+        args.yes,
+        writer,
+    )?;
+    let inkscape = Inkscape::find_app();
+    let scale = args.scale.or_else(|| {
+        machine
+            .as_ref()
+            .and_then(|m| scale_for(config.scales.as_ref(), &m.name))
+    });
+    let (accepted_formats, preferred_format) = output_formats(
+        machine.as_ref(),
+        args.output_format.or(config.output_format),
+        inkscape.as_ref(),
+    );
+    if inkscape::IMAGE_EXPORT_FORMATS.contains(&preferred_format.as_str()) {
+        anyhow::bail!(
+            "{} is an image format, which can't be sent to a machine. Use 'stitch-sync batch' to export previews.",
+            preferred_format
+        );
+    }
+    warn_if_unreadable(machine.as_ref(), &preferred_format, writer);
+
+    let format = services::file_format(&args.file);
+    let convertible = inkscape.as_ref().is_some_and(|inkscape| {
+        inkscape.supported_read_formats.contains(&format.as_str())
+            && inkscape.can_write(&preferred_format)
+    });
+    if !accepted_formats.contains(&format) && !convertible {
+        anyhow::bail!(
+            "{} can't be sent: it isn't in one of the accepted formats ({}), and it can't be converted to {}{}",
+            args.file.display(),
+            accepted_formats.join(", "),
+            preferred_format,
+            if inkscape.is_none() {
+                " because Inkscape is not installed"
+            } else {
+                ""
+            }
+        );
+    }
+
+    let use_usb_subpath = config.usb_subpath.is_some() && !args.usb_root;
+    let usb_path = config
+        .usb_subpath
+        .as_deref()
+        .or_else(|| machine.as_ref().and_then(|m| m.usb_path.as_deref()))
+        .filter(|_| !args.usb_root)
+        .map(normalize_usb_path)
+        .transpose()?
+        .unwrap_or_default();
+    let usb_allowlist = config.usb_allowlist.unwrap_or_default();
+    let usb_drives = UsbDrive::list_allowed(&usb_allowlist);
+    if usb_drives.is_empty() {
+        anyhow::bail!("No USB drives detected. Insert a USB drive and try again.");
+    }
+    let usb_target_path =
+        prepare_usb_target(&usb_path, &usb_drives, args.yes || use_usb_subpath, writer)?;
+
+    let options = ConversionOptions {
+        eject_after: args.eject,
+        output_dir: config.output_dir,
+        machine: machine.as_ref().map(|m| m.name.clone()),
+        usb_allowlist,
+        scale,
+        sanitize_case: args
+            .sanitize_case
+            .or(config.sanitize_case)
+            .unwrap_or_default(),
+        presets: config.presets.unwrap_or_default(),
+        // The file was named on the command line, so send it even if it's
+        // hidden or artwork
+        include_hidden: true,
+        import_vectors: true,
+        ..Default::default()
+    };
+    services::handle_file_detection(
+        &args.file,
+        &inkscape,
+        &Some(usb_target_path),
+        &accepted_formats
+            .iter()
+            .map(|s| s.as_str())
+            .collect::<Vec<_>>(),
+        &preferred_format,
+        &options,
+    )?;
+    Ok(())
+}
+
+/// Finds the machine named `name`. If there's no exact match, asks which of
+/// the similar machines to use, or with `assume_yes`, uses the closest one,
+/// then shows the machine's details and asks to confirm it.
+fn resolve_machine<W: Write>(
+    name: Option<&str>,
+    options: &MatchOptions,
+    assume_yes: bool,
+    writer: &mut W,
+) -> Result<Option<Machine>> {
+    let Some(name) = name else {
+        return Ok(None);
+    };
+    if options.verbose {
+        write_match_trace(name, options, writer)?;
+    }
+    if let Some(machine) = Machine::find_by_name(name) {
+        return Ok(Some(machine));
+    }
+    let machine = if assume_yes {
+        // There's no one to choose between similar names, so use the closest
+        Machine::find_similar_names(name, options.threshold)
+            .into_iter()
+            .next()
+    } else {
+        Machine::interactive_find_by_name(name, options)
+    };
+    let Some(machine) = machine else {
+        anyhow::bail!("Machine '{}' not found", name);
+    };
+    // A similar name may be the wrong machine, so show what was chosen
+    writeln!(writer, "'{}' matched {}", name, machine.name)?;
+    write_machine_details(&machine, writer)?;
+    if !assume_yes && !prompt_yes_no("Use this machine? [Y/n] ", Some(true)) {
+        anyhow::bail!("No machine selected");
+    }
+    Ok(Some(machine))
+}
+
+/// Asks which format to write, when neither a machine nor a format was
+/// given. Returns `None`, for the default format, if the user cancels.
+fn prompt_output_format<W: Write>(writer: &mut W) -> Result<Option<String>> {
+    let mut formats = inkscape::SUPPORTED_WRITE_FORMATS
+        .iter()
+        .filter(|&&format| format != DEFAULT_FORMAT)
+        .map(|format| format.to_string())
+        .collect::<Vec<_>>();
+    formats.insert(0, DEFAULT_FORMAT.to_string());
+    let labels = formats
+        .iter()
+        .map(|format| match FileFormat::find_by_extension(format) {
+            Some(file_format) => format!("{} ({})", format, file_format.name),
+            None => format.clone(),
+        })
+        .collect::<Vec<_>>();
+
+    writeln!(
+        writer,
+        "No machine or output format is set. Which format should files be converted to?"
+    )?;
+    writer.flush()?;
+    match utils::prompt_from_list(&labels) {
+        Some(index) => Ok(Some(formats.swap_remove(index))),
+        None => {
+            writeln!(writer, "Using {}", DEFAULT_FORMAT)?;
+            Ok(None)
         }
+    }
+}
 
+/// Warns if `machine` can't read `format`, e.g. because `--output-format`
+/// names a format that isn't one of the machine's.
+fn warn_if_unreadable<W: Write>(machine: Option<&Machine>, format: &str, writer: &mut W) {
+    let Some(machine) = machine else {
+        return;
+    };
+    if !machine.reads_format(format) {
+        write_warning!(
+            writer,
+            "The {} reads {} files, not {}. Files will be converted to {} anyway.",
+            machine.name,
+            machine.file_formats.join(", "),
+            format,
+            format
+        );
+    }
+}
 
-    // Determine accepted formats and preferred format
-    let (accepted_formats, preferred_format) = match &machine {
+/// Returns the formats that can be copied to the machine as they are, and the
+/// format to convert other files to: `output_format` if it's given, or else
+/// the machine's default format (see `Machine::default_format`).
+fn output_formats(
+    machine: Option<&Machine>,
+    output_format: Option<String>,
+    inkscape: Option<&Inkscape>,
+) -> (Vec<String>, String) {
+    let (accepted_formats, preferred_format) = match machine {
         Some(machine) => {
             let formats = machine.file_formats.clone();
             let preferred = output_format
-                .or_else(|| formats.first().map(|s| s.to_string()))
+                .or_else(|| machine.default_format().map(ToString::to_string))
                 .unwrap_or_else(|| DEFAULT_FORMAT.to_string())
                 .to_lowercase();
             (formats, preferred)
@@ -319,62 +1838,308 @@ fn watch_command<W: Write>(
 
     // Convert preferred format to 'jef' if it ends with 'jef+'
     let preferred_format = if preferred_format == "jef+"
-        && !inkscape
-            .as_ref()
-            .unwrap()
-            .supported_write_formats
-            .contains(&preferred_format.as_str())
-    {
+        && !inkscape.is_some_and(|inkscape| {
+            inkscape
+                .supported_write_formats
+                .contains(&preferred_format.as_str())
+        }) {
         "jef".to_string()
     } else {
         preferred_format
     };
+    (accepted_formats, preferred_format)
+}
 
-    if let Some(ref machine) = machine {
-        writeln!(writer, "{} {}", "🧵 Machine:".bright_blue(), machine.name.clone().bold())?;
+/// Makes sure that `usb_target_path` exists on one of `usb_drives`, offering
+/// to create it on the first. Returns the path to copy files into, which is
+/// the drive root if the user declines.
+fn prepare_usb_target<'a, W: Write>(
+    usb_target_path: &'a str,
+    usb_drives: &[UsbDrive],
+    assume_yes: bool,
+    writer: &mut W,
+) -> Result<&'a str> {
+    let target_exists = usb_drives
+        .iter()
+        .any(|drive| drive.mount_point.join(usb_target_path).exists());
+    let Some(first_drive) = usb_drives.first().filter(|_| !target_exists) else {
+        return Ok(usb_target_path);
+    };
+    let full_path = first_drive.mount_point.join(usb_target_path);
+    writeln!(
+        writer,
+        "Target path '{}' does not exist on any USB drives.",
+        usb_target_path
+    )?;
+    if assume_yes || prompt_yes_no(&format!("Create it on {}? [y/n] ", first_drive.name), None) {
+        if let Err(e) = std::fs::create_dir_all(&full_path) {
+            write_warning!(
+                writer,
+                "Couldn't create {}: {}. Files will be copied to the root of the drive.",
+                full_path.display(),
+                e
+            );
+            return Ok("");
+        }
+        Ok(usb_target_path)
+    } else {
+        writeln!(
+            writer,
+            "Target path '{}' not created. Files will be copied to the root of the drive.",
+            usb_target_path
+        )?;
+        Ok("")
     }
-    writeln!(writer, "{} {}", "📁 Watch directory:".bright_blue(), watch_dir.display().to_string().bold())?;
-    if let Some(usb_target_dir) = find_usb_containing_path(usb_target_path) {
-        writeln!(writer, "{} {}", "💾 USB target directory:".bright_blue(), usb_target_dir.display().to_string().bold())?;
+}
+
+/// Warns about drives that files can't be written to, e.g. because their
+/// write-protect switch is on, so that this can be fixed before files arrive
+/// rather than discovered as each copy fails.
+fn warn_if_read_only<W: Write>(usb_drives: &[UsbDrive], usb_target_path: &str, writer: &mut W) {
+    for drive in usb_drives {
+        let dir = drive.mount_point.join(usb_target_path);
+        if !dir.is_dir() {
+            continue;
+        }
+        if let Err(e) = check_writable(&dir) {
+            write_warning!(
+                writer,
+                "Can't write to {} on {} ({}). Check that the drive isn't write-protected; until then, files won't be copied to it.",
+                dir.display(),
+                drive.name,
+                e
+            );
+        }
     }
-    match accepted_formats.len() {
-        1 => writeln!(writer, " {} {}", "→ Files will be converted to".bright_blue(), accepted_formats[0].clone().bold())?,
-        _ => writeln!(writer, " {} {}", "→ Files will be converted to one of:".bright_blue(), accepted_formats.join(", ").bold())?,
+}
+
+/// Lists the `count` most recent files in the watch directories, with a ✓
+/// for those that would be converted or copied and a ✗ for the others.
+fn show_recent_files<W: Write>(
+    watch_dirs: &[PathBuf],
+    count: usize,
+    is_convertible: impl Fn(&str) -> bool,
+    writer: &mut W,
+) -> Result<()> {
+    let files = services::recent_files(watch_dirs, count);
+    if files.is_empty() {
+        writeln!(
+            writer,
+            " {}",
+            "→ There are no files in the watch directory".bright_blue()
+        )?;
+        return Ok(());
     }
-    writeln!(writer, " {} {} {}", "→ Files will be copied into the".bright_blue(), machine
-        .as_ref()
-        .and_then(|m| m.usb_path.as_deref())
-        .unwrap_or(" root ")
-        .stylize().bold(),
-        "directory on a mounted USB drive".bright_blue())?;
-    writeln!(writer, "\n{}", "Press 'q' to quit".bright_black().italic())?;
+    writeln!(writer, "{}", "🕘 Recent files:".bright_blue())?;
+    for path in files {
+        let name = if watch_dirs.len() == 1 {
+            path.file_name().unwrap_or_default().to_string_lossy()
+        } else {
+            path.to_string_lossy()
+        };
+        let mark = if is_convertible(&services::file_format(&path)) {
+            "✓".bright_green()
+        } else {
+            "✗".bright_red()
+        };
+        writeln!(writer, "   {} {}", mark, name)?;
+    }
+    Ok(())
+}
 
-    services::watch_dir(
-        &watch_dir,
-        &Some(usb_target_path),
-        &accepted_formats
-            .iter()
-            .map(|s| s.as_str())
-            .collect::<Vec<_>>(),
-        &preferred_format,
-        inkscape,
-    );
+/// The directory to watch when none is given or configured: the current
+/// directory, if it has designs in it and the user agrees, or else
+/// `~/Downloads`.
+fn default_watch_dir(can_prompt: bool) -> PathBuf {
+    if can_prompt {
+        if let Ok(cwd) = std::env::current_dir() {
+            if services::contains_design_files(&cwd)
+                && prompt_yes_no(
+                    &format!("Watch current directory ({})? [Y/n] ", cwd.display()),
+                    Some(true),
+                )
+            {
+                return cwd;
+            }
+        }
+    }
+    dirs::home_dir()
+        .expect("Could not find home directory")
+        .join("Downloads")
+}
+
+/// Makes sure the watch directory exists, creating it or waiting for it as
+/// requested. Returns false if watching should not proceed.
+fn ensure_watch_dir<W: Write>(
+    watch_dir: &Path,
+    create_dir: bool,
+    wait_for_dir: bool,
+    writer: &mut W,
+) -> Result<bool> {
+    if watch_dir.is_dir() {
+        return Ok(true);
+    }
+
+    let create_dir = create_dir
+        || (!wait_for_dir
+            && io::stdin().is_terminal()
+            && prompt_yes_no(
+                &format!(
+                    "Directory {} does not exist. Create it? [y/N] ",
+                    watch_dir.display()
+                ),
+                Some(false),
+            ));
+
+    if create_dir {
+        fs::create_dir_all(watch_dir)?;
+        writeln!(writer, "Created directory {}", watch_dir.display())?;
+    } else if wait_for_dir {
+        writeln!(
+            writer,
+            "Waiting for {} to be created (press Ctrl-C to cancel)...",
+            watch_dir.display()
+        )?;
+        while !watch_dir.is_dir() {
+            std::thread::sleep(DIRECTORY_POLL_INTERVAL);
+        }
+    } else {
+        print_error!("Directory does not exist: {}", watch_dir.display());
+        writeln!(
+            writer,
+            "Use --create-dir to create it, or --wait-for-dir to wait for it to appear."
+        )?;
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+fn prune_outputs<W: Write>(days: u64, writer: &mut W) -> Result<()> {
+    let mut manifest = OutputManifest::load();
+    let removed = manifest.prune_older_than(Duration::from_secs(days * 24 * 60 * 60));
+    manifest.save()?;
+
+    for path in &removed {
+        writeln!(writer, "🗑️  Removed {}", path.display())?;
+    }
+    if !removed.is_empty() {
+        writeln!(
+            writer,
+            "Removed {} converted file{} older than {} day{}",
+            removed.len(),
+            if removed.len() == 1 { "" } else { "s" },
+            days,
+            if days == 1 { "" } else { "s" }
+        )?;
+    }
     Ok(())
 }
 
-fn update_command<W: Write>(dry_run: bool, writer: &mut W) -> Result<()> {
+/// The release URLs, from the config file or the environment
+fn update_urls() -> UpdateUrls {
+    let config = ConfigManager::new()
+        .and_then(|manager| manager.load_merged())
+        .unwrap_or_else(|_| Config::default().with_env_overrides().unwrap_or_default());
+    UpdateUrls::new(
+        config.update_api_url.as_deref(),
+        config.update_base_url.as_deref(),
+    )
+}
+
+/// The exit status of `update --exit-code` when an update was installed, or
+/// is available with `--dry-run`
+const UPDATED_EXIT_CODE: u8 = 10;
+
+/// The exit status of `batch` when any file couldn't be converted
+const BATCH_FAILED_EXIT_CODE: u8 = 1;
+
+/// Returned by a command whose exit status should say more than whether it
+/// ran, such as `update --exit-code` when there was an update, or `batch`
+/// when some files failed to convert. The command has already reported its
+/// results.
+/// `main` exits with `code` instead of reporting this as an error.
+#[derive(Debug, PartialEq)]
+pub struct ExitStatus {
+    pub code: u8,
+}
+
+impl fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "exit status {}", self.code)
+    }
+}
+
+impl std::error::Error for ExitStatus {}
+
+/// The result of `update`, as printed by `update --json`
+#[derive(Serialize)]
+pub(super) struct UpdateReport {
+    /// Whether a new version was installed
+    pub(super) updated: bool,
+    pub(super) from: String,
+    /// The latest version, which is `from` if that's already the latest
+    pub(super) to: String,
+    pub(super) dry_run: bool,
+}
+
+fn update_command<W: Write>(
+    dry_run: bool,
+    json: bool,
+    exit_code: bool,
+    writer: &mut W,
+) -> Result<()> {
+    // With --json, only the report is written
+    let report = if json {
+        install_update(dry_run, &mut io::sink())?
+    } else {
+        install_update(dry_run, writer)?
+    };
+    finish_update(&report, json, exit_code, writer)
+}
+
+/// Prints `report` if `json` is set. With `exit_code`, returns `ExitStatus`
+/// if there was an update.
+pub(super) fn finish_update<W: Write>(
+    report: &UpdateReport,
+    json: bool,
+    exit_code: bool,
+    writer: &mut W,
+) -> Result<()> {
+    if json {
+        writeln!(writer, "{}", serde_json::to_string(report)?)?;
+    }
+    if exit_code && report.to != report.from {
+        return Err(ExitStatus {
+            code: UPDATED_EXIT_CODE,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Installs the latest version, if it's newer than this one, reporting
+/// progress to `writer`.
+fn install_update<W: Write>(dry_run: bool, writer: &mut W) -> Result<UpdateReport> {
     let current_version = env!("CARGO_PKG_VERSION");
     writeln!(writer, "Current version: {}", current_version)?;
+    let mut report = UpdateReport {
+        updated: false,
+        from: current_version.to_string(),
+        to: current_version.to_string(),
+        dry_run,
+    };
 
     // Force fresh check for updates
     writeln!(writer, "Checking for updates...")?;
-    let latest_version = match version::get_latest_version(true)? {
+    let urls = update_urls();
+    let latest_version = match version::get_latest_version(true, &urls)? {
         Some(version) => version,
         None => {
             writeln!(writer, "You're already running the latest version!")?;
-            return Ok(());
+            return Ok(report);
         }
     };
+    report.to = latest_version.clone();
 
     writeln!(writer, "New version available: {}", latest_version)?;
 
@@ -386,6 +2151,20 @@ fn update_command<W: Write>(dry_run: bool, writer: &mut W) -> Result<()> {
         _ => return Err(anyhow::anyhow!("Unsupported platform")),
     };
 
+    let asset_name = format!("stitch-sync-x86_64-{}.tar.gz", platform);
+    let download_url = urls.download_url(&latest_version, &asset_name);
+
+    if dry_run {
+        writeln!(writer, "Download URL: {}", download_url)?;
+        let size = version::check_download(&download_url)
+            .with_context(|| format!("The download for {} isn't available", latest_version))?;
+        if let Some(size) = size {
+            writeln!(writer, "Download size: {}", utils::format_size(size))?;
+        }
+        writeln!(writer, "Dry run - not installing update")?;
+        return Ok(report);
+    }
+
     // Create temporary directory that will be cleaned up when we're done
     let tmp_dir = tempfile::tempdir()?;
     let _tmp_guard = scopeguard::guard(tmp_dir.path().to_path_buf(), |p| {
@@ -394,11 +2173,6 @@ fn update_command<W: Write>(dry_run: bool, writer: &mut W) -> Result<()> {
 
     // Download new version
     writeln!(writer, "⬇️  Downloading new version...")?;
-    let asset_name = format!("stitch-sync-x86_64-{}.tar.gz", platform);
-    let download_url = format!(
-        "https://github.com/osteele/stitch-sync/releases/download/v{}/{}",
-        latest_version, asset_name
-    );
 
     let archive_path = tmp_dir.path().join(&asset_name);
     let client = reqwest::blocking::Client::new();
@@ -421,17 +2195,58 @@ fn update_command<W: Write>(dry_run: bool, writer: &mut W) -> Result<()> {
     // Get current executable path
     let current_exe = env::current_exe()?;
 
-    if dry_run {
-        writeln!(writer, "Dry run - not installing update")?;
-        return Ok(());
-    }
-
     // Replace current executable
     writeln!(writer, "⬇️  Installing update...")?;
     let new_exe = tmp_dir.path().join(exe_name);
     fs::rename(&new_exe, &current_exe)?;
 
-    writeln!(writer, "✅ Successfully updated to version {}", latest_version)?;
+    writeln!(
+        writer,
+        "✅ Successfully updated to version {}",
+        latest_version
+    )?;
+    report.updated = true;
+    Ok(report)
+}
+
+/// The directories that stitch-sync keeps files in, with a description and
+/// the names of the files in each.
+fn app_dirs() -> Vec<(&'static str, Option<PathBuf>, &'static [&'static str])> {
+    [
+        ("Config", AppDir::Config),
+        ("Cache", AppDir::Cache),
+        ("Data", AppDir::Data),
+    ]
+    .into_iter()
+    .map(|(label, dir)| (label, dir.path(), dir.files()))
+    .collect()
+}
+
+fn dirs_command<W: Write>(open: bool, writer: &mut W) -> Result<()> {
+    let dirs = app_dirs();
+    for (label, dir, files) in &dirs {
+        let Some(dir) = dir else {
+            writeln!(writer, "{} directory: unknown on this system", label)?;
+            continue;
+        };
+        let missing = if dir.exists() {
+            ""
+        } else {
+            " (not created yet)"
+        };
+        writeln!(writer, "{} directory: {}{}", label, dir.display(), missing)?;
+        for file in *files {
+            writeln!(writer, "  {}", file)?;
+        }
+    }
+    if open {
+        let config_dir = dirs[0]
+            .1
+            .as_ref()
+            .context("Could not determine config directory")?;
+        fs::create_dir_all(config_dir)?;
+        services::open_browser(&config_dir.to_string_lossy());
+    }
     Ok(())
 }
 
@@ -481,7 +2296,10 @@ Add any other context about the problem here.
 
     // Open the new issue page with the bug report template
     let url_with_template = format!("{}?body={}", url, urlencoding::encode(&bug_report_template));
-    writeln!(writer, "Opening new issue page on GitHub with bug report template...")?;
+    writeln!(
+        writer,
+        "Opening new issue page on GitHub with bug report template..."
+    )?;
     services::open_browser(&url_with_template);
     Ok(())
 }
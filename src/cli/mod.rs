@@ -1,6 +1,9 @@
-#[cfg(test)]
-mod tests;
+#[allow(clippy::module_inception)]
 mod cli;
 mod commands;
+#[cfg(test)]
+#[allow(clippy::module_inception)]
+mod tests;
 
 pub use cli::*;
+pub use commands::ExitStatus;
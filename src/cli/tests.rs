@@ -1,11 +1,12 @@
 use super::*;
 use crate::types::machine::Machine;
+use crate::types::MACHINES;
 use lazy_static::lazy_static;
 use mockall::mock;
 use mockall::predicate::*;
+use std::fs;
 use std::path::PathBuf;
 use std::sync::Mutex;
-use std::fs;
 use tempfile::TempDir;
 
 use crate::config::manager::ConfigManager;
@@ -87,11 +88,14 @@ mod tests {
             });
 
         let cli = Cli {
-            command: Some(Commands::Watch {
-                dir: Some(PathBuf::from("/test/dir")),
+            theme: None,
+            inkscape_path: None,
+            command: Some(Commands::Watch(Box::new(WatchArgs {
+                dir: vec![PathBuf::from("/test/dir")],
                 output_format: Some("exp".to_string()),
                 machine: Some("test_machine".to_string()),
-            }),
+                ..Default::default()
+            }))),
         };
 
         let mut writer = std::io::stdout();
@@ -114,9 +118,12 @@ mod tests {
             .returning(|_, _| Ok(()));
 
         let cli = Cli {
+            theme: None,
+            inkscape_path: None,
             command: Some(Commands::Set {
                 what: "machine".to_string(),
                 value: Some("test_machine".to_string()),
+                match_threshold: None,
             }),
         };
 
@@ -145,9 +152,14 @@ mod tests {
         });
 
         let cli = Cli {
+            theme: None,
+            inkscape_path: None,
             command: Some(Commands::Machines {
+                command: None,
                 format: Some("dst".to_string()),
+                manufacturer: None,
                 verbose: false,
+                stats: false,
             }),
         };
 
@@ -173,6 +185,356 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_machine_stats_command() {
+        let cli = Cli {
+            theme: None,
+            inkscape_path: None,
+            command: Some(Commands::Machines {
+                command: None,
+                format: Some("dst".to_string()),
+                manufacturer: None,
+                verbose: false,
+                stats: true,
+            }),
+        };
+
+        let mut output = Vec::new();
+        cli.command.unwrap().execute(&mut output).unwrap();
+
+        let output_string = String::from_utf8(output).unwrap();
+        let dst_count = MACHINES
+            .iter()
+            .filter(|m| m.file_formats.contains(&"dst".to_string()))
+            .count();
+        assert!(output_string.starts_with(&format!("{} machines\n", dst_count)));
+        assert!(output_string.contains("Brother"));
+        let dst_line = output_string
+            .lines()
+            .find(|line| line.trim_start().starts_with("dst "))
+            .expect("Output should count the dst format");
+        assert!(dst_line.ends_with(&dst_count.to_string()));
+    }
+
+    #[test]
+    fn test_usb_target_argument() {
+        use clap::Parser;
+
+        let cli = Cli::try_parse_from([
+            "stitch-sync",
+            "watch",
+            "--usb-target",
+            "JANOME-USB:Embf/new",
+        ])
+        .unwrap();
+        let Some(Commands::Watch(args)) = cli.command else {
+            panic!("Expected the watch command");
+        };
+        assert_eq!(
+            args.usb_target,
+            Some(UsbTarget {
+                drive: "JANOME-USB".to_string(),
+                path: PathBuf::from("Embf").join("new").to_string_lossy().into(),
+            })
+        );
+
+        let cli = Cli::try_parse_from(["stitch-sync", "watch", "--usb-target", "E::"]).unwrap();
+        let Some(Commands::Watch(args)) = cli.command else {
+            panic!("Expected the watch command");
+        };
+        let target = args.usb_target.unwrap();
+        assert_eq!((target.drive.as_str(), target.path.as_str()), ("E:", ""));
+
+        assert!(
+            Cli::try_parse_from(["stitch-sync", "watch", "--usb-target", "JANOME-USB"]).is_err()
+        );
+        assert!(
+            Cli::try_parse_from(["stitch-sync", "watch", "--usb-target", "USB:../up"]).is_err()
+        );
+        assert!(Cli::try_parse_from([
+            "stitch-sync",
+            "watch",
+            "--usb-target",
+            "USB:Embf",
+            "--usb-root"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_match_threshold_argument() {
+        use clap::Parser;
+
+        let cli =
+            Cli::try_parse_from(["stitch-sync", "watch", "--match-threshold", "0.6"]).unwrap();
+        let Some(Commands::Watch(args)) = cli.command else {
+            panic!("Expected the watch command");
+        };
+        assert_eq!(args.match_threshold, Some(0.6));
+
+        assert!(Cli::try_parse_from(["stitch-sync", "watch", "--match-threshold", "1.5"]).is_err());
+        assert!(Cli::try_parse_from([
+            "stitch-sync",
+            "machine",
+            "info",
+            "pe800",
+            "--match-threshold",
+            "high"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_input_format_argument() {
+        use clap::Parser;
+
+        let cli = Cli::try_parse_from(["stitch-sync", "watch", "--input-format", ".DST"]).unwrap();
+        let Some(Commands::Watch(args)) = cli.command else {
+            panic!("Expected the watch command");
+        };
+        assert_eq!(args.input_format.as_deref(), Some("dst"));
+
+        assert!(Cli::try_parse_from(["stitch-sync", "watch", "--input-format", "dts"]).is_err());
+    }
+
+    #[test]
+    fn test_machines_edit_argument() {
+        use clap::Parser;
+
+        let cli = Cli::try_parse_from(["stitch-sync", "machines", "edit"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Machines {
+                command: Some(MachinesCommand::Edit),
+                ..
+            })
+        ));
+        assert!(Cli::try_parse_from(["stitch-sync", "machines", "--stats", "edit"]).is_err());
+    }
+
+    #[test]
+    fn test_batch_since_argument() {
+        use clap::Parser;
+        use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+        let since = |value: &str| match Cli::try_parse_from([
+            "stitch-sync",
+            "batch",
+            "designs",
+            "--since",
+            value,
+        ])
+        .map(|cli| cli.command)
+        {
+            Ok(Some(Commands::Batch { since, .. })) => Ok(since),
+            Ok(_) => panic!("expected a batch command"),
+            Err(e) => Err(e),
+        };
+        assert_eq!(since("last").unwrap(), Some(Since::LastRun));
+        assert_eq!(
+            since("2024-02-29").unwrap(),
+            Some(Since::Time(UNIX_EPOCH + Duration::from_secs(1_709_164_800)))
+        );
+        let Some(Since::Time(time)) = since("2h").unwrap() else {
+            panic!("expected a time");
+        };
+        let age = SystemTime::now().duration_since(time).unwrap();
+        assert!(age >= Duration::from_secs(7200) && age < Duration::from_secs(7260));
+        assert!(since("yesterday").is_err());
+    }
+
+    #[test]
+    fn test_uses_machines() {
+        use clap::Parser;
+
+        let uses_machines = |args: &[&str]| {
+            Cli::try_parse_from(["stitch-sync"].iter().chain(args))
+                .unwrap()
+                .uses_machines()
+        };
+        assert!(uses_machines(&[]));
+        assert!(uses_machines(&["watch", "--machine", "Brother PE800"]));
+        assert!(uses_machines(&["machine", "info", "pe800"]));
+        assert!(uses_machines(&["machines"]));
+        assert!(uses_machines(&["config", "set", "machine", "pe800"]));
+        assert!(uses_machines(&["__complete-machines"]));
+        assert!(!uses_machines(&["machines", "edit"]));
+        assert!(!uses_machines(&["config", "show"]));
+        assert!(!uses_machines(&["formats"]));
+        assert!(!uses_machines(&["dirs"]));
+    }
+
+    #[test]
+    fn test_batch_missing_dir() {
+        let dir = TempDir::new().unwrap();
+        let result = Commands::Batch {
+            dir: dir.path().join("missing"),
+            output_format: None,
+            output: None,
+            jobs: None,
+            quiet: true,
+            since: None,
+        }
+        .execute(&mut Vec::new());
+        assert!(result.unwrap_err().to_string().contains("does not exist"));
+    }
+
+    #[test]
+    fn test_machines_manufacturer_filter() {
+        let mut output = Vec::new();
+        Commands::Machines {
+            command: None,
+            format: None,
+            manufacturer: Some("brother".to_string()),
+            verbose: false,
+            stats: false,
+        }
+        .execute(&mut output)
+        .unwrap();
+
+        let output_string = String::from_utf8(output).unwrap();
+        assert!(output_string.contains("Brother PE800"));
+        assert!(!output_string.contains("Janome"));
+    }
+
+    #[test]
+    fn test_complete_machines_command() {
+        let mut output = Vec::new();
+        Commands::CompleteMachines {
+            prefix: Some("brother".to_string()),
+        }
+        .execute(&mut output)
+        .unwrap();
+
+        let output_string = String::from_utf8(output).unwrap();
+        let names = output_string.lines().collect::<Vec<_>>();
+        assert!(names.contains(&"Brother PE800"));
+        assert!(names.iter().all(|name| name.starts_with("Brother")));
+    }
+
+    #[test]
+    fn test_formats_extension() {
+        let mut output = Vec::new();
+        Commands::Formats {
+            extension: Some("JEF".to_string()),
+        }
+        .execute(&mut output)
+        .unwrap();
+        let output_string = String::from_utf8(output).unwrap();
+        assert_eq!(output_string.lines().count(), 1);
+        assert!(output_string.starts_with("jef: "));
+
+        let result = Commands::Formats {
+            extension: Some("nope".to_string()),
+        }
+        .execute(&mut Vec::new());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_convert_options() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("design.bin");
+        std::fs::write(&file, "LA:design").unwrap();
+        let mut output = Vec::new();
+        Commands::ConvertOptions { file: file.clone() }
+            .execute(&mut output)
+            .unwrap();
+        let output_string = String::from_utf8(output).unwrap();
+        assert!(output_string.contains("(dst) can be converted to:"));
+        assert!(output_string.contains("\n  jef: "));
+        assert!(!output_string.contains("\n  dst"));
+        assert!(output_string.contains("Image previews: png"));
+
+        std::fs::write(&file, "not a design").unwrap();
+        let result = Commands::ConvertOptions { file }.execute(&mut Vec::new());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_dirs() {
+        let mut output = Vec::new();
+        Commands::Dirs { open: false }.execute(&mut output).unwrap();
+        let output_string = String::from_utf8(output).unwrap();
+        assert!(output_string.contains("Config directory: "));
+        assert!(output_string.contains("  config.toml"));
+        assert!(output_string.contains("  version-cache.json"));
+        assert!(output_string.contains("  history.jsonl"));
+    }
+
+    #[test]
+    fn test_compat_command() {
+        let mut output = Vec::new();
+        Commands::Compat {
+            machine: Some("janome mc500e".to_string()),
+            format: None,
+        }
+        .execute(&mut output)
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("Janome MC500E  jef "), "{}", output);
+        assert!(output.contains("for 1 of 1 machines"));
+
+        let mut output = Vec::new();
+        Commands::Compat {
+            machine: None,
+            format: Some("jef".to_string()),
+        }
+        .execute(&mut output)
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("Janome MB7"), "{}", output);
+        assert!(!output.contains("Brother"));
+
+        assert!(Commands::Compat {
+            machine: Some("Not A Machine".to_string()),
+            format: None,
+        }
+        .execute(&mut Vec::new())
+        .is_err());
+    }
+
+    #[test]
+    fn test_doctor_json() {
+        let mut output = Vec::new();
+        Commands::Doctor {
+            test_convert: false,
+            json: true,
+        }
+        .execute(&mut output)
+        .unwrap();
+        let report: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert!(report["inkscape"]["found"].is_boolean());
+        assert!(report["inkstitch"]["found"].is_boolean());
+        assert!(report["usb_drives"].is_array());
+        assert!(report["config"].is_object());
+    }
+
+    #[test]
+    fn test_machine_info_verbose() {
+        let mut output = Vec::new();
+        MachineCommand::Info {
+            name: "brother pe-800".to_string(),
+            match_threshold: None,
+            verbose: true,
+        }
+        .execute(&mut output)
+        .unwrap();
+        let output_string = String::from_utf8(output).unwrap();
+        assert!(output_string.contains("is an exact match for Brother PE800"));
+        assert!(output_string.contains("  1.000  Brother PE800\n"));
+    }
+
+    #[test]
+    fn test_send_missing_file() {
+        let result = Commands::Send(SendArgs {
+            file: PathBuf::from("does-not-exist.dst"),
+            ..Default::default()
+        })
+        .execute(&mut Vec::new());
+        assert!(result.unwrap_err().to_string().contains("File not found"));
+    }
+
     #[test]
     #[ignore]
     fn test_machine_info_command() {
@@ -188,9 +550,13 @@ mod tests {
             });
 
         let cli = Cli {
+            theme: None,
+            inkscape_path: None,
             command: Some(Commands::Machine {
                 command: MachineCommand::Info {
                     name: "machine1".to_string(),
+                    match_threshold: None,
+                    verbose: false,
                 },
             }),
         };
@@ -231,7 +597,13 @@ mod tests {
             .returning(|| Ok(Some("100.0.0".to_string())));
 
         let cli = Cli {
-            command: Some(Commands::Update { dry_run: true }),
+            theme: None,
+            inkscape_path: None,
+            command: Some(Commands::Update {
+                dry_run: true,
+                json: false,
+                exit_code: false,
+            }),
         };
 
         let mut output = Vec::new();
@@ -253,6 +625,44 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_update_report() {
+        let report = commands::UpdateReport {
+            updated: false,
+            from: "1.0.0".to_string(),
+            to: "1.1.0".to_string(),
+            dry_run: true,
+        };
+        let mut output = Vec::new();
+        let result = commands::finish_update(&report, true, true, &mut output);
+        let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "updated": false,
+                "from": "1.0.0",
+                "to": "1.1.0",
+                "dry_run": true,
+            })
+        );
+        assert_eq!(
+            result.unwrap_err().downcast_ref::<ExitStatus>(),
+            Some(&ExitStatus { code: 10 })
+        );
+
+        // Without an update, or without --exit-code, the command succeeds
+        let report = commands::UpdateReport {
+            to: "1.0.0".to_string(),
+            ..report
+        };
+        assert!(commands::finish_update(&report, false, true, &mut Vec::new()).is_ok());
+        let report = commands::UpdateReport {
+            to: "1.1.0".to_string(),
+            ..report
+        };
+        assert!(commands::finish_update(&report, false, false, &mut Vec::new()).is_ok());
+    }
+
     #[test]
     fn test_config_commands() {
         // Create a temporary directory for the config file
@@ -264,7 +674,9 @@ mod tests {
         // Create a mock file system
         let mut fs_mock = FILE_SYSTEM_MOCK.lock().unwrap();
         let config_path_clone = config_path.clone();
-        fs_mock.expect_file_exists().returning(move |path| path == &config_path_clone);
+        fs_mock
+            .expect_file_exists()
+            .returning(move |path| path == &config_path_clone);
         let config_path_clone = config_path.clone();
         fs_mock.expect_read_file().returning(move |path| {
             if *path == config_path_clone {
@@ -286,10 +698,13 @@ mod tests {
         let mut output = Vec::new();
 
         let set_cli = Cli {
+            theme: None,
+            inkscape_path: None,
             command: Some(Commands::Config {
                 command: ConfigCommand::Set {
                     key: ConfigKey::WatchDir,
                     value: Some("/new/watch/dir".to_string()),
+                    match_threshold: None,
                 },
             }),
         };
@@ -298,9 +713,14 @@ mod tests {
             let mut writer = std::io::BufWriter::new(&mut output);
             set_cli.command.unwrap().execute(&mut writer)
         };
-        assert!(set_result.is_ok(), "Config set command should execute successfully");
+        assert!(
+            set_result.is_ok(),
+            "Config set command should execute successfully"
+        );
 
         let show_cli = Cli {
+            theme: None,
+            inkscape_path: None,
             command: Some(Commands::Config {
                 command: ConfigCommand::Show,
             }),
@@ -310,13 +730,24 @@ mod tests {
             let mut writer = std::io::BufWriter::new(&mut output);
             show_cli.command.unwrap().execute(&mut writer)
         };
-        assert!(show_result.is_ok(), "Config show command should execute successfully");
+        assert!(
+            show_result.is_ok(),
+            "Config show command should execute successfully"
+        );
 
         let show_output = String::from_utf8(output.clone()).unwrap();
-        assert!(show_output.contains("Watch directory:"), "Output should contain Watch directory key");
-        assert!(show_output.contains("/new/watch/dir"), "Output should contain the new watch directory");
+        assert!(
+            show_output.contains("Watch directory:"),
+            "Output should contain Watch directory key"
+        );
+        assert!(
+            show_output.contains("/new/watch/dir"),
+            "Output should contain the new watch directory"
+        );
 
         let clear_cli = Cli {
+            theme: None,
+            inkscape_path: None,
             command: Some(Commands::Config {
                 command: ConfigCommand::Clear {
                     key: ConfigKey::WatchDir,
@@ -328,10 +759,16 @@ mod tests {
             let mut writer = std::io::BufWriter::new(&mut output);
             clear_cli.command.unwrap().execute(&mut writer)
         };
-        assert!(clear_result.is_ok(), "Config clear command should execute successfully");
+        assert!(
+            clear_result.is_ok(),
+            "Config clear command should execute successfully"
+        );
 
         // Assert the config key was cleared
         let config = config_manager.load().unwrap();
-        assert!(config.watch_dir.is_none(), "Watch directory should be cleared");
+        assert!(
+            config.watch_dir.is_none(),
+            "Watch directory should be cleared"
+        );
     }
 }
@@ -1,8 +1,13 @@
-use super::types::Config;
+use super::types::{Config, ProjectConfig};
 use anyhow::{Context, Result};
-use dirs::config_dir;
+use std::env;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::utils::app_dirs::{AppDir, CONFIG_FILE, USER_MACHINES_FILE};
+
+pub const PROJECT_CONFIG_FILE: &str = ".stitch-sync.toml";
 
 pub struct ConfigManager {
     config_path: PathBuf,
@@ -10,12 +15,12 @@ pub struct ConfigManager {
 
 impl ConfigManager {
     pub fn new() -> Result<Self> {
-        let config_dir = config_dir()
-            .context("Could not determine config directory")?
-            .join("stitch-sync");
+        let config_dir = AppDir::Config
+            .path()
+            .context("Could not determine config directory")?;
 
         fs::create_dir_all(&config_dir)?;
-        let config_path = config_dir.join("config.toml");
+        let config_path = config_dir.join(CONFIG_FILE);
 
         Ok(Self { config_path })
     }
@@ -26,36 +31,203 @@ impl ConfigManager {
         }
 
         let content = fs::read_to_string(&self.config_path)?;
-        toml::from_str(&content).context("Failed to parse config file")
+        toml::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", self.config_path.display()))
     }
 
-    pub fn save(&self, config: &Config) -> Result<()> {
-        let content = toml::to_string_pretty(config)?;
+    /// Loads the global config, overridden by the project config for the
+    /// current directory, if there is one, and then by `STITCH_SYNC_*`
+    /// environment variables.
+    pub fn load_merged(&self) -> Result<Config> {
+        let config = self.load()?;
+        let config = match env::current_dir()
+            .ok()
+            .and_then(|dir| find_project_config(&dir))
+        {
+            Some(path) => config.merge(load_project_config(&path)?),
+            None => config,
+        };
+        config.with_env_overrides().map_err(anyhow::Error::msg)
+    }
+
+    /// Saves the config, with `watch_dir` folded into `watch_dirs`.
+    pub fn save(&self, config: Config) -> Result<()> {
+        let content = toml::to_string_pretty(&config.migrate())?;
         fs::write(&self.config_path, content)?;
         Ok(())
     }
 
+    pub fn config_path(&self) -> &Path {
+        &self.config_path
+    }
+
+    /// The path of the user's machine list, next to the config file.
+    pub fn machines_path(&self) -> PathBuf {
+        self.config_path.with_file_name(USER_MACHINES_FILE)
+    }
+
+    /// Opens the config file in the user's editor, waits for it to exit, and
+    /// then checks that the file still parses.
+    pub fn edit(&self) -> Result<Config> {
+        if !self.config_path.exists() {
+            self.save(Config::default())?;
+        }
+        open_in_editor(&self.config_path)?;
+        self.load()
+    }
+
+    /// Replaces the watch directories with `path`.
     pub fn set_watch_dir(&self, path: PathBuf) -> Result<()> {
         let mut config = self.load()?;
         config.watch_dir = Some(path);
-        self.save(&config)
+        config.watch_dirs = None;
+        self.save(config)
     }
 
     pub fn set_machine(&self, machine: String) -> Result<()> {
         let mut config = self.load()?;
         config.machine = Some(machine);
-        self.save(&config)
+        self.save(config)
     }
 
     pub fn clear_watch_dir(&self) -> Result<()> {
         let mut config = self.load()?;
         config.watch_dir = None;
-        self.save(&config)
+        config.watch_dirs = None;
+        self.save(config)
     }
 
     pub fn clear_machine(&self) -> Result<()> {
         let mut config = self.load()?;
         config.machine = None;
-        self.save(&config)
+        self.save(config)
+    }
+}
+
+/// Opens `path` in `$VISUAL`, `$EDITOR`, or the platform's default editor,
+/// and waits for the editor to exit.
+pub fn open_in_editor(path: &Path) -> Result<()> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| default_editor().to_string());
+    let mut words = editor.split_whitespace();
+    let program = words.next().context("The editor command is empty")?;
+    let status = Command::new(program)
+        .args(words)
+        .arg(path)
+        .status()
+        .with_context(|| format!("Failed to run editor '{}'", editor))?;
+    if !status.success() {
+        anyhow::bail!("Editor '{}' exited with {}", editor, status);
+    }
+    Ok(())
+}
+
+fn default_editor() -> &'static str {
+    if cfg!(target_os = "windows") {
+        "notepad"
+    } else if cfg!(target_os = "macos") {
+        // -W waits for the editor to close, -t uses the default text editor
+        "open -W -t"
+    } else {
+        "vi"
+    }
+}
+
+/// Returns the path of the nearest `.stitch-sync.toml` in `start` or one of
+/// its ancestors.
+pub fn find_project_config(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .map(|dir| dir.join(PROJECT_CONFIG_FILE))
+        .find(|path| path.is_file())
+}
+
+/// Reads a project config. A relative `output_dir` is resolved against the
+/// directory that contains the file.
+pub fn load_project_config(path: &Path) -> Result<ProjectConfig> {
+    let content = fs::read_to_string(path)?;
+    let mut config: ProjectConfig =
+        toml::from_str(&content).with_context(|| format!("Failed to parse {}", path.display()))?;
+    if let (Some(output_dir), Some(project_dir)) = (&config.output_dir, path.parent()) {
+        config.output_dir = Some(project_dir.join(output_dir));
+    }
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_project_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let nested = dir.path().join("designs").join("flowers");
+        fs::create_dir_all(&nested).unwrap();
+        assert_eq!(find_project_config(&nested), None);
+
+        let path = dir.path().join(PROJECT_CONFIG_FILE);
+        fs::write(&path, "").unwrap();
+        assert_eq!(find_project_config(&nested), Some(path));
+    }
+
+    #[test]
+    fn test_project_config_overrides_global() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(PROJECT_CONFIG_FILE);
+        fs::write(&path, "machine = \"Brother PE800\"\noutput_dir = \"out\"\n").unwrap();
+
+        let global = Config {
+            watch_dir: Some(PathBuf::from("/downloads")),
+            machine: Some("Janome 500E".to_string()),
+            output_format: Some("jef".to_string()),
+            output_dir: None,
+            ..Default::default()
+        };
+        let config = global.merge(load_project_config(&path).unwrap());
+
+        assert_eq!(config.watch_dir, Some(PathBuf::from("/downloads")));
+        assert_eq!(config.machine.as_deref(), Some("Brother PE800"));
+        assert_eq!(config.output_format.as_deref(), Some("jef"));
+        assert_eq!(config.output_dir, Some(dir.path().join("out")));
+    }
+
+    #[test]
+    fn test_project_presets_override_global_presets() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("config.toml");
+        fs::write(
+            &config_path,
+            "[presets]\njef = [\"--a\"]\npes = [\"--b\"]\n",
+        )
+        .unwrap();
+        let path = dir.path().join(PROJECT_CONFIG_FILE);
+        fs::write(&path, "[presets]\njef = [\"--c\", \"--d\"]\n").unwrap();
+
+        let global = ConfigManager { config_path }.load().unwrap();
+        let presets = global
+            .merge(load_project_config(&path).unwrap())
+            .presets
+            .unwrap();
+        assert_eq!(presets["jef"], ["--c", "--d"]);
+        assert_eq!(presets["pes"], ["--b"]);
+    }
+
+    #[test]
+    fn test_project_config_rejects_unknown_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(PROJECT_CONFIG_FILE);
+        fs::write(&path, "watch_dir = \"/tmp\"\n").unwrap();
+        assert!(load_project_config(&path).is_err());
+    }
+
+    #[test]
+    fn test_config_rejects_unknown_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("config.toml");
+        fs::write(&config_path, "machne = \"Brother PE800\"\n").unwrap();
+        let manager = ConfigManager { config_path };
+        let error = format!("{:#}", manager.load().unwrap_err());
+        assert!(error.contains("unknown field `machne`"), "{}", error);
     }
 }
@@ -1,8 +1,280 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::PathBuf;
+use std::str::FromStr;
 
+use crate::utils::colors::Theme;
+use crate::utils::SanitizeCase;
+
+/// The global settings. Unknown keys are rejected, so that a misspelled
+/// setting is reported instead of being ignored.
 #[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
+    /// A single watch directory. Configs are saved with this folded into
+    /// `watch_dirs`, but it's still read.
     pub watch_dir: Option<PathBuf>,
+    /// Directories to watch, in addition to `watch_dir`
+    pub watch_dirs: Option<Vec<PathBuf>>,
     pub machine: Option<String>,
+    pub output_format: Option<String>,
+    pub output_dir: Option<PathBuf>,
+    /// Overrides the URL used to find the latest release
+    pub update_api_url: Option<String>,
+    /// Overrides the URL that release downloads are fetched from
+    pub update_base_url: Option<String>,
+    /// A CSV file, in the format of the built-in machine list, whose
+    /// machines are added to the built-in ones
+    pub machines_url: Option<String>,
+    /// Whether the terminal background is dark or light, if the colors that
+    /// are chosen automatically are hard to read
+    pub theme: Option<Theme>,
+    /// If set, only USB drives with these names or mount points are used
+    pub usb_allowlist: Option<Vec<String>>,
+    /// Whether output filenames are lowercased (the default), uppercased, or
+    /// keep the case of the original
+    pub sanitize_case: Option<SanitizeCase>,
+    /// The Inkscape executable, for installs that aren't found automatically
+    /// such as an AppImage
+    pub inkscape_path: Option<PathBuf>,
+    /// The directory on USB drives that files are copied to, such as
+    /// `Embroidery/Inbox`, instead of the machine's designs directory
+    pub usb_subpath: Option<String>,
+    /// Extra Inkscape arguments for conversions to each output format, e.g.
+    /// `jef = ["--export-dpi=300"]` in a `[presets]` table
+    pub presets: Option<BTreeMap<String, Vec<String>>>,
+    /// How much to scale designs that are converted for each machine, e.g.
+    /// `"Brother SE600" = 0.75` in a `[scales]` table
+    pub scales: Option<BTreeMap<String, f64>>,
+}
+
+/// Settings from a project's `.stitch-sync.toml`. These take precedence over
+/// the global config.
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectConfig {
+    pub machine: Option<String>,
+    pub output_format: Option<String>,
+    pub output_dir: Option<PathBuf>,
+    /// Replaces the global presets for the formats that it lists
+    pub presets: Option<BTreeMap<String, Vec<String>>>,
+}
+
+/// Environment variables that override config keys, e.g. `STITCH_SYNC_MACHINE`
+/// for `machine`.
+pub const ENV_PREFIX: &str = "STITCH_SYNC_";
+
+impl Config {
+    /// Replaces settings with the `STITCH_SYNC_*` environment variables that
+    /// are set and not empty. Returns an error, naming the variable, if one
+    /// has a value that the setting can't have.
+    pub fn with_env_overrides(self) -> Result<Config, String> {
+        self.with_overrides(|key| {
+            std::env::var(env_var_name(key))
+                .ok()
+                .filter(|value| !value.is_empty())
+        })
+    }
+
+    /// The directories to watch: `watch_dir`, if it's set, followed by those
+    /// in `watch_dirs`, without duplicates.
+    pub fn watch_dirs(&self) -> Vec<PathBuf> {
+        let mut dirs: Vec<PathBuf> = Vec::new();
+        for dir in self
+            .watch_dir
+            .iter()
+            .chain(self.watch_dirs.iter().flatten())
+        {
+            if !dirs.contains(dir) {
+                dirs.push(dir.clone());
+            }
+        }
+        dirs
+    }
+
+    /// Moves `watch_dir` into `watch_dirs`, so that the saved config has a
+    /// single list of directories.
+    pub fn migrate(self) -> Config {
+        let watch_dirs = self.watch_dirs();
+        Config {
+            watch_dir: None,
+            watch_dirs: (!watch_dirs.is_empty()).then_some(watch_dirs),
+            ..self
+        }
+    }
+
+    /// Replaces settings with the values that `lookup` returns for their keys.
+    /// `presets` and `scales` are tables, and can't be overridden.
+    fn with_overrides(self, lookup: impl Fn(&str) -> Option<String>) -> Result<Config, String> {
+        // A directory from the environment replaces the configured list
+        let (watch_dir, watch_dirs) = match lookup("watch_dir") {
+            Some(dir) => (Some(PathBuf::from(dir)), None),
+            None => (self.watch_dir, self.watch_dirs),
+        };
+        Ok(Config {
+            watch_dir,
+            watch_dirs,
+            machine: lookup("machine").or(self.machine),
+            output_format: lookup("output_format").or(self.output_format),
+            output_dir: lookup("output_dir").map(PathBuf::from).or(self.output_dir),
+            update_api_url: lookup("update_api_url").or(self.update_api_url),
+            update_base_url: lookup("update_base_url").or(self.update_base_url),
+            machines_url: lookup("machines_url").or(self.machines_url),
+            theme: parse_override(&lookup, "theme")?.or(self.theme),
+            usb_allowlist: lookup("usb_allowlist")
+                .map(|names| {
+                    names
+                        .split(',')
+                        .map(|name| name.trim().to_string())
+                        .collect()
+                })
+                .or(self.usb_allowlist),
+            sanitize_case: parse_override(&lookup, "sanitize_case")?.or(self.sanitize_case),
+            inkscape_path: lookup("inkscape_path")
+                .map(PathBuf::from)
+                .or(self.inkscape_path),
+            usb_subpath: lookup("usb_subpath").or(self.usb_subpath),
+            presets: self.presets,
+            scales: self.scales,
+        })
+    }
+
+    pub fn merge(self, project: ProjectConfig) -> Config {
+        Config {
+            watch_dir: self.watch_dir,
+            watch_dirs: self.watch_dirs,
+            machine: project.machine.or(self.machine),
+            output_format: project.output_format.or(self.output_format),
+            output_dir: project.output_dir.or(self.output_dir),
+            update_api_url: self.update_api_url,
+            update_base_url: self.update_base_url,
+            machines_url: self.machines_url,
+            theme: self.theme,
+            usb_allowlist: self.usb_allowlist,
+            sanitize_case: self.sanitize_case,
+            inkscape_path: self.inkscape_path,
+            usb_subpath: self.usb_subpath,
+            presets: match (self.presets, project.presets) {
+                (Some(mut presets), Some(project_presets)) => {
+                    presets.extend(project_presets);
+                    Some(presets)
+                }
+                (presets, project_presets) => project_presets.or(presets),
+            },
+            scales: self.scales,
+        }
+    }
+}
+
+/// The environment variable that overrides the config key `key`.
+fn env_var_name(key: &str) -> String {
+    format!("{}{}", ENV_PREFIX, key.to_uppercase())
+}
+
+/// Parses the value that `lookup` returns for `key`, if there is one.
+fn parse_override<T: FromStr<Err = String>>(
+    lookup: impl Fn(&str) -> Option<String>,
+    key: &str,
+) -> Result<Option<T>, String> {
+    lookup(key)
+        .map(|value| value.parse())
+        .transpose()
+        .map_err(|e| format!("{}: {}", env_var_name(key), e))
+}
+
+/// The scale in `scales` for the named machine. Machine names are matched
+/// ignoring case.
+pub fn scale_for(scales: Option<&BTreeMap<String, f64>>, machine: &str) -> Option<f64> {
+    scales
+        .into_iter()
+        .flatten()
+        .find(|(name, _)| name.eq_ignore_ascii_case(machine))
+        .map(|(_, scale)| *scale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_with_overrides() {
+        let config = Config {
+            machine: Some("Janome 500E".to_string()),
+            output_format: Some("jef".to_string()),
+            ..Default::default()
+        };
+        let config = config.with_overrides(|key| match key {
+            "machine" => Some("Brother PE800".to_string()),
+            "watch_dir" => Some("/designs".to_string()),
+            "theme" => Some("Light".to_string()),
+            "usb_allowlist" => Some("EMB, Janome".to_string()),
+            "sanitize_case" => Some("preserve".to_string()),
+            "inkscape_path" => Some("/opt/Inkscape.AppImage".to_string()),
+            "usb_subpath" => Some("Embroidery/Inbox".to_string()),
+            _ => None,
+        });
+        let config = config.unwrap();
+        assert_eq!(config.machine.as_deref(), Some("Brother PE800"));
+        assert_eq!(config.watch_dir, Some(PathBuf::from("/designs")));
+        assert_eq!(config.output_format.as_deref(), Some("jef"));
+        assert_eq!(config.theme, Some(Theme::Light));
+        assert_eq!(config.sanitize_case, Some(SanitizeCase::Preserve));
+        assert_eq!(
+            config.inkscape_path,
+            Some(PathBuf::from("/opt/Inkscape.AppImage"))
+        );
+        assert_eq!(config.usb_subpath.as_deref(), Some("Embroidery/Inbox"));
+        assert_eq!(
+            config.usb_allowlist,
+            Some(vec!["EMB".to_string(), "Janome".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_invalid_override() {
+        let error = Config::default()
+            .with_overrides(|key| (key == "theme").then(|| "dim".to_string()))
+            .unwrap_err();
+        assert_eq!(
+            error,
+            "STITCH_SYNC_THEME: Unknown theme 'dim'; expected 'auto', 'dark', or 'light'"
+        );
+        let error = Config::default()
+            .with_overrides(|key| (key == "sanitize_case").then(|| "title".to_string()))
+            .unwrap_err();
+        assert!(error.starts_with("STITCH_SYNC_SANITIZE_CASE: "));
+    }
+
+    #[test]
+    fn test_watch_dirs() {
+        let config: Config =
+            toml::from_str("watch_dir = \"/a\"\nwatch_dirs = [\"/b\", \"/a\"]\n").unwrap();
+        assert_eq!(
+            config.watch_dirs(),
+            [PathBuf::from("/a"), PathBuf::from("/b")]
+        );
+
+        let config = config.migrate();
+        assert_eq!(config.watch_dir, None);
+        assert_eq!(
+            config.watch_dirs,
+            Some(vec![PathBuf::from("/a"), PathBuf::from("/b")])
+        );
+
+        let config = config
+            .with_overrides(|key| (key == "watch_dir").then(|| "/c".to_string()))
+            .unwrap();
+        assert_eq!(config.watch_dirs(), [PathBuf::from("/c")]);
+        assert_eq!(Config::default().migrate().watch_dirs, None);
+    }
+
+    #[test]
+    fn test_scale_for() {
+        let config: Config = toml::from_str("[scales]\n\"Brother SE600\" = 0.75\n").unwrap();
+        assert_eq!(
+            scale_for(config.scales.as_ref(), "brother se600"),
+            Some(0.75)
+        );
+        assert_eq!(scale_for(config.scales.as_ref(), "Janome 500E"), None);
+    }
 }
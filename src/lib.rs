@@ -0,0 +1,25 @@
+//! Watch a directory for embroidery designs, convert them with Inkscape and
+//! ink/stitch, and copy them to a USB drive.
+//!
+//! The `stitch-sync` binary is a thin wrapper around this library. Other
+//! programs can use the same building blocks, for example to look up a
+//! [`Machine`], run a conversion with [`handle_file_detection`], or drive the
+//! watch loop with [`watch_dir`].
+//!
+//! Enable the `async` feature for `watch_async`, a non-blocking version of the
+//! watch loop for use inside a tokio runtime.
+
+pub mod cli;
+pub mod config;
+pub mod services;
+pub mod types;
+pub mod utils;
+
+pub use services::{
+    handle_file_detection, watch_dir, ConversionOptions, ConvertError, Inkscape, SourceAction,
+    UsbDrive, WatchOptions,
+};
+pub use types::{FileFormat, Machine};
+
+#[cfg(feature = "async")]
+pub use services::watch_async;
@@ -1,21 +1,24 @@
-mod cli;
-mod config;
-mod services;
-mod types;
-mod utils;
+use std::process::ExitCode;
 
 use anyhow::Result;
 use clap::Parser;
-use cli::*;
+use stitch_sync::cli::*;
 
-fn main() -> Result<()> {
+fn main() -> Result<ExitCode> {
     let cli = Cli::parse();
+    cli.apply_theme();
+    cli.apply_inkscape_path()?;
+    cli.load_machines();
     let mut writer = std::io::stdout();
-    cli.command
-        .unwrap_or(Commands::Watch {
-            dir: None,
-            output_format: None,
-            machine: None,
-        })
-        .execute(&mut writer)
+    let result = cli
+        .command
+        .unwrap_or_else(|| Commands::Watch(Box::default()))
+        .execute(&mut writer);
+    match result {
+        Ok(()) => Ok(ExitCode::SUCCESS),
+        Err(e) => match e.downcast_ref::<ExitStatus>() {
+            Some(status) => Ok(ExitCode::from(status.code)),
+            None => Err(e),
+        },
+    }
 }
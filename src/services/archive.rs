@@ -0,0 +1,104 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::services::batch::find_files;
+use crate::services::file_conversion::file_format;
+use crate::services::inkscape::VECTOR_IMPORT_FORMATS;
+use crate::types::FileFormat;
+
+/// The extension of the archives that `--extract-archives` opens. Design
+/// marketplaces deliver designs, with their color charts, as zip files.
+pub const ARCHIVE_EXTENSION: &str = "zip";
+
+/// A directory that macOS adds to zip files it creates, holding metadata
+/// rather than designs
+const MACOS_METADATA_DIR: &str = "__MACOSX";
+
+/// Extracts the zip archive at `path` into `dest`. This uses `unzip` on Linux,
+/// and elsewhere the `tar` that comes with macOS and Windows, which reads zip
+/// files. Returns the tool's error message if it fails.
+pub fn extract_archive(path: &Path, dest: &Path) -> Result<(), String> {
+    #[cfg(target_os = "linux")]
+    let output = Command::new("unzip")
+        .args(["-qq", "-o"])
+        .arg(path)
+        .arg("-d")
+        .arg(dest)
+        .output();
+    #[cfg(not(target_os = "linux"))]
+    let output = Command::new("tar")
+        .arg("-xf")
+        .arg(path)
+        .arg("-C")
+        .arg(dest)
+        .output();
+    match output {
+        Ok(output) if output.status.success() => Ok(()),
+        Ok(output) => Err(String::from_utf8_lossy(&output.stderr).trim().to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Returns the design to use from the files extracted into `dir`: the first,
+/// in path order, that is in one of `accepted_formats`, or else the first in
+/// any embroidery format. PDF and Illustrator files are skipped, since in an
+/// archive these are usually color charts, as are hidden files.
+pub fn find_design(dir: &Path, accepted_formats: &[&str]) -> Option<PathBuf> {
+    let designs = find_files(dir)
+        .ok()?
+        .into_iter()
+        .filter(|path| !is_metadata(dir, path))
+        .map(|path| {
+            let format = file_format(&path);
+            (path, format)
+        })
+        .filter(|(_, format)| {
+            FileFormat::find_by_extension(format).is_some()
+                && !VECTOR_IMPORT_FORMATS.contains(&format.as_str())
+        })
+        .collect::<Vec<_>>();
+    designs
+        .iter()
+        .find(|(_, format)| accepted_formats.contains(&format.as_str()))
+        .or_else(|| designs.first())
+        .map(|(path, _)| path.clone())
+}
+
+/// Returns true for hidden files, and files in hidden or macOS metadata
+/// directories, such as `__MACOSX/._design.pes`.
+fn is_metadata(dir: &Path, path: &Path) -> bool {
+    path.strip_prefix(dir)
+        .unwrap_or(path)
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .any(|name| name.starts_with('.') || name == MACOS_METADATA_DIR)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_find_design() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str| {
+            let path = dir.path().join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, "design").unwrap();
+            path
+        };
+        write("Rose/colors.pdf");
+        write("Rose/notes.txt");
+        write("__MACOSX/Rose/._rose.dst");
+        let dst = write("Rose/rose.dst");
+        let jef = write("Rose/rose.jef");
+
+        assert_eq!(find_design(dir.path(), &["jef"]), Some(jef));
+        assert_eq!(find_design(dir.path(), &["pes"]), Some(dst));
+
+        let empty = tempfile::tempdir().unwrap();
+        fs::write(empty.path().join("colors.pdf"), "%PDF").unwrap();
+        assert_eq!(find_design(empty.path(), &["pes"]), None);
+    }
+}
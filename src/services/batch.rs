@@ -0,0 +1,214 @@
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::SystemTime;
+
+use crate::services::file_conversion::{
+    convert_file, file_format, should_convert_file, ConversionOptions,
+};
+use crate::services::Inkscape;
+use crate::utils::app_dirs::{AppDir, BATCH_RUNS_FILE};
+use crate::utils::ProgressBar;
+
+/// The outcome of converting a directory of designs.
+#[derive(Debug, Default)]
+pub struct BatchReport {
+    /// (source, output) pairs
+    pub converted: Vec<(PathBuf, PathBuf)>,
+    /// (source, error message) pairs
+    pub failed: Vec<(PathBuf, String)>,
+    /// Files that were already in the output format
+    pub skipped: Vec<PathBuf>,
+    /// Files that were last modified before `modified_since`
+    pub unchanged: Vec<PathBuf>,
+}
+
+/// A file to convert, and the format it's in.
+struct BatchFile {
+    path: PathBuf,
+    format: String,
+}
+
+/// Converts every design under `dir` that ink/stitch can read into
+/// `output_format`, using up to `jobs` Inkscape processes at once. Unless
+/// `quiet` is set, a progress bar tracks the conversions.
+///
+/// With `output_dir`, outputs are written there, mirroring the
+/// subdirectories of `dir`; otherwise each is written next to its source.
+pub fn batch_convert(
+    dir: &Path,
+    inkscape: &Inkscape,
+    output_format: &str,
+    output_dir: Option<&Path>,
+    jobs: usize,
+    quiet: bool,
+    options: &ConversionOptions,
+) -> io::Result<BatchReport> {
+    let mut report = BatchReport::default();
+    let mut files = Vec::new();
+    for path in find_files(dir)? {
+        let format = file_format(&path);
+        if options
+            .modified_since
+            .is_some_and(|since| !modified_since(&path, since))
+        {
+            report.unchanged.push(path);
+        } else if format == output_format {
+            report.skipped.push(path);
+        } else if should_convert_file(&format, Some(inkscape), output_format, options) {
+            files.push(BatchFile { path, format });
+        }
+    }
+
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::new());
+    let progress = (!quiet).then(|| Mutex::new(ProgressBar::new(files.len())));
+    thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, files.len().max(1)) {
+            scope.spawn(|| {
+                while let Some(file) = files.get(next.fetch_add(1, Ordering::SeqCst)) {
+                    let mut options = ConversionOptions {
+                        quiet: true,
+                        ..options.clone()
+                    };
+                    if let Some(output_dir) = output_dir {
+                        let relative_dir = file
+                            .path
+                            .parent()
+                            .and_then(|parent| parent.strip_prefix(dir).ok())
+                            .unwrap_or(Path::new(""));
+                        options.output_dir = Some(output_dir.join(relative_dir));
+                    }
+                    let result =
+                        convert_file(&file.path, &file.format, inkscape, output_format, &options)
+                            .map_err(|e| e.to_string());
+                    results.lock().unwrap().push((file.path.clone(), result));
+                    if let Some(progress) = &progress {
+                        progress.lock().unwrap().inc();
+                    }
+                }
+            });
+        }
+    });
+
+    if let Some(progress) = progress {
+        progress.into_inner().unwrap().finish();
+    }
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by(|a, b| a.0.cmp(&b.0));
+    for (path, result) in results {
+        match result {
+            Ok(output) => report.converted.push((path, output)),
+            Err(e) => report.failed.push((path, e)),
+        }
+    }
+    Ok(report)
+}
+
+fn modified_since(path: &Path, since: SystemTime) -> bool {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .is_ok_and(|modified| modified >= since)
+}
+
+fn get_runs_path() -> PathBuf {
+    AppDir::Data.file(BATCH_RUNS_FILE)
+}
+
+/// Returns when the last batch run of `dir` that converted every file
+/// started, for `batch --since last`.
+pub fn last_run(dir: &Path) -> Option<SystemTime> {
+    last_run_from(&get_runs_path(), dir)
+}
+
+/// Records that a batch run of `dir` that started at `start` converted every
+/// file.
+pub fn record_run(dir: &Path, start: SystemTime) -> Result<()> {
+    record_run_to(&get_runs_path(), dir, start)
+}
+
+/// The start times of the last successful run of each directory. Directories
+/// are stored as absolute paths, so that a run from another working
+/// directory finds them.
+fn read_runs_from(path: &Path) -> BTreeMap<PathBuf, SystemTime> {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+fn last_run_from(path: &Path, dir: &Path) -> Option<SystemTime> {
+    let dir = fs::canonicalize(dir).ok()?;
+    read_runs_from(path).get(&dir).copied()
+}
+
+fn record_run_to(path: &Path, dir: &Path, start: SystemTime) -> Result<()> {
+    let mut runs = read_runs_from(path);
+    runs.insert(fs::canonicalize(dir)?, start);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, serde_json::to_string(&runs)?)?;
+    Ok(())
+}
+
+/// Returns the files under `dir`, recursively, in sorted order.
+pub(super) fn find_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_find_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("flowers")).unwrap();
+        fs::write(dir.path().join("a.pes"), "").unwrap();
+        fs::write(dir.path().join("flowers").join("b.pes"), "").unwrap();
+
+        let files = find_files(dir.path()).unwrap();
+        assert_eq!(
+            files,
+            vec![
+                dir.path().join("a.pes"),
+                dir.path().join("flowers").join("b.pes")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_record_run() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let path = data_dir.path().join("batch-runs.json");
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(last_run_from(&path, dir.path()), None);
+
+        let start = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_709_210_096);
+        record_run_to(&path, dir.path(), start).unwrap();
+        assert_eq!(last_run_from(&path, dir.path()), Some(start));
+        let other = tempfile::tempdir().unwrap();
+        assert_eq!(last_run_from(&path, other.path()), None);
+    }
+}
@@ -0,0 +1,60 @@
+use std::io;
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+use crate::services::inkscape::{INKSCAPE_DOWNLOAD_URL, INKSTITCH_INSTALL_URL};
+
+/// The ways that converting a design, or copying it to a USB drive, can fail.
+#[derive(Debug, Error)]
+pub enum ConvertError {
+    /// Inkscape is installed, but the ink/stitch extension isn't, or doesn't
+    /// work
+    #[error("ink/stitch extension not installed or not working properly. Please download and install from {}", INKSTITCH_INSTALL_URL)]
+    InkstitchMissing,
+    /// Inkscape and ink/stitch can't read files in this format
+    #[error("Can't convert {}: ink/stitch can't read {format} files", path.display())]
+    UnsupportedInput { path: PathBuf, format: String },
+    /// Inkscape and ink/stitch can't write files in this format
+    #[error("Can't convert to {0}: ink/stitch can't write this format")]
+    UnsupportedOutput(String),
+    /// Inkscape is older than 1.0, whose command line ink/stitch requires
+    #[error(
+        "Inkscape {0} is too old to convert designs. Please install Inkscape 1.0 or later from {}",
+        INKSCAPE_DOWNLOAD_URL
+    )]
+    InkscapeTooOld(String),
+    /// Inkscape exited with an error, which is Inkscape's own message
+    #[error("Inkscape conversion failed: {0}")]
+    InkscapeFailed(String),
+    /// Inkscape exited successfully without writing the output file
+    #[error("Inkscape reported success, but {} was not created", .0.display())]
+    MissingOutput(PathBuf),
+    /// The conversion was stopped before it finished
+    #[error("Conversion cancelled")]
+    Cancelled,
+    /// The design has more stitches than the machine can handle, so it
+    /// wasn't copied
+    #[error("{} has {count} stitches, more than the maximum of {max}. It was not copied to the USB drive", path.display())]
+    TooManyStitches { path: PathBuf, count: u32, max: u32 },
+    /// A zip archive couldn't be extracted. The message is the extraction
+    /// tool's.
+    #[error("Failed to extract {}: {message}", path.display())]
+    ArchiveFailed { path: PathBuf, message: String },
+    /// A zip archive has no files in an embroidery format
+    #[error("{} doesn't contain an embroidery design", .0.display())]
+    NoDesignInArchive(PathBuf),
+    /// Another program kept the file open for too long
+    #[error("{} is still in use by another program", .0.display())]
+    FileLocked(PathBuf),
+    /// The file couldn't be copied to the USB drive. The hint, if any,
+    /// suggests a fix, such as freeing space on the drive.
+    #[error("Failed to copy {}: {source}{}", path.display(), hint.map(|hint| format!(". {}", hint)).unwrap_or_default())]
+    CopyFailed {
+        path: PathBuf,
+        source: io::Error,
+        hint: Option<&'static str>,
+    },
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
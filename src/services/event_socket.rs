@@ -0,0 +1,246 @@
+use serde::Serialize;
+
+use std::fmt;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// What happened to a file, as published to `--event-socket` clients and,
+/// with `--stdout-format ndjson`, to stdout. Each event is written as one
+/// line of JSON, e.g.
+/// `{"event":"copied","path":"a.jef","destination":"/Volumes/USB/a.jef"}`.
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum FileEvent {
+    Detected {
+        path: PathBuf,
+    },
+    Converting {
+        path: PathBuf,
+        format: String,
+    },
+    /// The conversion finished, and wrote `output`
+    Done {
+        path: PathBuf,
+        output: PathBuf,
+    },
+    Failed {
+        path: PathBuf,
+        error: String,
+    },
+    Copied {
+        path: PathBuf,
+        destination: PathBuf,
+    },
+}
+
+impl FileEvent {
+    /// A line for a person to read, for events that finish handling a file.
+    /// Failures aren't included, since the watcher reports them itself.
+    pub fn transcript_line(&self) -> Option<String> {
+        match self {
+            FileEvent::Done { path, output } => Some(format!(
+                "Converted {} to {}",
+                path.display(),
+                output.display()
+            )),
+            FileEvent::Copied { path, destination } => Some(format!(
+                "Copied {} to {}",
+                path.display(),
+                destination.display()
+            )),
+            FileEvent::Detected { .. }
+            | FileEvent::Converting { .. }
+            | FileEvent::Failed { .. } => None,
+        }
+    }
+}
+
+/// A destination for events, and whether it's sent JSON or transcript lines
+struct Client {
+    writer: Box<dyn Write + Send>,
+    transcript: bool,
+}
+
+/// Sends events to every client, such as stdout or a connection to a local
+/// socket. Socket clients may connect and disconnect at any time; events are
+/// dropped if there are no clients.
+#[derive(Clone, Default)]
+pub struct EventPublisher {
+    clients: Arc<Mutex<Vec<Client>>>,
+    socket: Option<Arc<SocketPath>>,
+}
+
+/// Removes the socket file when the last publisher is dropped
+#[cfg_attr(not(unix), allow(dead_code))]
+struct SocketPath(PathBuf);
+
+impl Drop for SocketPath {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// A client that doesn't read its events is dropped after this long, rather
+/// than holding up the watcher
+#[cfg(unix)]
+const WRITE_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(100);
+
+impl fmt::Debug for EventPublisher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EventPublisher")
+            .field("socket", &self.socket.as_ref().map(|socket| &socket.0))
+            .finish()
+    }
+}
+
+impl EventPublisher {
+    /// Returns a publisher without clients; see [`add_client`](Self::add_client).
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sends future events to `client`, until a write to it fails.
+    pub fn add_client(&self, client: Box<dyn Write + Send>) {
+        self.clients.lock().unwrap().push(Client {
+            writer: client,
+            transcript: false,
+        });
+    }
+
+    /// Sends future events to `client` as [transcript
+    /// lines](FileEvent::transcript_line) rather than JSON.
+    pub fn add_transcript_client(&self, client: Box<dyn Write + Send>) {
+        self.clients.lock().unwrap().push(Client {
+            writer: client,
+            transcript: true,
+        });
+    }
+
+    /// Listens for clients on a Unix domain socket at `path`, replacing any
+    /// socket left there by an earlier run.
+    #[cfg(unix)]
+    pub fn bind(path: &Path) -> io::Result<Self> {
+        use std::os::unix::fs::FileTypeExt;
+        use std::os::unix::net::UnixListener;
+
+        if std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+            std::fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        let publisher = Self {
+            clients: Arc::new(Mutex::new(Vec::new())),
+            socket: Some(Arc::new(SocketPath(path.to_path_buf()))),
+        };
+        let clients = publisher.clients.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if stream.set_write_timeout(Some(WRITE_TIMEOUT)).is_ok() {
+                    clients.lock().unwrap().push(Client {
+                        writer: Box::new(stream),
+                        transcript: false,
+                    });
+                }
+            }
+        });
+        Ok(publisher)
+    }
+
+    #[cfg(not(unix))]
+    pub fn bind(_path: &Path) -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "event sockets are only supported on macOS and Linux",
+        ))
+    }
+
+    /// Writes `event` to each client, and forgets clients that have gone away.
+    pub fn publish(&self, event: &FileEvent) {
+        let json = serde_json::to_string(event).ok().map(|line| line + "\n");
+        let transcript = event.transcript_line().map(|line| line + "\n");
+        self.clients.lock().unwrap().retain_mut(|client| {
+            let line = if client.transcript {
+                &transcript
+            } else {
+                &json
+            };
+            match line {
+                Some(line) => client.writer.write_all(line.as_bytes()).is_ok(),
+                None => true,
+            }
+        });
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::net::UnixStream;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_publish() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.sock");
+        let publisher = EventPublisher::bind(&path).unwrap();
+        // Publishing without clients is harmless
+        publisher.publish(&FileEvent::Detected {
+            path: PathBuf::from("a.dst"),
+        });
+
+        let client = UnixStream::connect(&path).unwrap();
+        let start = Instant::now();
+        while publisher.clients.lock().unwrap().is_empty() {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        publisher.publish(&FileEvent::Copied {
+            path: PathBuf::from("a.jef"),
+            destination: PathBuf::from("/usb/a.jef"),
+        });
+
+        let mut line = String::new();
+        BufReader::new(client).read_line(&mut line).unwrap();
+        assert_eq!(
+            line,
+            "{\"event\":\"copied\",\"path\":\"a.jef\",\"destination\":\"/usb/a.jef\"}\n"
+        );
+
+        drop(publisher);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_transcript_client() {
+        struct Buffer(Arc<Mutex<Vec<u8>>>);
+        impl Write for Buffer {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let publisher = EventPublisher::new();
+        let output = Arc::new(Mutex::new(Vec::new()));
+        publisher.add_transcript_client(Box::new(Buffer(output.clone())));
+        publisher.publish(&FileEvent::Detected {
+            path: PathBuf::from("a.svg"),
+        });
+        publisher.publish(&FileEvent::Done {
+            path: PathBuf::from("a.svg"),
+            output: PathBuf::from("a.jef"),
+        });
+        publisher.publish(&FileEvent::Copied {
+            path: PathBuf::from("a.jef"),
+            destination: PathBuf::from("/usb/a.jef"),
+        });
+
+        assert_eq!(
+            String::from_utf8(output.lock().unwrap().clone()).unwrap(),
+            "Converted a.svg to a.jef\nCopied a.jef to /usb/a.jef\n"
+        );
+    }
+}
@@ -1,92 +1,1276 @@
-use std::error::Error;
+use std::collections::BTreeMap;
+use std::fmt;
 use std::io::{self, Write};
 use std::path::{Path, PathBuf};
-use std::time::Instant;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
+use colored::Colorize;
+use regex::Regex;
+
+use crate::print_message;
+use crate::services::archive::{extract_archive, find_design, ARCHIVE_EXTENSION};
+use crate::services::error::ConvertError;
+use crate::services::event_socket::{EventPublisher, FileEvent};
+use crate::services::history::{self, HistoryEntry};
+use crate::services::inkscape::{
+    scale_args, IMAGE_EXPORT_FORMATS, SUPPORTED_WRITE_FORMATS, VECTOR_IMPORT_FORMATS,
+};
+use crate::services::output_manifest::OutputManifest;
+use crate::services::usb_manifest::UsbManifest;
 use crate::services::Inkscape;
 use crate::services::UsbDrive;
-use crate::utils::sanitize_filename;
+use crate::types::FileFormat;
+use crate::utils::messages::message_output;
+use crate::utils::{
+    copy_with_progress, fat_safe_filename, is_sharing_violation, reserve_unique_path,
+    sanitize_filename, SanitizeCase,
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct ConversionOptions {
+    /// Replace existing output files instead of choosing a new name
+    pub overwrite: bool,
+    /// Eject the USB drive after a file has been copied to it
+    pub eject_after: bool,
+    /// Only copy files that are already in an accepted format
+    pub no_convert: bool,
+    /// Write converted files here instead of next to the original
+    pub output_dir: Option<PathBuf>,
+    /// What to do with a source file once it has been copied to a USB drive
+    pub source_action: SourceAction,
+    /// The target machine's name, for the conversion history
+    pub machine: Option<String>,
+    /// Don't print progress for each file. The caller reports errors.
+    pub quiet: bool,
+    /// Set to stop the conversion in progress
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// If set, only files in these of the accepted formats are copied as-is.
+    /// Files in the other accepted formats are converted, if possible.
+    pub copy_formats: Option<Vec<String>>,
+    /// Copy files to the USB drive even if the drive's manifest shows that
+    /// an identical copy is already there
+    pub force_copy: bool,
+    /// When a file is converted, also copy the original to the USB drive
+    pub copy_source_too: bool,
+    /// Where to publish what happens to each file, for `--event-socket`
+    pub events: Option<EventPublisher>,
+    /// Files in subdirectories of these directories are written to the same
+    /// subdirectories of the output directory and the USB drive. If this is
+    /// empty, they are written directly into those directories.
+    pub source_roots: Vec<PathBuf>,
+    /// The subdirectory of the USB folder to copy to, instead of the one
+    /// found from `source_roots`. This is set for the design extracted from
+    /// an archive, which is in a temporary directory.
+    pub usb_subdir: Option<PathBuf>,
+    /// Only USB drives with these names or mount points are copied to. If
+    /// this is empty, any USB drive is used.
+    pub usb_allowlist: Vec<String>,
+    /// Extra Inkscape arguments for conversions to each output format
+    pub presets: BTreeMap<String, Vec<String>>,
+    /// The format of files whose format can't be determined from their
+    /// extension or content, such as a DST file named `design.bin`
+    pub input_format: Option<String>,
+    /// How much to scale designs when converting them, for machines whose
+    /// designs otherwise come out the wrong size
+    pub scale: Option<f64>,
+    /// If set, files whose names don't match this pattern are ignored
+    pub watch_pattern: Option<Regex>,
+    /// If set, `batch` skips files that were last modified before this
+    pub modified_since: Option<SystemTime>,
+    /// Warn before copying a design with more stitches than this, for the
+    /// formats whose stitch count can be read
+    pub max_stitches: Option<u32>,
+    /// Don't copy designs with more than `max_stitches` stitches
+    pub skip_over_max_stitches: bool,
+    /// Process hidden files and partial downloads, which are otherwise
+    /// ignored. See `is_hidden_or_temp`.
+    pub include_hidden: bool,
+    /// Extract zip archives, and process the design inside instead
+    pub extract_archives: bool,
+    /// Convert vector artwork (SVG, PDF and AI files) to stitches. Otherwise
+    /// these are ignored, since those that land in a watched downloads
+    /// folder are rarely meant for the machine.
+    pub import_vectors: bool,
+    /// The case of letters in the names of converted and copied files
+    pub sanitize_case: SanitizeCase,
+}
+
+impl ConversionOptions {
+    /// Whether the file's name matches `watch_pattern`, if there is one.
+    fn matches_watch_pattern(&self, path: &Path) -> bool {
+        self.watch_pattern.as_ref().is_none_or(|pattern| {
+            path.file_name()
+                .is_some_and(|name| pattern.is_match(&name.to_string_lossy()))
+        })
+    }
+
+    /// The format of the file at `path`, as found by `file_format`, unless
+    /// that isn't a design or vector format and `input_format` is set.
+    fn format_of(&self, path: &Path) -> String {
+        let format = file_format(path);
+        let known = FileFormat::find_by_extension(&format).is_some() || is_vector_format(&format);
+        match &self.input_format {
+            Some(input_format) if !known => input_format.clone(),
+            _ => format,
+        }
+    }
+
+    /// The subdirectory of the innermost of `source_roots` that contains
+    /// `path`, or an empty path if none of them contains `path`.
+    fn relative_dir(&self, path: &Path) -> PathBuf {
+        // Watchers may report canonical paths, e.g. /private/var on macOS
+        let canonical = |path: &Path| path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let Some(dir) = path.parent().map(canonical) else {
+            return PathBuf::new();
+        };
+        self.source_roots
+            .iter()
+            .filter_map(|root| dir.strip_prefix(canonical(root)).ok())
+            .min_by_key(|relative| relative.components().count())
+            .map(Path::to_path_buf)
+            .unwrap_or_default()
+    }
+
+    /// Returns true if `path` is one that stitch-sync wrote, such as an
+    /// archived source file or a file in the output directory, so that a
+    /// recursive watch doesn't process it again. Sources archived by earlier
+    /// runs count too, whatever this run does with its sources.
+    pub(super) fn is_own_output(&self, path: &Path) -> bool {
+        let archived = path
+            .parent()
+            .and_then(|dir| dir.file_name())
+            .is_some_and(|name| name == ARCHIVE_DIR);
+        // Event paths may be spelled differently from the configured
+        // directory, e.g. /private/var for /var on macOS
+        let in_output_dir = self.output_dir.as_ref().is_some_and(|dir| {
+            let dir = dir.canonicalize().unwrap_or_else(|_| dir.clone());
+            canonical_path(path).starts_with(dir)
+        });
+        archived || in_output_dir
+    }
+
+    /// Prints a message about the file's progress, unless `quiet` is set.
+    fn status(&self, message: fmt::Arguments) {
+        if !self.quiet {
+            print_message!("{}", message);
+        }
+    }
 
-fn convert_file(
+    fn publish(&self, event: FileEvent) {
+        if let Some(events) = &self.events {
+            events.publish(&event);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum SourceAction {
+    #[default]
+    Keep,
+    /// Move the source file into an `archive` directory next to it
+    Archive,
+    Delete,
+}
+
+const ARCHIVE_DIR: &str = "archive";
+
+/// `path` with symlinks and relative components resolved. A file that no
+/// longer exists is resolved through its directory.
+fn canonical_path(path: &Path) -> PathBuf {
+    path.canonicalize()
+        .ok()
+        .or_else(|| {
+            let dir = path.parent()?.canonicalize().ok()?;
+            Some(dir.join(path.file_name()?))
+        })
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+/// Files at least this large show a progress bar while they're copied to a
+/// USB drive, since a slow drive can take a minute or more to write them.
+const COPY_PROGRESS_MIN_BYTES: u64 = 1_000_000;
+
+/// Copies `source` to `dest` on a USB drive, with a progress bar if the file
+/// is large enough for the copy to take a while.
+fn copy_to_drive(source: &Path, dest: &Path, options: &ConversionOptions) -> io::Result<u64> {
+    let size = std::fs::metadata(source)?.len();
+    if options.quiet || size < COPY_PROGRESS_MIN_BYTES {
+        std::fs::copy(source, dest)
+    } else {
+        copy_with_progress(source, dest)
+    }
+}
+
+/// The subdirectory of the USB folder that `copy_source_too` copies
+/// originals into, so that they don't collide with converted files
+const SOURCE_COPY_DIR: &str = "source";
+
+pub(super) fn convert_file(
     input_path: &Path,
+    input_format: &str,
     inkscape: &Inkscape,
     output_format: &str,
-) -> Result<PathBuf, Box<dyn Error>> {
-    let mut stdout = io::stdout();
-    print!(
-        "Converting {} to {} using Inkscape...",
-        input_path.display(),
-        output_format
-    );
-    stdout.flush()?;
+    options: &ConversionOptions,
+) -> Result<PathBuf, ConvertError> {
+    if !inkscape.supported_read_formats.contains(&input_format) {
+        return Err(ConvertError::UnsupportedInput {
+            path: input_path.to_path_buf(),
+            format: input_format.to_string(),
+        });
+    }
+    if !inkscape.can_write(output_format) {
+        return Err(ConvertError::UnsupportedOutput(output_format.to_string()));
+    }
+    if !options.quiet {
+        let mut output = message_output();
+        write!(
+            output,
+            "Converting {} to {} using Inkscape...",
+            input_path.display(),
+            output_format
+        )?;
+        output.flush()?;
+    }
+    options.publish(FileEvent::Converting {
+        path: input_path.to_path_buf(),
+        format: output_format.to_string(),
+    });
 
-    let output_path = sanitize_filename(input_path).with_extension(output_format);
+    // `design.pes.svg` converts to `design.pes`, not `design.pes.pes`.
+    let sanitized = sanitize_filename(input_path, options.sanitize_case);
+    let stem = sanitized.with_extension("");
+    let mut output_path = if has_extension(&stem, output_format) {
+        stem
+    } else {
+        sanitized.with_extension(output_format)
+    };
+    if let (Some(output_dir), Some(filename)) = (&options.output_dir, output_path.file_name()) {
+        let output_dir = output_dir.join(options.relative_dir(input_path));
+        std::fs::create_dir_all(&output_dir)?;
+        output_path = output_dir.join(filename);
+    }
+    // Inkscape chooses an importer by extension, so give a misnamed file the
+    // extension that matches its content
+    let renamed_input = if has_extension(input_path, input_format) {
+        None
+    } else {
+        let temp = tempfile::Builder::new()
+            .suffix(&format!(".{}", input_format))
+            .tempfile()?;
+        std::fs::copy(input_path, temp.path())?;
+        Some(temp)
+    };
+    let inkscape_input = renamed_input
+        .as_ref()
+        .map_or(input_path, |temp| temp.path());
 
     let start = Instant::now();
-    inkscape.convert_file(input_path, &output_path)?;
+    // ink/stitch exports from SVG, so PDF and AI files are imported into an
+    // SVG first
+    let svg_dir = tempfile::tempdir()?;
+    let inkscape_input = if VECTOR_IMPORT_FORMATS.contains(&input_format) {
+        &inkscape.convert_file(
+            inkscape_input,
+            &svg_dir.path().join("design.svg"),
+            &[],
+            options.quiet,
+            options.cancel.as_deref(),
+        )?
+    } else {
+        inkscape_input
+    };
+    let mut args = options
+        .presets
+        .iter()
+        .find(|(format, _)| format.eq_ignore_ascii_case(output_format))
+        .map_or_else(Vec::new, |(_, args)| args.clone());
+    if let Some(scale) = options.scale.filter(|&scale| scale != 1.0) {
+        args.extend(scale_args(scale));
+    }
+    let reserved_path = if options.overwrite {
+        None
+    } else {
+        output_path = reserve_unique_path(&output_path)?;
+        Some(output_path.clone())
+    };
+    let result = inkscape.convert_file(
+        inkscape_input,
+        &output_path,
+        &args,
+        options.quiet,
+        options.cancel.as_deref(),
+    );
+    // The reserved file is still empty if Inkscape failed or wrote its output
+    // under another name
+    if let Some(reserved) = reserved_path.filter(|reserved| {
+        result.as_ref().map_or(true, |path| path != reserved)
+            && std::fs::metadata(reserved).is_ok_and(|m| m.len() == 0)
+    }) {
+        let _ = std::fs::remove_file(reserved);
+    }
+    let output_path = result?;
     let elapsed = start.elapsed();
 
-    println!("done ({:.2}s elapsed time)", elapsed.as_secs_f32());
+    if !options.quiet {
+        print_message!("done ({:.2}s elapsed time)", elapsed.as_secs_f32());
+        if let Some((from, to)) = renamed_output(input_path, &output_path, output_format) {
+            print_message!("renamed: {} → {}", from.red(), to.green());
+        }
+    }
+    record_output(&output_path);
+    options.publish(FileEvent::Done {
+        path: input_path.to_path_buf(),
+        output: output_path.clone(),
+    });
 
     Ok(output_path)
 }
 
-fn copy_file_to_usb_drives(source_path: &Path, usb_rel_path: &str) -> Result<(), Box<dyn Error>> {
-    let drives = UsbDrive::list();
-    let target_paths = drives
+/// Copies `source_path` into `usb_rel_path`, or into its `subdir`, on each
+/// USB drive that has `usb_rel_path`. Returns the copy on the first drive.
+fn copy_file_to_usb_drives(
+    source_path: &Path,
+    format: &str,
+    usb_rel_path: &str,
+    subdir: &Path,
+    options: &ConversionOptions,
+) -> Result<Option<PathBuf>, ConvertError> {
+    let drives = UsbDrive::list_allowed(&options.usb_allowlist);
+    let targets = drives
         .iter()
-        .map(|drive| drive.mount_point.join(usb_rel_path))
-        .filter(|path| path.exists())
-        .collect::<Vec<PathBuf>>();
+        .map(|drive| (drive, drive.mount_point.join(usb_rel_path)))
+        .filter(|(_, path)| path.exists())
+        .collect::<Vec<(&UsbDrive, PathBuf)>>();
 
-    match (drives.len(), target_paths.len()) {
-        (0, _) => println!("New file detected: {}", source_path.display()),
-        (_, 0) => println!(
+    match (drives.len(), targets.len()) {
+        (0, _) => options.status(format_args!("New file detected: {}", source_path.display())),
+        (_, 0) => options.status(format_args!(
             "New file {} will not be copied. USB drive{} found, but none contains the required target path {}.",
             source_path.display(),
             if drives.len() > 1 { "s" } else { "" },
             usb_rel_path,
-        ),
+        )),
         (_, 1) => (),
-        (_, _) => println!(
-            "Multiple USB drives found; selecting {}...",
-            target_paths.first().unwrap().display()
-        ),
-    }
-    if let Some(target_dir) = target_paths.first() {
-        let filename = source_path
-            .file_name()
-            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid source path"))?;
-        let dest = target_dir.join(sanitize_filename(Path::new(filename)));
-        std::fs::copy(source_path, &dest)?;
-        println!("Copied {} to {}", source_path.display(), dest.display());
+        (_, n) => options.status(format_args!(
+            "{} USB drives contain {}; copying to each...",
+            n,
+            usb_rel_path,
+        )),
     }
-    Ok(())
+    let mut first_dest = None;
+    for (drive, target_dir) in &targets {
+        let dest = copy_file_to_drive(
+            source_path,
+            format,
+            drive,
+            &target_dir.join(subdir),
+            options,
+        )?;
+        first_dest.get_or_insert(dest);
+    }
+    Ok(first_dest)
 }
 
-pub fn handle_file_detection(
+/// Copies `source_path` into `target_dir` on `drive`, unless the drive's
+/// manifest shows that an identical copy is already there.
+fn copy_file_to_drive(
+    source_path: &Path,
+    format: &str,
+    drive: &UsbDrive,
+    target_dir: &Path,
+    options: &ConversionOptions,
+) -> Result<PathBuf, ConvertError> {
+    let copy_failed = |e: io::Error| ConvertError::CopyFailed {
+        path: source_path.to_path_buf(),
+        hint: copy_error_hint(&e, target_dir),
+        source: e,
+    };
+    std::fs::create_dir_all(target_dir).map_err(copy_failed)?;
+    let filename = source_path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid source path"))?;
+    let mut filename = sanitize_filename(Path::new(filename), options.sanitize_case);
+    if !has_extension(&filename, format) {
+        filename.set_extension(format);
+    }
+    let filename = fat_safe_filename(&filename.to_string_lossy());
+    let mut manifest = UsbManifest::load(&drive.mount_point);
+    let mut dest = target_dir.join(filename);
+    let earlier_copy = if options.force_copy {
+        None
+    } else {
+        manifest.find_copy(source_path, &dest).unwrap_or(None)
+    };
+    if let Some(earlier_copy) = earlier_copy {
+        options.status(format_args!(
+            "{} is already on the USB drive as {}",
+            source_path.display(),
+            earlier_copy.display()
+        ));
+        dest = earlier_copy;
+    } else {
+        if !options.overwrite {
+            dest = reserve_unique_path(&dest).map_err(copy_failed)?;
+        }
+        copy_to_drive(source_path, &dest, options).map_err(copy_failed)?;
+        options.status(format_args!(
+            "Copied {} to {}",
+            source_path.display(),
+            dest.display()
+        ));
+        record_output(&dest);
+        record_usb_copy(&mut manifest, source_path, &dest);
+        options.publish(FileEvent::Copied {
+            path: source_path.to_path_buf(),
+            destination: dest.clone(),
+        });
+    }
+    Ok(dest)
+}
+
+/// Ejects the USB drives that `copy_file_to_usb_drives` copies to.
+fn eject_usb_drives(usb_rel_path: &str, options: &ConversionOptions) {
+    for drive in UsbDrive::list_allowed(&options.usb_allowlist)
+        .iter()
+        .filter(|drive| drive.mount_point.join(usb_rel_path).exists())
+    {
+        options.status(format_args!("Ejecting USB drive: {}", drive.name));
+        drive.unmount();
+    }
+}
+
+fn convert_and_copy(
     path: &Path,
-    inkscape: &Option<Inkscape>,
+    format: &str,
+    inkscape: &Inkscape,
     usb_target_path: &Option<&str>,
-    accepted_formats: &[&str],
     preferred_format: &str,
-) -> Result<(), Box<dyn Error>> {
+    options: &ConversionOptions,
+    entry: &mut HistoryEntry,
+) -> Result<PathBuf, ConvertError> {
+    let output_path = convert_file(path, format, inkscape, preferred_format, options)?;
+    entry.output = Some(output_path.clone());
+    // An image is a preview for people, not something a machine can stitch
+    let usb_target_path =
+        usb_target_path.filter(|_| !IMAGE_EXPORT_FORMATS.contains(&preferred_format));
+    if let Some(usb_rel_path) = usb_target_path {
+        copy_and_dispose(
+            path,
+            &output_path,
+            preferred_format,
+            usb_rel_path,
+            options,
+            entry,
+        )?;
+    }
+    Ok(output_path)
+}
+
+/// Copies `file` to the USB drive. Only once it's there is `source` (the
+/// file that `file` came from) archived or deleted, so that a failed
+/// conversion or copy never loses a design. With `eject_after`, the drive is
+/// ejected once everything has been copied. Returns the copy, if there was a
+/// drive to copy it to.
+fn copy_and_dispose(
+    source: &Path,
+    file: &Path,
+    format: &str,
+    usb_rel_path: &str,
+    options: &ConversionOptions,
+    entry: &mut HistoryEntry,
+) -> Result<Option<PathBuf>, ConvertError> {
+    check_stitch_count(file, format, options)?;
+    let subdir = options
+        .usb_subdir
+        .clone()
+        .unwrap_or_else(|| options.relative_dir(source));
+    entry.destination = copy_file_to_usb_drives(file, format, usb_rel_path, &subdir, options)?;
+    if entry.destination.is_none() {
+        return Ok(None);
+    }
+    let source_copied = if options.copy_source_too && file != source {
+        copy_file_to_usb_drives(
+            source,
+            &options.format_of(source),
+            usb_rel_path,
+            &subdir.join(SOURCE_COPY_DIR),
+            options,
+        )
+        .map(|copy| copy.is_some())
+    } else {
+        Ok(true)
+    };
+    if options.eject_after {
+        eject_usb_drives(usb_rel_path, options);
+    }
+    // The original is only disposed of if it was copied too, when it's meant
+    // to be
+    if source_copied? {
+        dispose_of_source(source, options.source_action, options.quiet)?;
+    }
+    Ok(entry.destination.clone())
+}
+
+/// Warns if `file` has more than `max_stitches` stitches, or with
+/// `skip_over_max_stitches`, returns an error so that it isn't copied.
+fn check_stitch_count(
+    file: &Path,
+    format: &str,
+    options: &ConversionOptions,
+) -> Result<(), ConvertError> {
+    let Some(max) = options.max_stitches else {
+        return Ok(());
+    };
+    let Some(count) = FileFormat::read_stitch_count(file, format).filter(|&count| count > max)
+    else {
+        return Ok(());
+    };
+    if options.skip_over_max_stitches {
+        return Err(ConvertError::TooManyStitches {
+            path: file.to_path_buf(),
+            count,
+            max,
+        });
+    }
+    if !options.quiet {
+        print_message!(
+            "{} {} has {} stitches, more than the maximum of {}. Your machine may not be able to stitch it.",
+            "⚠️ ".bright_yellow(),
+            file.display(),
+            count,
+            max
+        );
+    }
+    Ok(())
+}
+
+/// Archives or deletes `path`, according to `action`.
+fn dispose_of_source(path: &Path, action: SourceAction, quiet: bool) -> Result<(), ConvertError> {
+    match action {
+        SourceAction::Keep => (),
+        SourceAction::Archive => {
+            let (Some(dir), Some(filename)) = (path.parent(), path.file_name()) else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Cannot archive {}", path.display()),
+                )
+                .into());
+            };
+            let archive_dir = dir.join(ARCHIVE_DIR);
+            std::fs::create_dir_all(&archive_dir)?;
+            let dest = reserve_unique_path(&archive_dir.join(filename))?;
+            std::fs::rename(path, &dest)?;
+            if !quiet {
+                print_message!("Moved {} to {}", path.display(), dest.display());
+            }
+        }
+        SourceAction::Delete => {
+            std::fs::remove_file(path)?;
+            if !quiet {
+                print_message!("Deleted {}", path.display());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the input and output filenames if the output isn't simply the
+/// input with the output format's extension, e.g. because `sanitize_filename`
+/// lowercased it or `reserve_unique_path` added a number.
+fn renamed_output(
+    input_path: &Path,
+    output_path: &Path,
+    output_format: &str,
+) -> Option<(String, String)> {
+    let stem = Path::new(input_path.file_stem()?);
+    let expected = if has_extension(stem, output_format) {
+        stem.to_path_buf()
+    } else {
+        stem.with_extension(output_format)
+    };
+    let input_name = input_path.file_name()?;
+    let output_name = output_path.file_name()?;
+    (output_name != expected.as_os_str()).then(|| {
+        (
+            input_name.to_string_lossy().into_owned(),
+            output_name.to_string_lossy().into_owned(),
+        )
+    })
+}
+
+/// Extensions of files that browsers and sync clients write while a download
+/// is in progress, and then rename
+const TEMP_FILE_EXTENSIONS: &[&str] = &["crdownload", "part", "tmp"];
+
+/// Whether `path` is a dotfile, an editor's lock or backup file such as
+/// `~$design.svg`, or a partial download such as `design.dst.crdownload`.
+pub(super) fn is_hidden_or_temp(path: &Path) -> bool {
+    let hidden = path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .is_some_and(|name| name.starts_with('.') || name.starts_with('~'));
+    hidden
+        || TEMP_FILE_EXTENSIONS
+            .iter()
+            .any(|extension| has_extension(path, extension))
+}
+
+fn has_extension(path: &Path, extension: &str) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case(extension))
+}
+
+/// The suffix of a sidecar file that names the output format for the file
+/// it sits beside, e.g. `design.svg.format`.
+pub const FORMAT_SIDECAR_EXTENSION: &str = "format";
+
+fn is_output_format(format: &str) -> bool {
+    SUPPORTED_WRITE_FORMATS.contains(&format) || IMAGE_EXPORT_FORMATS.contains(&format)
+}
+
+/// Returns the output format requested for this file specifically, if any.
+/// A sidecar `design.svg.format` file containing e.g. `pes` takes precedence
+/// over a filename such as `design.pes.svg`.
+pub fn format_override(path: &Path) -> Option<String> {
+    let mut sidecar = path.as_os_str().to_owned();
+    sidecar.push(".");
+    sidecar.push(FORMAT_SIDECAR_EXTENSION);
+    if let Ok(content) = std::fs::read_to_string(PathBuf::from(sidecar)) {
+        let format = content.trim().trim_start_matches('.').to_lowercase();
+        if is_output_format(&format) {
+            return Some(format);
+        }
+    }
+    Path::new(path.file_stem()?)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase())
+        .filter(|format| is_output_format(format))
+}
+
+/// Returns the file's format. The extension is used if it names a known
+/// embroidery format; otherwise, for missing or generic extensions such as
+/// `.txt`, the format is identified from the file's content where possible.
+pub fn file_format(path: &Path) -> String {
     let extension = path
         .extension()
         .and_then(|e| e.to_str())
         .map(|s| s.to_lowercase())
         .unwrap_or_default();
+    if FileFormat::find_by_extension(&extension).is_some() {
+        return extension;
+    }
+    FileFormat::detect_from_content(path)
+        .map(ToString::to_string)
+        .unwrap_or(extension)
+}
 
-    if accepted_formats.contains(&extension.as_str()) {
-        if let Some(usb_rel_path) = usb_target_path {
-            copy_file_to_usb_drives(path, usb_rel_path)?;
+pub(super) fn should_convert_file(
+    format: &str,
+    inkscape: Option<&Inkscape>,
+    preferred_format: &str,
+    options: &ConversionOptions,
+) -> bool {
+    !options.no_convert
+        && (options.import_vectors || !is_vector_format(format))
+        && inkscape.is_some_and(|inkscape| {
+            inkscape.supported_read_formats.contains(&format)
+                && inkscape.can_write(preferred_format)
+        })
+}
+
+/// Returns true if `format` is one of the vector formats that are only
+/// converted with `import_vectors`.
+fn is_vector_format(format: &str) -> bool {
+    format == "svg" || VECTOR_IMPORT_FORMATS.contains(&format)
+}
+
+/// Returns true if a file in `format` should be copied without converting
+/// it. Files in the preferred format are always copied, as are files that
+/// can't be converted but are in an accepted format.
+fn should_copy_as_is(
+    format: &str,
+    accepted_formats: &[&str],
+    can_convert: bool,
+    preferred_format: &str,
+    options: &ConversionOptions,
+) -> bool {
+    accepted_formats.contains(&format)
+        && (format == preferred_format
+            || !can_convert
+            || options
+                .copy_formats
+                .as_ref()
+                .is_none_or(|formats| formats.iter().any(|f| f == format)))
+}
+
+/// Records the outcome of handling a file in the history, and publishes it
+/// if it failed.
+fn record_history<T>(
+    mut entry: HistoryEntry,
+    result: &Result<T, ConvertError>,
+    options: &ConversionOptions,
+) {
+    entry.success = result.is_ok();
+    entry.error = result.as_ref().err().map(|e| e.to_string());
+    if let Some(error) = &entry.error {
+        options.publish(FileEvent::Failed {
+            path: entry.source.clone(),
+            error: error.clone(),
+        });
+    }
+    if let Err(e) = history::append(&entry) {
+        eprintln!(
+            "Warning: could not record {} in the conversion history: {}",
+            entry.source.display(),
+            e
+        );
+    }
+}
+
+fn record_output(path: &Path) {
+    if let Err(e) = OutputManifest::record(path) {
+        eprintln!(
+            "Warning: could not record {} in the output manifest: {}",
+            path.display(),
+            e
+        );
+    }
+}
+
+fn record_usb_copy(manifest: &mut UsbManifest, source: &Path, dest: &Path) {
+    if let Err(e) = manifest
+        .add(source, dest)
+        .map_err(anyhow::Error::from)
+        .and_then(|_| manifest.save())
+    {
+        eprintln!(
+            "Warning: could not record {} in the USB drive's manifest: {}",
+            dest.display(),
+            e
+        );
+    }
+}
+
+/// Returns advice for the common ways a copy to a USB drive can fail.
+fn copy_error_hint(error: &io::Error, target_dir: &Path) -> Option<&'static str> {
+    match error.kind() {
+        io::ErrorKind::PermissionDenied | io::ErrorKind::ReadOnlyFilesystem => {
+            Some("The USB drive appears to be write-protected. Check the drive's lock switch, or try another drive.")
+        }
+        io::ErrorKind::StorageFull => {
+            Some("The USB drive is full. Remove some designs from it, then re-save the file.")
         }
-    } else if inkscape.as_ref().map_or(false, |inkscape|
-        inkscape.supported_read_formats
-        .contains(&extension.as_str())
-        && inkscape.supported_write_formats.contains(&preferred_format)
+        io::ErrorKind::NotFound if !target_dir.exists() => {
+            Some("The drive was removed. Re-insert it and re-save the file.")
+        }
+        io::ErrorKind::NotFound => {
+            Some("The source file was moved or deleted before it could be copied.")
+        }
+        _ => None,
+    }
+}
+
+/// How long to wait for another program to release a file before giving up
+const LOCKED_FILE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Waits while another program has the file open without sharing it, such as
+/// a browser that is still finishing a download on Windows. The delay between
+/// attempts doubles, up to a few seconds.
+fn wait_until_released(path: &Path, options: &ConversionOptions) -> Result<(), ConvertError> {
+    let start = Instant::now();
+    let mut delay = Duration::from_millis(250);
+    loop {
+        match std::fs::File::open(path) {
+            Err(e) if is_sharing_violation(&e) => {
+                if start.elapsed() >= LOCKED_FILE_TIMEOUT {
+                    return Err(ConvertError::FileLocked(path.to_path_buf()));
+                }
+                if delay == Duration::from_millis(250) {
+                    options.status(format_args!(
+                        "Waiting for another program to release {}",
+                        path.display()
+                    ));
+                }
+                if options
+                    .cancel
+                    .as_ref()
+                    .is_some_and(|cancel| cancel.load(Ordering::SeqCst))
+                {
+                    return Err(ConvertError::Cancelled);
+                }
+                std::thread::sleep(delay);
+                delay = (delay * 2).min(Duration::from_secs(4));
+            }
+            // Other errors are reported by whatever reads the file next
+            _ => return Ok(()),
+        }
+    }
+}
+
+/// Copies `path` to the USB drive if it's in one of the accepted formats, or
+/// else converts it to `preferred_format` and copies the result.
+///
+/// Returns the path of the converted file, or else of the copy on the USB
+/// drive, so that the caller can ignore the filesystem event for it. Returns
+/// `None` if the file was skipped, or there was no drive to copy it to.
+pub fn handle_file_detection(
+    path: &Path,
+    inkscape: &Option<Inkscape>,
+    usb_target_path: &Option<&str>,
+    accepted_formats: &[&str],
+    preferred_format: &str,
+    options: &ConversionOptions,
+) -> Result<Option<PathBuf>, ConvertError> {
+    if has_extension(path, FORMAT_SIDECAR_EXTENSION)
+        || (!options.include_hidden && is_hidden_or_temp(path))
+        || !options.matches_watch_pattern(path)
+    {
+        return Ok(None);
+    }
+    // A symlink is processed as the file it points to, under its own name
+    match std::fs::metadata(path) {
+        Ok(metadata) if metadata.is_dir() => return Ok(None),
+        Err(_) if path.is_symlink() => {
+            options.status(format_args!(
+                "Skipping {}: it's a symlink to a file that doesn't exist",
+                path.display()
+            ));
+            return Ok(None);
+        }
+        _ => (),
+    }
+    wait_until_released(path, options)?;
+    // Cloud sync tools may create an empty placeholder before the real file
+    // arrives. The watcher sees the file again once its content is written.
+    if std::fs::metadata(path).is_ok_and(|metadata| metadata.len() == 0) {
+        options.status(format_args!(
+            "Skipping {} until it has content",
+            path.display()
+        ));
+        return Ok(None);
+    }
+    if options.extract_archives && has_extension(path, ARCHIVE_EXTENSION) {
+        return handle_archive(
+            path,
+            inkscape,
+            usb_target_path,
+            accepted_formats,
+            preferred_format,
+            options,
+        );
+    }
+    options.publish(FileEvent::Detected {
+        path: path.to_path_buf(),
+    });
+    let format = options.format_of(path);
+    let format_override = format_override(path);
+    let preferred_format = format_override.as_deref().unwrap_or(preferred_format);
+    let can_convert = should_convert_file(&format, inkscape.as_ref(), preferred_format, options);
+
+    if should_copy_as_is(
+        &format,
+        accepted_formats,
+        can_convert,
+        preferred_format,
+        options,
     ) {
-        convert_file(path, inkscape.as_ref().unwrap(), preferred_format)?;
+        if let Some(usb_rel_path) = usb_target_path {
+            let mut entry = HistoryEntry::new(path, &format, options.machine.as_deref());
+            let result = copy_and_dispose(path, path, &format, usb_rel_path, options, &mut entry);
+            record_history(entry, &result, options);
+            return result;
+        }
+    } else if can_convert {
+        let mut entry = HistoryEntry::new(path, preferred_format, options.machine.as_deref());
+        let result = convert_and_copy(
+            path,
+            &format,
+            inkscape.as_ref().unwrap(),
+            usb_target_path,
+            preferred_format,
+            options,
+            &mut entry,
+        );
+        record_history(entry, &result, options);
+        return result.map(Some);
+    }
+    Ok(None)
+}
+
+/// Extracts the zip archive at `path` into a temporary directory, and
+/// processes the design inside as if it had been found next to the archive.
+/// The archive itself is kept, whatever `source_action` says.
+fn handle_archive(
+    path: &Path,
+    inkscape: &Option<Inkscape>,
+    usb_target_path: &Option<&str>,
+    accepted_formats: &[&str],
+    preferred_format: &str,
+    options: &ConversionOptions,
+) -> Result<Option<PathBuf>, ConvertError> {
+    options.status(format_args!("Extracting {}", path.display()));
+    // The directory is removed when this returns
+    let dir = tempfile::tempdir()?;
+    extract_archive(path, dir.path()).map_err(|message| ConvertError::ArchiveFailed {
+        path: path.to_path_buf(),
+        message,
+    })?;
+    let design = find_design(dir.path(), accepted_formats)
+        .ok_or_else(|| ConvertError::NoDesignInArchive(path.to_path_buf()))?;
+
+    // The design isn't under the source roots, so place its output and its
+    // copy where the archive's would go
+    let subdir = options.relative_dir(path);
+    let options = ConversionOptions {
+        // Otherwise the converted design would be written into the temporary
+        // directory, and removed with it
+        output_dir: options
+            .output_dir
+            .as_ref()
+            .map(|dir| dir.join(&subdir))
+            .or_else(|| path.parent().map(Path::to_path_buf)),
+        source_action: SourceAction::Keep,
+        source_roots: Vec::new(),
+        usb_subdir: Some(subdir),
+        watch_pattern: None,
+        ..options.clone()
+    };
+    handle_file_detection(
+        &design,
+        inkscape,
+        usb_target_path,
+        accepted_formats,
+        preferred_format,
+        &options,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_own_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = ConversionOptions::default();
+        assert!(options.is_own_output(&dir.path().join("archive").join("design.svg")));
+        assert!(!options.is_own_output(&dir.path().join("design.svg")));
+
+        // The output directory is recognized however the event spells it
+        let outputs = dir.path().join("outputs");
+        std::fs::create_dir(&outputs).unwrap();
+        let options = ConversionOptions {
+            output_dir: Some(dir.path().join(".").join("outputs")),
+            ..Default::default()
+        };
+        assert!(options.is_own_output(&outputs.join("design.jef")));
+        assert!(options.is_own_output(&outputs.canonicalize().unwrap().join("design.jef")));
+        #[cfg(unix)]
+        {
+            let link = dir.path().join("link");
+            std::os::unix::fs::symlink(&outputs, &link).unwrap();
+            let options = ConversionOptions {
+                output_dir: Some(link),
+                ..Default::default()
+            };
+            assert!(options.is_own_output(&outputs.join("design.jef")));
+        }
+    }
+
+    #[test]
+    fn test_renamed_output() {
+        assert_eq!(
+            renamed_output(
+                Path::new("/designs/MyDesign.svg"),
+                Path::new("/designs/mydesign.jef"),
+                "jef"
+            ),
+            Some(("MyDesign.svg".to_string(), "mydesign.jef".to_string()))
+        );
+        assert_eq!(
+            renamed_output(
+                Path::new("/designs/design.svg"),
+                Path::new("/designs/design.jef"),
+                "jef"
+            ),
+            None
+        );
+        assert_eq!(
+            renamed_output(
+                Path::new("/designs/design.pes.svg"),
+                Path::new("/designs/design.pes"),
+                "pes"
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_relative_dir() {
+        let options = ConversionOptions {
+            source_roots: vec![PathBuf::from("/designs"), PathBuf::from("/designs/hats")],
+            ..Default::default()
+        };
+        assert_eq!(
+            options.relative_dir(Path::new("/designs/shirts/tee.dst")),
+            PathBuf::from("shirts")
+        );
+        assert_eq!(
+            options.relative_dir(Path::new("/designs/hats/winter/cap.dst")),
+            PathBuf::from("winter")
+        );
+        assert_eq!(
+            options.relative_dir(Path::new("/designs/cap.dst")),
+            PathBuf::new()
+        );
+        assert_eq!(
+            options.relative_dir(Path::new("/elsewhere/cap.dst")),
+            PathBuf::new()
+        );
+    }
+
+    #[test]
+    fn test_should_copy_as_is() {
+        let accepted = ["jef", "dst", "exp"];
+        let options = ConversionOptions {
+            copy_formats: Some(vec!["jef".to_string()]),
+            ..Default::default()
+        };
+        assert!(should_copy_as_is("jef", &accepted, true, "jef", &options));
+        assert!(!should_copy_as_is("dst", &accepted, true, "jef", &options));
+        // A file that can't be converted is still copied
+        assert!(should_copy_as_is("exp", &accepted, false, "jef", &options));
+        assert!(!should_copy_as_is("pes", &accepted, false, "jef", &options));
+        // Without a list, every accepted format is copied
+        let options = ConversionOptions::default();
+        assert!(should_copy_as_is("dst", &accepted, true, "jef", &options));
+    }
+
+    #[test]
+    fn test_copy_error_hint() {
+        let dir = tempfile::tempdir().unwrap();
+        let hint = |kind| copy_error_hint(&io::Error::from(kind), dir.path());
+
+        assert!(hint(io::ErrorKind::PermissionDenied)
+            .unwrap()
+            .contains("write-protected"));
+        assert!(hint(io::ErrorKind::ReadOnlyFilesystem)
+            .unwrap()
+            .contains("write-protected"));
+        assert!(hint(io::ErrorKind::StorageFull).unwrap().contains("full"));
+        assert!(hint(io::ErrorKind::NotFound)
+            .unwrap()
+            .contains("source file"));
+        assert!(hint(io::ErrorKind::Interrupted).is_none());
+
+        let missing = dir.path().join("removed");
+        let hint = copy_error_hint(&io::Error::from(io::ErrorKind::NotFound), &missing);
+        assert!(hint.unwrap().contains("drive was removed"));
+    }
+
+    #[test]
+    fn test_file_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, content: &str| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, content).unwrap();
+            path
+        };
+
+        assert_eq!(file_format(&write("design.DST", "LA:design")), "dst");
+        assert_eq!(file_format(&write("design.txt", "LA:design")), "dst");
+        assert_eq!(file_format(&write("design", "#PES0001")), "pes");
+        assert_eq!(file_format(&write("notes.txt", "hello")), "txt");
+        // A known extension wins over the content
+        assert_eq!(file_format(&write("design.jef", "LA:design")), "jef");
+    }
+
+    #[test]
+    fn test_format_of() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, content: &str| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, content).unwrap();
+            path
+        };
+        let options = ConversionOptions {
+            input_format: Some("dst".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(options.format_of(&write("design.bin", "\u{0}")), "dst");
+        assert_eq!(options.format_of(&write("design", "\u{0}")), "dst");
+        assert_eq!(options.format_of(&write("design.pes", "#PES0001")), "pes");
+        assert_eq!(options.format_of(&write("design.pdf", "%PDF")), "pdf");
+        assert_eq!(
+            ConversionOptions::default().format_of(&write("design.bin", "\u{0}")),
+            "bin"
+        );
+    }
+
+    #[test]
+    fn test_format_override() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name);
+        assert_eq!(
+            format_override(&path("design.pes.svg")).as_deref(),
+            Some("pes")
+        );
+        assert_eq!(
+            format_override(&path("design.DST.svg")).as_deref(),
+            Some("dst")
+        );
+        assert_eq!(format_override(&path("design.v2.svg")), None);
+        assert_eq!(format_override(&path("design.svg")), None);
+
+        std::fs::write(path("design.pes.svg.format"), "jef\n").unwrap();
+        assert_eq!(
+            format_override(&path("design.pes.svg")).as_deref(),
+            Some("jef")
+        );
+        std::fs::write(path("other.svg.format"), "nonsense").unwrap();
+        assert_eq!(format_override(&path("other.svg")), None);
+    }
+
+    #[test]
+    fn test_convert_file_rejects_unsupported_formats() {
+        let inkscape = Inkscape {
+            path: PathBuf::from("inkscape"),
+            version: None,
+            has_inkstitch: true,
+            supported_read_formats: &["svg"],
+            supported_write_formats: &["dst"],
+        };
+        let options = ConversionOptions {
+            quiet: true,
+            ..Default::default()
+        };
+        let path = Path::new("design.bin");
+        assert!(matches!(
+            convert_file(path, "bin", &inkscape, "dst", &options),
+            Err(ConvertError::UnsupportedInput { format, .. }) if format == "bin"
+        ));
+        assert!(matches!(
+            convert_file(path, "svg", &inkscape, "xyz", &options),
+            Err(ConvertError::UnsupportedOutput(format)) if format == "xyz"
+        ));
+    }
+
+    #[test]
+    fn test_dispose_of_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("design.dst");
+
+        std::fs::write(&source, "LA:design").unwrap();
+        dispose_of_source(&source, SourceAction::Keep, true).unwrap();
+        assert!(source.exists());
+
+        dispose_of_source(&source, SourceAction::Archive, true).unwrap();
+        assert!(!source.exists());
+        assert!(dir.path().join("archive").join("design.dst").exists());
+
+        // A second file with the same name doesn't replace the first
+        std::fs::write(&source, "LA:design").unwrap();
+        dispose_of_source(&source, SourceAction::Archive, true).unwrap();
+        assert!(dir.path().join("archive").join("design (1).dst").exists());
+
+        std::fs::write(&source, "LA:design").unwrap();
+        dispose_of_source(&source, SourceAction::Delete, true).unwrap();
+        assert!(!source.exists());
+    }
+
+    struct Buffer(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_handle_file_detection_ignores_unmatched_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let events = EventPublisher::new();
+        let published = Arc::new(std::sync::Mutex::new(Vec::new()));
+        events.add_client(Box::new(Buffer(published.clone())));
+        let options = ConversionOptions {
+            quiet: true,
+            events: Some(events),
+            watch_pattern: Some(Regex::new("^JOB-").unwrap()),
+            ..Default::default()
+        };
+        let other = dir.path().join("design.dst");
+        std::fs::write(&other, "LA:design").unwrap();
+        handle_file_detection(&other, &None, &None, &["dst"], "dst", &options).unwrap();
+        assert!(published.lock().unwrap().is_empty());
+
+        let job = dir.path().join("JOB-42.dst");
+        std::fs::write(&job, "LA:design").unwrap();
+        handle_file_detection(&job, &None, &None, &["dst"], "dst", &options).unwrap();
+        assert!(!published.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_check_stitch_count() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("design.dst");
+        std::fs::write(&path, "LA:design         \rST: 300000\r").unwrap();
+        let mut options = ConversionOptions {
+            quiet: true,
+            max_stitches: Some(200_000),
+            ..Default::default()
+        };
+        assert!(check_stitch_count(&path, "dst", &options).is_ok());
+
+        options.skip_over_max_stitches = true;
+        assert!(matches!(
+            check_stitch_count(&path, "dst", &options),
+            Err(ConvertError::TooManyStitches { count: 300_000, .. })
+        ));
+        options.max_stitches = Some(300_000);
+        assert!(check_stitch_count(&path, "dst", &options).is_ok());
+    }
+
+    #[test]
+    fn test_is_hidden_or_temp() {
+        assert!(is_hidden_or_temp(Path::new("/designs/.DS_Store")));
+        assert!(is_hidden_or_temp(Path::new("/designs/~$design.svg")));
+        assert!(is_hidden_or_temp(Path::new(
+            "/designs/design.dst.crdownload"
+        )));
+        assert!(is_hidden_or_temp(Path::new("/designs/design.PART")));
+        assert!(is_hidden_or_temp(Path::new("/designs/design.tmp")));
+        assert!(!is_hidden_or_temp(Path::new("/designs/design.dst")));
+        assert!(!is_hidden_or_temp(Path::new("/.hidden/design.dst")));
+    }
+
+    #[test]
+    fn test_handle_file_detection_skips_empty_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("design.dst");
+        std::fs::write(&source, "").unwrap();
+        let events = EventPublisher::new();
+        let published = Arc::new(std::sync::Mutex::new(Vec::new()));
+        events.add_client(Box::new(Buffer(published.clone())));
+        let options = ConversionOptions {
+            quiet: true,
+            events: Some(events),
+            ..Default::default()
+        };
+        let result =
+            handle_file_detection(&source, &None, &None, &["dst"], "dst", &options).unwrap();
+        assert_eq!(result, None);
+        assert!(published.lock().unwrap().is_empty());
+
+        // Once the file has content, it's handled
+        std::fs::write(&source, "LA:design").unwrap();
+        handle_file_detection(&source, &None, &None, &["dst"], "dst", &options).unwrap();
+        assert!(!published.lock().unwrap().is_empty());
     }
-    Ok(())
 }
@@ -0,0 +1,102 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::utils::app_dirs::{AppDir, HISTORY_FILE};
+
+/// One line of the conversion history: a file that stitch-sync converted
+/// and/or copied to a USB drive, and whether that succeeded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub timestamp: SystemTime,
+    pub source: PathBuf,
+    /// The converted file, if the source was converted
+    pub output: Option<PathBuf>,
+    /// The format of the file that was sent to the machine
+    pub format: String,
+    pub machine: Option<String>,
+    /// Where the file was copied on the USB drive
+    pub destination: Option<PathBuf>,
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+impl HistoryEntry {
+    pub fn new(source: &Path, format: &str, machine: Option<&str>) -> Self {
+        Self {
+            timestamp: SystemTime::now(),
+            source: source.to_path_buf(),
+            output: None,
+            format: format.to_string(),
+            machine: machine.map(ToString::to_string),
+            destination: None,
+            success: false,
+            error: None,
+        }
+    }
+}
+
+fn get_history_path() -> PathBuf {
+    AppDir::Data.file(HISTORY_FILE)
+}
+
+/// Appends `entry` to the history file.
+pub fn append(entry: &HistoryEntry) -> Result<()> {
+    append_to(&get_history_path(), entry)
+}
+
+pub fn append_to(path: &Path, entry: &HistoryEntry) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(entry)?)?;
+    Ok(())
+}
+
+/// Returns the last `limit` entries, oldest first.
+pub fn read_recent(limit: usize) -> Result<Vec<HistoryEntry>> {
+    read_recent_from(&get_history_path(), limit)
+}
+
+/// Like [`read_recent`]. A missing file is an empty history, and lines that
+/// can't be parsed are skipped.
+pub fn read_recent_from(path: &Path, limit: usize) -> Result<Vec<HistoryEntry>> {
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    let content = fs::read_to_string(path)?;
+    let entries = content
+        .lines()
+        .filter_map(|line| serde_json::from_str::<HistoryEntry>(line).ok())
+        .collect::<Vec<_>>();
+    let skip = entries.len().saturating_sub(limit);
+    Ok(entries.into_iter().skip(skip).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append_and_read_recent() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.jsonl");
+        assert!(read_recent_from(&path, 10).unwrap().is_empty());
+
+        for name in ["a.pes", "b.pes", "c.pes"] {
+            let entry = HistoryEntry::new(Path::new(name), "dst", Some("Brother PE800"));
+            append_to(&path, &entry).unwrap();
+        }
+        fs::write(&path, fs::read_to_string(&path).unwrap() + "not json\n").unwrap();
+
+        let entries = read_recent_from(&path, 2).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].source, PathBuf::from("b.pes"));
+        assert_eq!(entries[1].source, PathBuf::from("c.pes"));
+        assert_eq!(entries[1].machine.as_deref(), Some("Brother PE800"));
+    }
+}
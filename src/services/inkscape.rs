@@ -1,14 +1,19 @@
 use lazy_static::lazy_static;
 
-use std::error::Error;
+use std::ffi::OsString;
+use std::fmt;
+use std::sync::atomic::AtomicBool;
+use std::sync::OnceLock;
 use std::{
+    fs,
     path::{Path, PathBuf},
     process::Command,
-    time::Duration,
+    time::{Duration, SystemTime},
 };
 use which::which;
 
-use crate::print_error;
+use crate::print_message;
+use crate::services::error::ConvertError;
 use crate::utils;
 
 pub const INKSCAPE_DOWNLOAD_URL: &str = "https://inkscape.org/en/download/";
@@ -33,26 +38,193 @@ lazy_static! {
 
     /// File formats that Ink/Stitch can read/import
     pub static ref SUPPORTED_READ_FORMATS: Vec<&'static str> = vec![
-        "100", "10o", "bro", "dat", "dsb", "dst", "dsz", "emd", "exp", "exy",
-        "fxy", "gt", "inb", "jef", "jpx", "ksm", "max", "mit", "new", "pcd",
-        "pcm", "pcq", "pcs", "pec", "pes", "phb", "phc", "sew", "shv", "stc",
-        "stx", "tap", "tbf", "txt", "u01", "vp3", "xxx", "zxy"
+        "100", "10o", "ai", "bro", "dat", "dsb", "dst", "dsz", "emd", "exp",
+        "exy", "fxy", "gt", "inb", "jef", "jpx", "ksm", "max", "mit", "new",
+        "pcd", "pcm", "pcq", "pcs", "pdf", "pec", "pes", "phb", "phc", "sew",
+        "shv", "stc", "stx", "svg", "tap", "tbf", "txt", "u01", "vp3", "xxx",
+        "zxy"
     ];
 }
 
+/// Messages in Inkscape's stderr that mean it has no exporter for the output
+/// format, which usually means that ink/stitch isn't installed. These are
+/// only consulted when the export fails, since Inkscape's messages may be
+/// translated or change between versions.
+const MISSING_EXTENSION_MESSAGES: &[&str] = &[
+    "extension not found",
+    "unknown extension",
+    "Could not detect file format",
+];
+
+/// Returns true if exporting to `path` requires ink/stitch. Inkscape can
+/// write SVG files and image previews by itself.
+fn needs_inkstitch(path: &Path) -> bool {
+    let format = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+    format != "svg" && !IMAGE_EXPORT_FORMATS.contains(&format.as_str())
+}
+
+/// Explains a failed export: Inkscape exited with an error, or it exited
+/// successfully without writing `output_path`. ink/stitch is reported as
+/// missing if the export needed it and it wasn't found at startup, or if
+/// Inkscape said it had no exporter for the format; otherwise the failure is
+/// Inkscape's own.
+fn classify_failure(
+    stderr: &str,
+    exited_ok: bool,
+    inkstitch_not_found: bool,
+    output_path: &Path,
+) -> ConvertError {
+    let no_exporter = MISSING_EXTENSION_MESSAGES
+        .iter()
+        .any(|message| stderr.contains(message));
+    if inkstitch_not_found || no_exporter {
+        ConvertError::InkstitchMissing
+    } else if exited_ok {
+        ConvertError::MissingOutput(output_path.to_path_buf())
+    } else {
+        ConvertError::InkscapeFailed(stderr.trim().to_string())
+    }
+}
+
+/// Vector formats that Inkscape can open, but that ink/stitch can't export
+/// from directly. These are converted to SVG first.
+pub const VECTOR_IMPORT_FORMATS: &[&str] = &["ai", "pdf"];
+
+/// Image formats that Inkscape can export a design to, as a preview. These
+/// can't be stitched, so they're never copied to USB drives. JPEG and WebP
+/// export require Inkscape 1.3 or later.
+pub const IMAGE_EXPORT_FORMATS: &[&str] = &["png", "jpg", "webp"];
+
+/// Inkscape arguments that scale the whole design by `scale` before it is
+/// exported, so that ink/stitch stitches it at the scaled size.
+pub fn scale_args(scale: f64) -> Vec<String> {
+    vec![format!(
+        "--actions=select-all:all;transform-scale:{}",
+        scale
+    )]
+}
+
+/// A small filled square, for checking that ink/stitch can convert a design
+const SELF_TEST_SVG: &str = r##"<?xml version="1.0" encoding="UTF-8"?>
+<svg xmlns="http://www.w3.org/2000/svg" width="20mm" height="20mm" viewBox="0 0 20 20">
+  <path d="M 2,2 H 18 V 18 H 2 Z" style="fill:#000000;stroke:none" />
+</svg>
+"##;
+
+/// An Inkscape release, as reported by `inkscape --version`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct InkscapeVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl InkscapeVersion {
+    /// The first version with `--export-filename`, which ink/stitch requires
+    const EXPORT_FILENAME: InkscapeVersion = InkscapeVersion::new(1, 0, 0);
+    /// The first version that infers the export type of an extension's
+    /// format, such as DST, from the export filename
+    const INFERRED_EXPORT_TYPE: InkscapeVersion = InkscapeVersion::new(1, 1, 0);
+
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Parses the output of `inkscape --version`, such as
+    /// "Inkscape 1.2.2 (b0a8486541, 2022-12-01)". A missing patch number, as
+    /// in "Inkscape 1.3 (0e150ed, 2023-07-21)", is 0.
+    pub fn parse(output: &str) -> Option<Self> {
+        let version = output
+            .lines()
+            .find_map(|line| line.trim().strip_prefix("Inkscape "))?
+            .split_whitespace()
+            .next()?;
+        // Ignore suffixes such as the "beta2" in "1.0beta2"
+        let mut parts = version.split('.').map(|part| {
+            let digits = part.len() - part.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            part[..digits].parse::<u32>().ok()
+        });
+        Some(Self::new(
+            parts.next()??,
+            parts.next().unwrap_or(Some(0))?,
+            parts.next().flatten().unwrap_or(0),
+        ))
+    }
+}
+
+impl fmt::Display for InkscapeVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// The Inkscape executable given by --inkscape-path or the `inkscape_path`
+/// setting, which is used instead of searching for one
+static INKSCAPE_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+/// Uses the Inkscape at `path` for the rest of the run, instead of searching
+/// for it. Only the first call has an effect.
+pub fn set_inkscape_path(path: PathBuf) {
+    let _ = INKSCAPE_PATH.set(path);
+}
+
+/// Checks that `path` is a file that can be run, so that a mistyped
+/// Inkscape path is reported before anything is converted.
+pub fn check_inkscape_path(path: &Path) -> Result<(), String> {
+    let metadata = fs::metadata(path).map_err(|_| "it doesn't exist".to_string())?;
+    if !metadata.is_file() {
+        return Err("it isn't a file".to_string());
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if metadata.permissions().mode() & 0o111 == 0 {
+            return Err("it isn't executable".to_string());
+        }
+    }
+    Ok(())
+}
+
 pub struct Inkscape {
     pub path: PathBuf,
+    /// The version that `inkscape --version` reported, if it could be read
+    pub version: Option<InkscapeVersion>,
     pub has_inkstitch: bool,
     pub supported_read_formats: &'static [&'static str],
     pub supported_write_formats: &'static [&'static str],
 }
 
 impl Inkscape {
+    /// Returns true if a design can be exported to `format`, either as an
+    /// embroidery file or as an image preview.
+    pub fn can_write(&self, format: &str) -> bool {
+        self.supported_write_formats.contains(&format) || IMAGE_EXPORT_FORMATS.contains(&format)
+    }
+
+    /// Returns the Inkscape set by `set_inkscape_path`, or else the one found
+    /// on the PATH or in the usual install locations.
     pub fn find_app() -> Option<Inkscape> {
-        Self::find_path().map(|path| {
-            let has_inkstitch = Self::find_inkstitch_extension(&path);
+        let path = INKSCAPE_PATH.get().cloned().or_else(Self::find_path);
+        path.map(|path| {
+            let has_inkstitch = Self::find_inkstitch_extension(&path).is_some();
+            let version = Command::new(&path)
+                .arg("--version")
+                .output()
+                .ok()
+                .and_then(|output| {
+                    InkscapeVersion::parse(&String::from_utf8_lossy(&output.stdout))
+                });
             Inkscape {
                 path,
+                version,
                 has_inkstitch,
                 supported_read_formats: &SUPPORTED_READ_FORMATS,
                 supported_write_formats: &SUPPORTED_WRITE_FORMATS,
@@ -64,50 +236,106 @@ impl Inkscape {
         &self,
         input_path: &Path,
         output_path: &PathBuf,
-    ) -> Result<PathBuf, Box<dyn Error>> {
+        extra_args: &[String],
+        quiet: bool,
+        cancel: Option<&AtomicBool>,
+    ) -> Result<PathBuf, ConvertError> {
+        let start = SystemTime::now();
         let mut child = Command::new(&self.path)
             .arg(input_path)
-            .arg("--export-filename")
-            .arg(output_path)
+            .args(self.export_args(output_path)?)
+            .args(extra_args)
             .stdout(std::process::Stdio::piped())
             .stderr(std::process::Stdio::piped())
             .spawn()?;
 
-        let dot_interval = Duration::from_secs(1);
+        let dot_interval = (!quiet).then_some(Duration::from_secs(1));
         let poll_interval = Duration::from_millis(50);
-        utils::wait_with_progress(&mut child, dot_interval, poll_interval)?;
+        let status = utils::wait_with_progress(&mut child, dot_interval, poll_interval, cancel)?;
+        if status == utils::WaitStatus::Cancelled {
+            // Don't leave a partial file for the watcher or the USB copy
+            let _ = fs::remove_file(output_path);
+            return Err(ConvertError::Cancelled);
+        }
 
         let output = child.wait_with_output()?;
 
-        if !output.stdout.is_empty() {
-            println!(
+        if !quiet && !output.stdout.is_empty() {
+            print_message!(
                 "\nInkscape output: {}",
                 String::from_utf8_lossy(&output.stdout)
             );
         }
-        if !output.stderr.is_empty() {
-            println!(
+        if !quiet && !output.stderr.is_empty() {
+            print_message!(
                 "\nInkscape error: {}",
                 String::from_utf8_lossy(&output.stderr)
             );
         }
 
-        let error = String::from_utf8_lossy(&output.stderr);
-        if error.contains("extension not found")
-            || error.contains("unknown extension")
-            || error.contains("Could not detect file format")
-        {
-            let msg = format!(
-                "ink/stitch extension not installed or not working properly. Please download and install from {}",
-                INKSTITCH_INSTALL_URL
-            );
-            return Err(msg.into());
-        } else if !output.status.success() {
-            print_error!("Error converting file: {}", error);
-            return Err("Inkscape conversion failed".into());
+        // An empty file is the placeholder that reserved the name
+        let created = if fs::metadata(output_path).is_ok_and(|m| m.len() > 0) {
+            Some(output_path.to_path_buf())
+        } else {
+            // Some Inkscape versions alter the export filename
+            find_recent_output(output_path, start)
+        };
+        match created {
+            Some(path) if output.status.success() => Ok(path),
+            _ => Err(classify_failure(
+                &String::from_utf8_lossy(&output.stderr),
+                output.status.success(),
+                needs_inkstitch(output_path) && !self.has_inkstitch,
+                output_path,
+            )),
         }
+    }
+
+    /// The arguments that export to `output_path`, for this version of
+    /// Inkscape. If the version is unknown, those for the current version
+    /// are used.
+    fn export_args(&self, output_path: &Path) -> Result<Vec<OsString>, ConvertError> {
+        let mut args = vec![
+            OsString::from("--export-filename"),
+            output_path.as_os_str().to_os_string(),
+        ];
+        match self.version {
+            Some(version) if version < InkscapeVersion::EXPORT_FILENAME => {
+                return Err(ConvertError::InkscapeTooOld(version.to_string()));
+            }
+            Some(version) if version < InkscapeVersion::INFERRED_EXPORT_TYPE => {
+                if let Some(extension) = output_path.extension() {
+                    let mut export_type = OsString::from("--export-type=");
+                    export_type.push(extension);
+                    args.push(export_type);
+                }
+            }
+            _ => (),
+        }
+        Ok(args)
+    }
 
-        Ok(output_path.to_path_buf())
+    /// Converts a small sample design to DST, to check that Inkscape and
+    /// ink/stitch work together. Returns how long the conversion took.
+    pub fn test_conversion(&self) -> Result<Duration, ConvertError> {
+        let dir = tempfile::tempdir()?;
+        let input_path = dir.path().join("stitch-sync-test.svg");
+        fs::write(&input_path, SELF_TEST_SVG)?;
+        let start = SystemTime::now();
+        let output_path = self.convert_file(
+            &input_path,
+            &dir.path().join("stitch-sync-test.dst"),
+            &[],
+            true,
+            None,
+        )?;
+        if fs::metadata(&output_path)?.len() == 0 {
+            return Err(ConvertError::InkscapeFailed(format!(
+                "{} is empty",
+                output_path.display()
+            )));
+        }
+        Ok(start.elapsed().unwrap_or_default())
     }
 
     fn find_path() -> Option<PathBuf> {
@@ -171,7 +399,16 @@ impl Inkscape {
         None
     }
 
-    fn find_inkstitch_extension(inkscape_path: &Path) -> bool {
+    /// Returns the version that the installed ink/stitch extension records
+    /// in its `VERSION` file, if it can be found.
+    pub fn inkstitch_version(&self) -> Option<String> {
+        let dir = Self::find_inkstitch_extension(&self.path)?;
+        let version = fs::read_to_string(dir.join("VERSION")).ok()?;
+        Some(version.trim().to_string()).filter(|version| !version.is_empty())
+    }
+
+    /// Returns the directory that the ink/stitch extension is installed in.
+    fn find_inkstitch_extension(inkscape_path: &Path) -> Option<PathBuf> {
         #[cfg(target_os = "macos")]
         {
             // Check in user's extensions directory
@@ -185,7 +422,7 @@ impl Inkscape {
                     .join("extensions")
                     .join("inkstitch");
                 if user_ext.exists() {
-                    return true;
+                    return Some(user_ext);
                 }
             }
 
@@ -199,7 +436,7 @@ impl Inkscape {
             });
 
             if let Some(path) = app_ext {
-                return path.exists();
+                return path.exists().then_some(path);
             }
         }
 
@@ -212,26 +449,25 @@ impl Inkscape {
                     .join("extensions")
                     .join("inkstitch");
                 if user_ext.exists() {
-                    return true;
+                    return Some(user_ext);
                 }
             }
 
             // Check in program files
-            let prog_ext = inkscape_path.parent().map(|p| p.parent()).map(|p| {
-                p.expect("Failed to find Inkscape installation path")
-                    .join("share")
+            let prog_ext = inkscape_path.parent().and_then(|p| p.parent()).map(|p| {
+                p.join("share")
                     .join("inkscape")
                     .join("extensions")
                     .join("inkstitch")
             });
 
             if let Some(path) = prog_ext {
-                return path.exists();
+                return path.exists().then_some(path);
             }
         }
 
         #[cfg(target_os = "linux")]
-        fn find_inkstitch_extension(_inkscape_path: &Path) -> bool {
+        {
             // Check in user's home directory
             if let Some(home) = dirs::home_dir() {
                 let user_ext = home
@@ -240,28 +476,211 @@ impl Inkscape {
                     .join("extensions")
                     .join("inkstitch");
                 if user_ext.exists() {
-                    return true;
+                    return Some(user_ext);
                 }
             }
 
+            // Check in the installation that the binary is in, such as
+            // /opt/inkscape for /opt/inkscape/bin/inkscape
+            let prefix_ext = inkscape_path.parent().and_then(|p| p.parent()).map(|p| {
+                p.join("share")
+                    .join("inkscape")
+                    .join("extensions")
+                    .join("inkstitch")
+            });
+            if let Some(path) = prefix_ext.filter(|path| path.exists()) {
+                return Some(path);
+            }
+
             // Check in system-wide installation
             let paths = [
                 "/usr/share/inkscape/extensions/inkstitch",
                 "/usr/local/share/inkscape/extensions/inkstitch",
             ];
 
-            paths.iter().any(|path| Path::new(path).exists())
+            if let Some(path) = paths.iter().map(PathBuf::from).find(|path| path.exists()) {
+                return Some(path);
+            }
         }
 
-        false
+        None
     }
 }
 
+/// Looks for a file with the same extension as `expected`, and a name that
+/// starts with the expected name, that was written to its directory since
+/// `since`. Other new files are left alone, since they may be the outputs of
+/// conversions that are running at the same time.
+fn find_recent_output(expected: &Path, since: SystemTime) -> Option<PathBuf> {
+    let dir = expected.parent()?;
+    let extension = expected.extension()?.to_str()?;
+    let stem = expected.file_stem()?.to_str()?.to_lowercase();
+    // Filesystem timestamps can be slightly coarser than the system clock
+    let since = since - Duration::from_secs(1);
+
+    let mut candidates = fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .and_then(|e| e.to_str())
+                .is_some_and(|e| e.eq_ignore_ascii_case(extension))
+                && path
+                    .file_stem()
+                    .and_then(|s| s.to_str())
+                    .is_some_and(|s| s.to_lowercase().starts_with(&stem))
+        })
+        .filter_map(|path| {
+            let metadata = fs::metadata(&path).ok().filter(|m| m.len() > 0)?;
+            let modified = metadata.modified().ok()?;
+            (modified >= since).then_some((path, modified))
+        })
+        .collect::<Vec<_>>();
+    candidates.sort_by_key(|(_, modified)| *modified);
+    candidates.pop().map(|(path, _)| path)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::types::FILE_FORMATS;
 
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(
+            InkscapeVersion::parse("Inkscape 1.2.2 (b0a8486541, 2022-12-01)\n"),
+            Some(InkscapeVersion::new(1, 2, 2))
+        );
+        assert_eq!(
+            InkscapeVersion::parse("Inkscape 1.3 (0e150ed, 2023-07-21)"),
+            Some(InkscapeVersion::new(1, 3, 0))
+        );
+        assert_eq!(
+            InkscapeVersion::parse("Gtk-Message: ...\nInkscape 1.0beta2 (2b71d25, 2019-12-03)"),
+            Some(InkscapeVersion::new(1, 0, 0))
+        );
+        assert_eq!(InkscapeVersion::parse("command not found"), None);
+    }
+
+    #[test]
+    fn test_export_args() {
+        let inkscape = |version| Inkscape {
+            path: PathBuf::from("inkscape"),
+            version,
+            has_inkstitch: true,
+            supported_read_formats: &SUPPORTED_READ_FORMATS,
+            supported_write_formats: &SUPPORTED_WRITE_FORMATS,
+        };
+        let output = Path::new("design.dst");
+        assert_eq!(
+            inkscape(None).export_args(output).unwrap(),
+            ["--export-filename", "design.dst"]
+        );
+        assert_eq!(
+            inkscape(Some(InkscapeVersion::new(1, 2, 2)))
+                .export_args(output)
+                .unwrap(),
+            ["--export-filename", "design.dst"]
+        );
+        assert_eq!(
+            inkscape(Some(InkscapeVersion::new(1, 0, 2)))
+                .export_args(output)
+                .unwrap(),
+            ["--export-filename", "design.dst", "--export-type=dst"]
+        );
+        assert!(matches!(
+            inkscape(Some(InkscapeVersion::new(0, 92, 4))).export_args(output),
+            Err(ConvertError::InkscapeTooOld(version)) if version == "0.92.4"
+        ));
+    }
+
+    #[test]
+    fn test_classify_failure() {
+        let path = Path::new("design.jef");
+        let classify = |stderr, exited_ok, inkstitch_not_found| {
+            classify_failure(stderr, exited_ok, inkstitch_not_found, path)
+        };
+        assert!(matches!(
+            classify("Segmentation fault", false, false),
+            ConvertError::InkscapeFailed(message) if message == "Segmentation fault"
+        ));
+        assert!(matches!(
+            classify("", true, false),
+            ConvertError::MissingOutput(_)
+        ));
+        assert!(matches!(
+            classify("Segmentation fault", false, true),
+            ConvertError::InkstitchMissing
+        ));
+        assert!(matches!(
+            classify("Erweiterung nicht gefunden", true, true),
+            ConvertError::InkstitchMissing
+        ));
+        assert!(matches!(
+            classify("unknown extension: org.inkstitch.output.jef", false, false),
+            ConvertError::InkstitchMissing
+        ));
+        assert!(needs_inkstitch(path));
+        assert!(!needs_inkstitch(Path::new("design.SVG")));
+        assert!(!needs_inkstitch(Path::new("design.png")));
+    }
+
+    #[test]
+    fn test_find_recent_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let expected = dir.path().join("design.jef");
+        let old = dir.path().join("old.jef");
+        fs::write(&old, "x").unwrap();
+        let since = SystemTime::now();
+        let file = fs::File::options().write(true).open(&old).unwrap();
+        file.set_modified(since - Duration::from_secs(60)).unwrap();
+        assert_eq!(find_recent_output(&expected, since), None);
+
+        // Another conversion's output isn't taken for this one's, nor is the
+        // empty file that reserved the expected name
+        fs::write(dir.path().join("another.jef"), "x").unwrap();
+        fs::write(&expected, "").unwrap();
+        assert_eq!(find_recent_output(&expected, since), None);
+
+        let other = dir.path().join("other.jef");
+        let renamed = dir.path().join("design_1.jef");
+        fs::write(&renamed, "x").unwrap();
+        fs::write(&other, "x").unwrap();
+        fs::write(dir.path().join("design_1.dst"), "x").unwrap();
+        assert_eq!(find_recent_output(&expected, since), Some(renamed));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_find_inkstitch_extension_next_to_binary() {
+        let dir = tempfile::tempdir().unwrap();
+        let inkscape_path = dir.path().join("bin").join("inkscape");
+        let extension = dir.path().join("share/inkscape/extensions/inkstitch");
+        fs::create_dir_all(&extension).unwrap();
+        assert_eq!(
+            Inkscape::find_inkstitch_extension(&inkscape_path),
+            Some(extension)
+        );
+    }
+
+    #[test]
+    fn test_check_inkscape_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("Inkscape.AppImage");
+        assert!(check_inkscape_path(&path).is_err());
+        assert!(check_inkscape_path(dir.path()).is_err());
+
+        fs::write(&path, "").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert!(check_inkscape_path(&path).is_err());
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        assert_eq!(check_inkscape_path(&path), Ok(()));
+    }
+
     #[test]
     #[ignore]
     fn test_formats_are_supported_by_inkstitch() {
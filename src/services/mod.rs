@@ -1,12 +1,28 @@
+mod archive;
+pub mod batch;
 pub mod browser;
+pub mod error;
+pub mod event_socket;
+pub mod history;
 pub mod inkscape;
+pub mod output_manifest;
+pub mod remote_machines;
 pub mod usb_drive;
+pub mod usb_manifest;
 
 mod file_conversion;
 mod watch;
+#[cfg(feature = "async")]
+mod watch_async;
 
 pub use browser::open_browser;
+pub use error::ConvertError;
+pub use file_conversion::{file_format, handle_file_detection, ConversionOptions, SourceAction};
 pub use inkscape::Inkscape;
+pub use output_manifest::OutputManifest;
 pub use usb_drive::find_usb_containing_path;
 pub use usb_drive::UsbDrive;
 pub use watch::watch as watch_dir;
+pub use watch::{contains_design_files, recent_files, WatchOptions, HEARTBEAT_INTERVAL};
+#[cfg(feature = "async")]
+pub use watch_async::{file_events, watch_async, FileEvents};
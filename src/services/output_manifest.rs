@@ -0,0 +1,223 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, SystemTime};
+
+use crate::utils::app_dirs::{AppDir, OUTPUTS_FILE};
+
+/// Held while the manifest is read, changed and saved, since conversions
+/// that run at the same time each record their outputs
+static MANIFEST_LOCK: Mutex<()> = Mutex::new(());
+
+/// Records the files that stitch-sync has written (converted outputs and USB
+/// copies), so that they can later be pruned without touching anything the
+/// user created.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct OutputManifest {
+    #[serde(skip)]
+    path: PathBuf,
+    entries: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ManifestEntry {
+    path: PathBuf,
+    created: SystemTime,
+}
+
+fn get_manifest_path() -> PathBuf {
+    AppDir::Cache.file(OUTPUTS_FILE)
+}
+
+impl OutputManifest {
+    pub fn load() -> Self {
+        Self::load_from(get_manifest_path())
+    }
+
+    /// Reads the manifest at `path`. A missing or unreadable manifest is
+    /// treated as empty, since the worst case is that old outputs are kept.
+    pub fn load_from(path: PathBuf) -> Self {
+        let entries = fs::read_to_string(&path)
+            .ok()
+            .and_then(|content| serde_json::from_str::<OutputManifest>(&content).ok())
+            .map(|manifest| manifest.entries)
+            .unwrap_or_default();
+        Self { path, entries }
+    }
+
+    /// Writes the manifest to a temporary file and moves it into place, so
+    /// that it's never read half-written.
+    pub fn save(&self) -> Result<()> {
+        let dir = match self.path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        fs::create_dir_all(dir)?;
+        let mut file = tempfile::NamedTempFile::new_in(dir)?;
+        file.write_all(serde_json::to_string_pretty(self)?.as_bytes())?;
+        file.persist(&self.path)?;
+        Ok(())
+    }
+
+    pub fn add(&mut self, path: &Path) {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        self.entries.retain(|entry| entry.path != path);
+        self.entries.push(ManifestEntry {
+            path,
+            created: SystemTime::now(),
+        });
+    }
+
+    /// Whether `path` is one of the recorded files.
+    pub fn contains(&self, path: &Path) -> bool {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        self.entries.iter().any(|entry| entry.path == path)
+    }
+
+    /// Adds `path` to the on-disk manifest.
+    pub fn record(path: &Path) -> Result<()> {
+        Self::record_in(get_manifest_path(), path)
+    }
+
+    fn record_in(manifest_path: PathBuf, path: &Path) -> Result<()> {
+        let _guard = MANIFEST_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut manifest = Self::load_from(manifest_path);
+        manifest.add(path);
+        manifest.save()
+    }
+
+    /// Deletes recorded files that were produced more than `max_age` ago and
+    /// haven't been modified since, and returns the paths that were removed.
+    ///
+    /// Entries for files on drives that aren't currently mounted are kept, so
+    /// that they can be pruned the next time the drive is present.
+    pub fn prune_older_than(&mut self, max_age: Duration) -> Vec<PathBuf> {
+        let cutoff = SystemTime::now()
+            .checked_sub(max_age)
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        let mut removed = Vec::new();
+
+        self.entries.retain(|entry| {
+            if !entry.path.exists() {
+                // Keep the entry only while its directory is unavailable
+                return entry.path.parent().is_some_and(|p| !p.exists());
+            }
+            if entry.created > cutoff {
+                return true;
+            }
+            let modified = fs::metadata(&entry.path).and_then(|m| m.modified());
+            if modified.map_or(true, |modified| modified > cutoff) {
+                return true;
+            }
+            match fs::remove_file(&entry.path) {
+                Ok(()) => {
+                    removed.push(entry.path.clone());
+                    false
+                }
+                Err(_) => true,
+            }
+        });
+
+        removed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    fn age_file(path: &Path, age: Duration) {
+        let file = File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::now() - age).unwrap();
+    }
+
+    #[test]
+    fn test_prune_older_than() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manifest = OutputManifest::load_from(dir.path().join("outputs.json"));
+
+        let old = dir.path().join("old.jef");
+        let recent = dir.path().join("recent.jef");
+        let unrecorded = dir.path().join("source.dst");
+        for path in [&old, &recent, &unrecorded] {
+            fs::write(path, "").unwrap();
+            age_file(path, 10 * DAY);
+        }
+        manifest.add(&old);
+        manifest.add(&recent);
+        manifest.entries[0].created -= 10 * DAY;
+        manifest.entries[1].created -= DAY;
+
+        let removed = manifest.prune_older_than(7 * DAY);
+
+        assert_eq!(removed.len(), 1);
+        assert!(!old.exists());
+        assert!(recent.exists());
+        assert!(unrecorded.exists());
+        assert_eq!(manifest.entries.len(), 1);
+    }
+
+    #[test]
+    fn test_prune_keeps_modified_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manifest = OutputManifest::load_from(dir.path().join("outputs.json"));
+
+        let edited = dir.path().join("edited.jef");
+        fs::write(&edited, "").unwrap();
+        manifest.add(&edited);
+        manifest.entries[0].created -= 10 * DAY;
+
+        assert!(manifest.prune_older_than(7 * DAY).is_empty());
+        assert!(edited.exists());
+    }
+
+    #[test]
+    fn test_manifest_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest_path = dir.path().join("outputs.json");
+        let output = dir.path().join("design.jef");
+        fs::write(&output, "").unwrap();
+
+        let mut manifest = OutputManifest::load_from(manifest_path.clone());
+        manifest.add(&output);
+        manifest.save().unwrap();
+
+        let manifest = OutputManifest::load_from(manifest_path);
+        assert_eq!(manifest.entries.len(), 1);
+    }
+
+    #[test]
+    fn test_contains() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("design.jef");
+        fs::write(&output, "x").unwrap();
+        let mut manifest = OutputManifest::load_from(dir.path().join("outputs.json"));
+        assert!(!manifest.contains(&output));
+        manifest.add(&output);
+        assert!(manifest.contains(&output));
+        assert!(manifest.contains(&dir.path().join(".").join("design.jef")));
+        assert!(!manifest.contains(&dir.path().join("design.dst")));
+    }
+
+    #[test]
+    fn test_record_concurrently() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest_path = dir.path().join("outputs.json");
+        std::thread::scope(|scope| {
+            for i in 0..8 {
+                let manifest_path = manifest_path.clone();
+                let output = dir.path().join(format!("design{}.jef", i));
+                scope.spawn(move || OutputManifest::record_in(manifest_path, &output).unwrap());
+            }
+        });
+
+        let manifest = OutputManifest::load_from(manifest_path);
+        assert_eq!(manifest.entries.len(), 8);
+    }
+}
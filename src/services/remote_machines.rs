@@ -0,0 +1,141 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::types::machine::{parse_machines, Machine};
+use crate::utils::app_dirs::{AppDir, MACHINES_CACHE_FILE};
+
+/// How long a downloaded machine list is used before it's fetched again
+const CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60); // 24 hours
+
+/// How long to wait for the machine list, so that an unreachable server
+/// doesn't hold up startup
+const FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Increment this when `MachinesCache` changes, so that caches written by
+/// other versions are ignored rather than misread.
+const CACHE_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct MachinesCache {
+    schema_version: u32,
+    /// The `machines_url` that the list was fetched from
+    url: String,
+    fetched: SystemTime,
+    csv: String,
+}
+
+/// Returns the machines listed in the CSV file at `url`, in the format of the
+/// built-in `machines.csv`. The list is cached for a day. If it can't be
+/// fetched or parsed, an older cached list is used; if there is none, this
+/// returns an error, and the caller should use the built-in list.
+pub fn load_remote_machines(url: &str) -> Result<Vec<Machine>> {
+    let cache_path = get_cache_path();
+    let cache = read_cache_from(&cache_path, url);
+    if let Some(cache) = &cache {
+        if cache.fetched + CACHE_TTL > SystemTime::now() {
+            if let Some(machines) = valid_machines(&cache.csv) {
+                return Ok(machines);
+            }
+        }
+    }
+
+    match fetch_machines(url) {
+        Ok((csv, machines)) => {
+            // A list that can't be cached is still usable
+            let _ = write_cache_to(&cache_path, url, csv);
+            Ok(machines)
+        }
+        Err(e) => cache.and_then(|cache| valid_machines(&cache.csv)).ok_or(e),
+    }
+}
+
+/// Returns the machines in the list last downloaded from `url`, however old
+/// it is, without fetching it.
+pub fn cached_machines(url: &str) -> Option<Vec<Machine>> {
+    read_cache_from(&get_cache_path(), url).and_then(|cache| valid_machines(&cache.csv))
+}
+
+/// Downloads the list at `url`, and checks that it lists at least one machine.
+/// Rows that can't be parsed are reported and skipped.
+fn fetch_machines(url: &str) -> Result<(String, Vec<Machine>)> {
+    let client = reqwest::blocking::Client::builder()
+        .timeout(FETCH_TIMEOUT)
+        .build()?;
+    let csv = client
+        .get(url)
+        .header("User-Agent", "stitch-sync")
+        .send()?
+        .error_for_status()?
+        .text()?;
+    let (machines, errors) = parse_machines(&csv);
+    if machines.is_empty() {
+        anyhow::bail!(
+            "{} is not a valid machine list: {}",
+            url,
+            errors
+                .first()
+                .map_or("it lists no machines", String::as_str)
+        );
+    }
+    for error in errors {
+        eprintln!("Warning: skipped a machine in {}: {}", url, error);
+    }
+    Ok((csv, machines))
+}
+
+/// Returns the machines in a cached list, if it has any. Its bad rows were
+/// reported when it was downloaded.
+fn valid_machines(csv: &str) -> Option<Vec<Machine>> {
+    let (machines, _) = parse_machines(csv);
+    (!machines.is_empty()).then_some(machines)
+}
+
+fn get_cache_path() -> PathBuf {
+    AppDir::Cache.file(MACHINES_CACHE_FILE)
+}
+
+/// Returns the list cached at `path`, if it was fetched from `url`, however
+/// old it is.
+fn read_cache_from(path: &Path, url: &str) -> Option<MachinesCache> {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|content| serde_json::from_str::<MachinesCache>(&content).ok())
+        .filter(|cache| cache.schema_version == CACHE_SCHEMA_VERSION && cache.url == url)
+}
+
+fn write_cache_to(path: &Path, url: &str, csv: String) -> Result<()> {
+    let cache = MachinesCache {
+        schema_version: CACHE_SCHEMA_VERSION,
+        url: url.to_string(),
+        fetched: SystemTime::now(),
+        csv,
+    };
+    fs::create_dir_all(path.parent().unwrap())?;
+    fs::write(path, serde_json::to_string(&cache)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_is_per_url() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("machines-cache.json");
+        let url = "https://example.com/machines.csv";
+        write_cache_to(
+            &path,
+            url,
+            "Machine Name,File Formats\nJanome MC500E,jef\n".to_string(),
+        )
+        .unwrap();
+
+        let cache = read_cache_from(&path, url).unwrap();
+        assert_eq!(valid_machines(&cache.csv).unwrap()[0].name, "Janome MC500E");
+        assert!(read_cache_from(&path, "https://example.com/other.csv").is_none());
+    }
+}
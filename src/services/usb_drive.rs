@@ -1,8 +1,10 @@
-use regex::Regex;
 use std::io;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
+use crate::print_message;
+use crate::utils::{c_locale_command, prompt_input, prompt_yes_no};
+
 #[cfg(target_os = "windows")]
 use windows::{
     core::PCWSTR,
@@ -18,12 +20,33 @@ use windows::{
 #[cfg(target_os = "linux")]
 use libudev::Enumerator;
 
+/// In debug builds, a directory named by this environment variable is
+/// reported as the only USB drive, so that copying can be tested without
+/// hardware.
+#[cfg(debug_assertions)]
+pub const FAKE_USB_ENV: &str = "STITCH_SYNC_FAKE_USB";
+
 pub struct UsbDrive {
     pub mount_point: PathBuf,
     pub name: String,
 }
 
 impl UsbDrive {
+    #[cfg(debug_assertions)]
+    fn fake_drive() -> Option<UsbDrive> {
+        let mount_point = PathBuf::from(std::env::var_os(FAKE_USB_ENV)?);
+        let name = mount_point
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "FAKE USB".to_string());
+        Some(UsbDrive { mount_point, name })
+    }
+
+    #[cfg(debug_assertions)]
+    fn is_fake(&self) -> bool {
+        std::env::var_os(FAKE_USB_ENV).is_some_and(|path| self.mount_point == Path::new(&path))
+    }
+
     #[cfg(target_os = "windows")]
     fn is_usb_drive(path: &Path) -> bool {
         use std::ffi::OsStr;
@@ -66,7 +89,7 @@ impl UsbDrive {
                 if let Some(devnode) = device.devnode() {
                     if devnode == device_path {
                         if let Some(parent) = device.parent() {
-                            return parent.subsystem().map_or(false, |s| s == "usb");
+                            return parent.subsystem().is_some_and(|s| s == "usb");
                         }
                     }
                 }
@@ -77,24 +100,77 @@ impl UsbDrive {
 
     #[cfg(target_os = "macos")]
     fn is_usb_drive(path: &Path) -> bool {
-        use std::process::Command;
-
-        // Get the device identifier for the given path
-        let output = match Command::new("diskutil").arg("info").arg(path).output() {
+        // Get the device information for the given path
+        let output = match c_locale_command("diskutil").arg("info").arg(path).output() {
             Ok(output) => output,
             Err(_) => return false,
         };
 
-        let info = String::from_utf8_lossy(&output.stdout);
+        parse_diskutil_is_usb(&String::from_utf8_lossy(&output.stdout))
+    }
+
+    /// The type of the drive's filesystem, such as `vfat` or `FAT32`
+    pub fn filesystem_type(&self) -> Option<String> {
+        crate::utils::filesystem_type(&self.mount_point)
+    }
+
+    /// The number of bytes free on the drive
+    #[cfg(not(target_os = "windows"))]
+    pub fn free_space(&self) -> Option<u64> {
+        let output = c_locale_command("df")
+            .arg("-Pk")
+            .arg(&self.mount_point)
+            .output()
+            .ok()?;
+        parse_df_available(&String::from_utf8_lossy(&output.stdout))
+    }
 
-        let removable_re = Regex::new(r"^\s*Removable Media:\s+(Yes|Removable)\s*$").unwrap();
-        let protocol_re = Regex::new(r"^\s*Protocol:\s+USB\s*$").unwrap();
+    #[cfg(target_os = "windows")]
+    pub fn free_space(&self) -> Option<u64> {
+        use std::ffi::OsStr;
+        use std::os::windows::ffi::OsStrExt;
+        use windows::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
 
-        info.lines().any(|line| removable_re.is_match(line))
-            && info.lines().any(|line| protocol_re.is_match(line))
+        let mut wide: Vec<u16> = OsStr::new(&self.mount_point).encode_wide().collect();
+        wide.push(0);
+        let mut available = 0u64;
+        let ok = unsafe {
+            GetDiskFreeSpaceExW(
+                PCWSTR::from_raw(wide.as_ptr()),
+                Some(&mut available),
+                None,
+                None,
+            )
+        };
+        ok.as_bool().then_some(available)
+    }
+
+    /// Returns true if the drive is named in `allowlist`, or `allowlist` is
+    /// empty. Drives match by name, such as the volume label, or mount
+    /// point, such as `E:`, ignoring case.
+    pub fn is_allowed(&self, allowlist: &[String]) -> bool {
+        let mount_point = self.mount_point.to_string_lossy();
+        allowlist.is_empty()
+            || allowlist.iter().any(|allowed| {
+                allowed.eq_ignore_ascii_case(&self.name)
+                    || allowed.eq_ignore_ascii_case(&mount_point)
+            })
+    }
+
+    /// Lists the drives that [`is_allowed`](Self::is_allowed) accepts.
+    pub fn list_allowed(allowlist: &[String]) -> Vec<UsbDrive> {
+        Self::list()
+            .into_iter()
+            .filter(|drive| drive.is_allowed(allowlist))
+            .collect()
     }
 
     pub fn list() -> Vec<UsbDrive> {
+        #[cfg(debug_assertions)]
+        if let Some(drive) = Self::fake_drive() {
+            return vec![drive];
+        }
+
         #[cfg(target_os = "macos")]
         {
             let volumes = Path::new("/Volumes");
@@ -166,48 +242,54 @@ impl UsbDrive {
     }
 
     pub fn unmount(&self) {
+        #[cfg(debug_assertions)]
+        if self.is_fake() {
+            print_message!("Ejected fake drive: {}", self.name);
+            return;
+        }
+
+        match self.eject() {
+            Ok(()) => print_message!("Successfully ejected drive: {}", self.name),
+            Err(e) => print_message!("Error ejecting drive: {}", e),
+        }
+    }
+
+    /// Unmounts the drive and, where the platform supports it, powers it off,
+    /// so that it can be removed. Returns the reason if it fails.
+    pub fn eject(&self) -> Result<(), String> {
+        #[cfg(debug_assertions)]
+        if self.is_fake() {
+            return Ok(());
+        }
+
         #[cfg(target_os = "macos")]
         {
-            let result = Command::new("diskutil")
+            let output = Command::new("diskutil")
                 .arg("eject")
                 .arg(&self.mount_point)
-                .output();
-
-            match result {
-                Ok(output) if output.status.success() => {
-                    println!("Successfully ejected drive: {}", self.name);
-                }
-                Ok(output) => {
-                    println!(
-                        "Error ejecting drive: {}",
-                        String::from_utf8_lossy(&output.stderr)
-                    );
-                }
-                Err(e) => println!("Error running diskutil: {}", e),
+                .output()
+                .map_err(|e| format!("couldn't run diskutil: {}", e))?;
+            if !output.status.success() {
+                return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
             }
+            Ok(())
         }
 
         #[cfg(target_os = "linux")]
         {
-            let result = Command::new("umount").arg(&self.mount_point).output();
-
-            match result {
-                Ok(output) if output.status.success() => {
-                    let _ = Command::new("udisksctl")
-                        .arg("power-off")
-                        .arg("-b")
-                        .arg(&self.mount_point)
-                        .output();
-                    println!("Successfully ejected drive: {}", self.name);
-                }
-                Ok(output) => {
-                    println!(
-                        "Error ejecting drive: {}",
-                        String::from_utf8_lossy(&output.stderr)
-                    );
-                }
-                Err(e) => println!("Error running umount: {}", e),
+            let output = Command::new("umount")
+                .arg(&self.mount_point)
+                .output()
+                .map_err(|e| format!("couldn't run umount: {}", e))?;
+            if !output.status.success() {
+                return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
             }
+            let _ = Command::new("udisksctl")
+                .arg("power-off")
+                .arg("-b")
+                .arg(&self.mount_point)
+                .output();
+            Ok(())
         }
 
         #[cfg(target_os = "windows")]
@@ -216,97 +298,179 @@ impl UsbDrive {
             use std::os::windows::ffi::OsStrExt;
             use std::ptr;
 
+            // CreateFileW takes the access mask as a plain u32
+            const GENERIC_READ: u32 = 0x8000_0000;
+            const GENERIC_WRITE: u32 = 0x4000_0000;
+
+            let Some(device_path) = volume_device_path(&self.mount_point) else {
+                return Err("it has no drive letter".to_string());
+            };
             unsafe {
-                let device_path = format!(
-                    "\\\\.\\{}:",
-                    self.mount_point
-                        .to_str()
-                        .unwrap_or("")
-                        .chars()
-                        .next()
-                        .unwrap()
-                );
                 let wide_path: Vec<u16> = OsStr::new(&device_path)
                     .encode_wide()
                     .chain(std::iter::once(0))
                     .collect();
 
-                let handle_result = CreateFileW(
+                let handle = CreateFileW(
                     PCWSTR::from_raw(wide_path.as_ptr()),
-                    0x80000000 | 0x40000000, // GENERIC_READ | GENERIC_WRITE
+                    GENERIC_READ | GENERIC_WRITE,
                     FILE_SHARE_READ | FILE_SHARE_WRITE,
                     Some(ptr::null()),
                     OPEN_EXISTING,
                     FILE_FLAG_SEQUENTIAL_SCAN,
                     HANDLE(0),
+                )
+                .map_err(|_| "failed to open the drive".to_string())?;
+                if handle == INVALID_HANDLE_VALUE {
+                    return Err("failed to open the drive".to_string());
+                }
+
+                // Try to eject the media
+                let mut bytes_returned: u32 = 0;
+                let result = DeviceIoControl(
+                    handle,
+                    IOCTL_STORAGE_EJECT_MEDIA,
+                    None,
+                    0,
+                    None,
+                    0,
+                    Some(&mut bytes_returned),
+                    None,
                 );
 
-                match handle_result {
-                    Ok(handle) => {
-                        if handle == INVALID_HANDLE_VALUE {
-                            println!("Error opening drive handle");
-                            return;
-                        }
+                // Close handle before checking result
+                let _ = CloseHandle(handle);
 
-                        // Try to eject the media
-                        let mut bytes_returned: u32 = 0;
-                        let result = DeviceIoControl(
-                            handle,
-                            IOCTL_STORAGE_EJECT_MEDIA,
-                            None,
-                            0,
-                            None,
-                            0,
-                            Some(&mut bytes_returned),
-                            None,
-                        );
-
-                        // Close handle before checking result
-                        let _ = CloseHandle(handle);
-
-                        if result.as_bool() {
-                            println!("Successfully ejected drive: {}", self.name);
-                        } else {
-                            println!("Error ejecting drive");
-                        }
-                    }
-                    Err(_) => {
-                        println!("Failed to open drive handle");
-                    }
+                if !result.as_bool() {
+                    return Err("the drive refused to eject".to_string());
                 }
             }
+            Ok(())
+        }
+
+        #[cfg(not(any(target_os = "macos", target_os = "linux", target_os = "windows")))]
+        Err("ejecting isn't supported on this platform".to_string())
+    }
+}
+
+/// Returns the device path of the volume mounted at `mount_point`, such as
+/// `\\.\E:` for `E:\`, for opening the volume to eject it on Windows.
+#[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+fn volume_device_path(mount_point: &Path) -> Option<String> {
+    let letter = mount_point.to_str()?.chars().next()?;
+    letter
+        .is_ascii_alphabetic()
+        .then(|| format!("\\\\.\\{}:", letter))
+}
+
+/// Converts a machine's USB path, such as `Embf/Designs`, into a path
+/// relative to the drive root, with this platform's separators. Either `/`
+/// or `\` may separate directories. Returns an error if the path is
+/// absolute or uses `..`, since it would then name a directory outside the
+/// drive.
+pub fn normalize_usb_path(path: &str) -> io::Result<String> {
+    let invalid = |reason: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("USB path '{}' {}", path, reason),
+        )
+    };
+    if path.starts_with(['/', '\\']) || path.contains(':') {
+        return Err(invalid("must be relative to the drive root"));
+    }
+    let mut parts = Vec::new();
+    for part in path.split(['/', '\\']) {
+        match part {
+            "" | "." => (),
+            ".." => return Err(invalid("must not contain '..'")),
+            _ => parts.push(part),
         }
     }
+    Ok(parts.join(std::path::MAIN_SEPARATOR_STR))
+}
+
+/// Reads whether the output of `diskutil info` describes removable USB
+/// media. The labels are only in English when `diskutil` runs in the C
+/// locale.
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+fn parse_diskutil_is_usb(info: &str) -> bool {
+    use regex::Regex;
+
+    let removable_re = Regex::new(r"^\s*Removable Media:\s+(Yes|Removable)\s*$").unwrap();
+    let protocol_re = Regex::new(r"^\s*Protocol:\s+USB\s*$").unwrap();
+
+    info.lines().any(|line| removable_re.is_match(line))
+        && info.lines().any(|line| protocol_re.is_match(line))
 }
 
-pub fn find_usb_containing_path(path: &str) -> Option<PathBuf> {
-    UsbDrive::list()
+/// Reads the available space, in bytes, from the output of `df -Pk`:
+/// a header line, then a line whose fourth field is the number of free
+/// kilobytes.
+#[cfg_attr(target_os = "windows", allow(dead_code))]
+fn parse_df_available(output: &str) -> Option<u64> {
+    let line = output.lines().nth(1)?;
+    let kilobytes = line.split_whitespace().nth(3)?.parse::<u64>().ok()?;
+    Some(kilobytes * 1024)
+}
+
+/// Returns `path` on each allowed USB drive that has it as a directory.
+pub fn find_usb_containing_path(path: &str, allowlist: &[String]) -> Vec<PathBuf> {
+    UsbDrive::list_allowed(allowlist)
         .into_iter()
-        .map(|drive| drive.mount_point)
-        .find(|mount_point| mount_point.join(path).is_dir())
-        .map(|mount_point| mount_point.join(path))
+        .map(|drive| drive.mount_point.join(path))
+        .filter(|dir| dir.is_dir())
+        .collect()
+}
+
+/// Checks that files can be written to `dir`, by creating and removing a
+/// hidden file there. This fails for a drive whose write-protect switch is on,
+/// or that is mounted read-only.
+pub fn check_writable(dir: &Path) -> io::Result<()> {
+    tempfile::Builder::new()
+        .prefix(".stitch-sync-")
+        .tempfile_in(dir)?
+        .close()
+}
+
+/// Asks before ejecting `drive`, unless `assume_yes` is set.
+fn confirm_unmount(drive: &UsbDrive, assume_yes: bool) -> bool {
+    assume_yes
+        || prompt_yes_no(
+            &format!(
+                "Eject {} ({})? [y/N] ",
+                drive.name,
+                drive.mount_point.display()
+            ),
+            Some(false),
+        )
 }
 
-pub fn unmount_usb_volume() {
+pub fn unmount_usb_volume(assume_yes: bool) {
     let drives = UsbDrive::list();
 
     match drives.len() {
         0 => {
-            println!("No USB drives found.");
+            print_message!("No USB drives found.");
         }
         1 => {
-            println!("Ejecting USB drive: {}", drives[0].name);
-            drives[0].unmount();
+            if confirm_unmount(&drives[0], assume_yes) {
+                print_message!("Ejecting USB drive: {}", drives[0].name);
+                drives[0].unmount();
+            }
         }
         _ => {
-            println!("Multiple USB drives found. Please choose one (or 'q' to quit):");
+            print_message!("Multiple USB drives found. Please choose one (or 'q' to quit):");
             for (i, drive) in drives.iter().enumerate() {
-                println!("{}. {}", i + 1, drive.name);
+                print_message!(
+                    "{}. {} ({})",
+                    i + 1,
+                    drive.name,
+                    drive.mount_point.display()
+                );
             }
 
-            let mut input = String::new();
-            io::stdin().read_line(&mut input).ok();
-            let input = input.trim();
+            let input = prompt_input("");
+            let input = input.as_str();
 
             if input.eq_ignore_ascii_case("q") {
                 return;
@@ -314,13 +478,109 @@ pub fn unmount_usb_volume() {
 
             if let Ok(choice) = input.parse::<usize>() {
                 if choice > 0 && choice <= drives.len() {
-                    drives[choice - 1].unmount();
+                    let drive = &drives[choice - 1];
+                    if confirm_unmount(drive, assume_yes) {
+                        drive.unmount();
+                    }
                 } else {
-                    println!("Invalid selection.");
+                    print_message!("Invalid selection.");
                 }
             } else {
-                println!("Invalid input.");
+                print_message!("Invalid input.");
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::MAIN_SEPARATOR_STR as SEP;
+
+    #[test]
+    fn test_normalize_usb_path() {
+        assert_eq!(normalize_usb_path("").unwrap(), "");
+        assert_eq!(normalize_usb_path("embf").unwrap(), "embf");
+        assert_eq!(
+            normalize_usb_path("Embf/Designs").unwrap(),
+            format!("Embf{SEP}Designs")
+        );
+        assert_eq!(
+            normalize_usb_path("./Embf\\Designs/").unwrap(),
+            format!("Embf{SEP}Designs")
+        );
+        assert_eq!(
+            Path::new("/media/usb").join(normalize_usb_path("a/b/c").unwrap()),
+            Path::new("/media/usb").join("a").join("b").join("c")
+        );
+
+        assert!(normalize_usb_path("/embf").is_err());
+        assert!(normalize_usb_path("\\embf").is_err());
+        assert!(normalize_usb_path("C:embf").is_err());
+        assert!(normalize_usb_path("embf/../../etc").is_err());
+    }
+
+    #[test]
+    fn test_check_writable() {
+        let dir = tempfile::tempdir().unwrap();
+        check_writable(dir.path()).unwrap();
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+        assert!(check_writable(&dir.path().join("missing")).is_err());
+    }
+
+    #[test]
+    fn test_volume_device_path() {
+        assert_eq!(
+            volume_device_path(Path::new("E:\\")).as_deref(),
+            Some("\\\\.\\E:")
+        );
+        assert_eq!(volume_device_path(Path::new("")), None);
+        assert_eq!(volume_device_path(Path::new("\\\\server\\share")), None);
+    }
+
+    /// Compiles the Windows eject path, and checks that a drive without a
+    /// drive letter is reported rather than panicking
+    #[cfg(target_os = "windows")]
+    #[test]
+    fn test_unmount_without_drive_letter() {
+        let drive = UsbDrive {
+            mount_point: PathBuf::new(),
+            name: "NO LETTER".to_string(),
+        };
+        drive.unmount();
+    }
+
+    #[test]
+    fn test_is_allowed() {
+        let drive = UsbDrive {
+            mount_point: PathBuf::from("/media/me/EMBROIDERY"),
+            name: "EMBROIDERY".to_string(),
+        };
+        assert!(drive.is_allowed(&[]));
+        assert!(drive.is_allowed(&["embroidery".to_string()]));
+        assert!(drive.is_allowed(&["SD".to_string(), "/media/me/EMBROIDERY".to_string()]));
+        assert!(!drive.is_allowed(&["SD".to_string()]));
+    }
+
+    #[test]
+    fn test_parse_df_available() {
+        let output = "Filesystem 1024-blocks Used Available Capacity Mounted on\n\
+                      /dev/sdb1 7812864 1024 7811840 1% /media/me/USB\n";
+        assert_eq!(parse_df_available(output), Some(7811840 * 1024));
+        assert_eq!(parse_df_available(""), None);
+    }
+
+    #[test]
+    fn test_parse_diskutil_is_usb() {
+        let usb = "   Device Identifier:         disk4s1\n\
+                   Protocol:                  USB\n\
+                   Removable Media:           Removable\n";
+        assert!(parse_diskutil_is_usb(usb));
+
+        let internal = "   Device Identifier:         disk1s1\n\
+                        Protocol:                  Apple Fabric\n\
+                        Removable Media:           Fixed\n";
+        assert!(!parse_diskutil_is_usb(internal));
+        assert!(!parse_diskutil_is_usb(""));
+    }
+}
@@ -0,0 +1,189 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const USB_MANIFEST_FILE: &str = ".stitch-sync-manifest.json";
+
+/// Records the files that stitch-sync has copied to a USB drive, and their
+/// contents' hashes, so that an unchanged design isn't copied again when the
+/// drive is re-inserted. The manifest is stored at the root of the drive.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct UsbManifest {
+    #[serde(skip)]
+    root: PathBuf,
+    /// Keyed by path relative to the drive root, with `/` separators
+    files: BTreeMap<String, ManifestEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct ManifestEntry {
+    size: u64,
+    hash: String,
+}
+
+impl UsbManifest {
+    /// Reads the manifest on the drive mounted at `root`. A missing or
+    /// unreadable manifest is treated as empty, so the worst case is a copy
+    /// that wasn't needed.
+    pub fn load(root: &Path) -> Self {
+        let files = fs::read_to_string(root.join(USB_MANIFEST_FILE))
+            .ok()
+            .and_then(|content| serde_json::from_str::<UsbManifest>(&content).ok())
+            .map(|manifest| manifest.files)
+            .unwrap_or_default();
+        Self {
+            root: root.to_path_buf(),
+            files,
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        fs::write(
+            self.root.join(USB_MANIFEST_FILE),
+            serde_json::to_string_pretty(self)?,
+        )?;
+        Ok(())
+    }
+
+    /// Returns a file in the directory of `dest`, a path on the drive, that
+    /// was copied from a file with the same contents as `source`, and is
+    /// still there. `dest` itself is preferred, but an earlier copy may have
+    /// been saved as "design (1).dst" because "design.dst" was taken.
+    pub fn find_copy(&self, source: &Path, dest: &Path) -> io::Result<Option<PathBuf>> {
+        let (Some(dest_key), Some(dir_key)) =
+            (self.key(dest), dest.parent().and_then(|dir| self.key(dir)))
+        else {
+            return Ok(None);
+        };
+        let mut candidates = self
+            .files
+            .iter()
+            .filter(|(key, _)| {
+                let dir = key.rsplit_once('/').map_or("", |(dir, _)| dir);
+                dir == dir_key
+            })
+            .collect::<Vec<_>>();
+        if candidates.is_empty() {
+            return Ok(None);
+        }
+        candidates.sort_by_key(|(key, _)| **key != dest_key);
+        let source_entry = ManifestEntry::for_file(source)?;
+        Ok(candidates
+            .into_iter()
+            .filter(|(_, entry)| **entry == source_entry)
+            .map(|(key, entry)| (self.root.join(key), entry.size))
+            .find(|(path, size)| fs::metadata(path).is_ok_and(|metadata| metadata.len() == *size))
+            .map(|(path, _)| path))
+    }
+
+    /// Records that `dest`, a path on the drive, was copied from `source`.
+    pub fn add(&mut self, source: &Path, dest: &Path) -> io::Result<()> {
+        if let Some(key) = self.key(dest) {
+            self.files.insert(key, ManifestEntry::for_file(source)?);
+        }
+        Ok(())
+    }
+
+    fn key(&self, dest: &Path) -> Option<String> {
+        let relative = dest.strip_prefix(&self.root).ok()?;
+        let parts = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>();
+        Some(parts.join("/"))
+    }
+}
+
+impl ManifestEntry {
+    fn for_file(path: &Path) -> io::Result<Self> {
+        let content = fs::read(path)?;
+        Ok(Self {
+            size: content.len() as u64,
+            hash: format!("{:016x}", fnv1a(&content)),
+        })
+    }
+}
+
+/// The 64-bit FNV-1a hash. Unlike std's `DefaultHasher`, its output is the
+/// same in every release, so manifests stay valid across upgrades.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fnv1a() {
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+    }
+
+    #[test]
+    fn test_contains_copy() {
+        let drive = tempfile::tempdir().unwrap();
+        let source_dir = tempfile::tempdir().unwrap();
+        let source = source_dir.path().join("design.jef");
+        let dest = drive.path().join("EMB").join("design.jef");
+        fs::write(&source, "design").unwrap();
+        fs::create_dir(drive.path().join("EMB")).unwrap();
+        fs::copy(&source, &dest).unwrap();
+
+        let mut manifest = UsbManifest::load(drive.path());
+        assert_eq!(manifest.find_copy(&source, &dest).unwrap(), None);
+        manifest.add(&source, &dest).unwrap();
+        manifest.save().unwrap();
+
+        let manifest = UsbManifest::load(drive.path());
+        assert_eq!(
+            manifest.find_copy(&source, &dest).unwrap(),
+            Some(dest.clone())
+        );
+
+        // The source changed
+        fs::write(&source, "edited").unwrap();
+        assert_eq!(manifest.find_copy(&source, &dest).unwrap(), None);
+
+        // The copy was deleted from the drive
+        fs::write(&source, "design").unwrap();
+        fs::remove_file(&dest).unwrap();
+        assert_eq!(manifest.find_copy(&source, &dest).unwrap(), None);
+    }
+
+    #[test]
+    fn test_find_copy_under_another_name() {
+        let drive = tempfile::tempdir().unwrap();
+        let source_dir = tempfile::tempdir().unwrap();
+        let source = source_dir.path().join("design.jef");
+        let emb = drive.path().join("EMB");
+        fs::write(&source, "design").unwrap();
+        fs::create_dir(&emb).unwrap();
+        // The user's own design.jef was already there, so the copy was
+        // saved as design (1).jef
+        fs::write(emb.join("design.jef"), "theirs").unwrap();
+        let copy = emb.join("design (1).jef");
+        fs::copy(&source, &copy).unwrap();
+
+        let mut manifest = UsbManifest::load(drive.path());
+        manifest.add(&source, &copy).unwrap();
+        assert_eq!(
+            manifest
+                .find_copy(&source, &emb.join("design.jef"))
+                .unwrap(),
+            Some(copy)
+        );
+        // Copies in other directories don't count
+        assert_eq!(
+            manifest
+                .find_copy(&source, &drive.path().join("design.jef"))
+                .unwrap(),
+            None
+        );
+    }
+}
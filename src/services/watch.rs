@@ -3,34 +3,100 @@ use crossterm::{
     terminal::{disable_raw_mode, enable_raw_mode},
 };
 use notify::Event as NotifyEvent;
-use notify::{Config, RecommendedWatcher, RecursiveMode, Watcher};
+use notify::{Config, PollWatcher, RecommendedWatcher, RecursiveMode, Watcher};
 use scopeguard::defer;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io::{self, Write};
 use std::path::Path;
 use std::path::PathBuf;
 use std::sync::mpsc::channel;
 use std::sync::mpsc::Receiver;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::SystemTime;
 use std::{
     sync::atomic::{AtomicBool, Ordering},
-    thread::sleep,
+    thread::{self, sleep},
     time::Duration,
 };
 
+use crate::print_error;
+use crate::print_message;
+use crate::services::error::ConvertError;
+use crate::services::file_conversion::{
+    handle_file_detection, is_hidden_or_temp, ConversionOptions,
+};
+use crate::services::inkscape::Inkscape;
+use crate::services::output_manifest::OutputManifest;
 use crate::services::usb_drive::unmount_usb_volume;
-use crate::services::{ file_conversion::handle_file_detection, inkscape::Inkscape };
-use crate::utils::WATCH_POLL_INTERVAL;
+use crate::types::FileFormat;
+use crate::utils::messages::stdout_reserved;
+use crate::utils::terminal::{self, KeyInputMode};
+use crate::utils::{DIRECTORY_POLL_INTERVAL, WATCH_POLL_INTERVAL};
 
 // Option 1: Scanning folder animation
 const CURSOR_FRAMES: &[&str] = &["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
 const FRAME_DURATION: Duration = Duration::from_millis(200);
 
+/// A pass of the watch loop that takes this much longer than the poll
+/// interval means the computer was asleep. File system events may have been
+/// missed, so the watch directories are re-scanned.
+const RESUME_GAP: Duration = Duration::from_secs(30);
+
+/// The default interval between "Still watching" lines
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Clone)]
+pub struct WatchOptions {
+    pub conversion: ConversionOptions,
+    /// Don't ask for confirmation, e.g. before ejecting a drive
+    pub assume_yes: bool,
+    /// How long to wait for keyboard input in each pass of the watch loop.
+    /// Longer intervals wake the CPU less often, but files and key presses
+    /// take longer to be noticed.
+    pub poll_interval: Duration,
+    /// Scan the directory periodically instead of waiting for file system
+    /// events, which network shares may not deliver
+    pub scan_directory: bool,
+    /// Also watch subdirectories of the watch directory
+    pub recursive: bool,
+    /// With `recursive`, ignore files more than this many directories below
+    /// the watch directory
+    pub max_depth: Option<usize>,
+    /// With `recursive`, also watch directories that are symlinks. Otherwise
+    /// files in them are ignored.
+    pub follow_symlinks: bool,
+    /// Hide the spinner, so that lines printed for each file aren't
+    /// overwritten and the terminal keeps a readable record of the session
+    pub transcript: bool,
+    /// While no files arrive, print a "Still watching" line this often
+    pub heartbeat: Option<Duration>,
+    /// Stop watching once no file has arrived for this long
+    pub idle_timeout: Option<Duration>,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        Self {
+            conversion: ConversionOptions::default(),
+            assume_yes: false,
+            poll_interval: WATCH_POLL_INTERVAL,
+            scan_directory: false,
+            recursive: false,
+            max_depth: None,
+            follow_symlinks: false,
+            transcript: false,
+            heartbeat: Some(HEARTBEAT_INTERVAL),
+            idle_timeout: None,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum WatcherEvent {
     File(notify::Result<NotifyEvent>),
+    /// Files found by re-scanning the watch directories
+    Rescan(Vec<PathBuf>),
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -39,18 +105,18 @@ struct FileMetadata {
     size: u64,
 }
 
-struct FileCache {
+pub(super) struct FileCache {
     cache: HashMap<PathBuf, FileMetadata>,
 }
 
 impl FileCache {
-    fn new() -> Self {
+    pub(super) fn new() -> Self {
         Self {
             cache: HashMap::new(),
         }
     }
 
-    fn filter_new_files<'a>(
+    pub(super) fn filter_new_files<'a>(
         &'a mut self,
         paths: &'a [PathBuf],
     ) -> impl Iterator<Item = &'a PathBuf> {
@@ -73,16 +139,38 @@ impl FileCache {
             }
         })
     }
+
+    /// Forgets every file, so that each is processed again when it's next
+    /// seen.
+    pub(super) fn clear(&mut self) {
+        self.cache.clear();
+    }
+
+    /// Records the current state of a file that stitch-sync wrote itself, so
+    /// that the event for it isn't treated as a new file.
+    pub(super) fn mark_seen(&mut self, path: &Path) {
+        if let Ok(metadata) = std::fs::metadata(path) {
+            self.cache.insert(
+                path.to_path_buf(),
+                FileMetadata {
+                    modified: metadata.modified().unwrap_or(SystemTime::now()),
+                    size: metadata.len(),
+                },
+            );
+        }
+    }
 }
 
 pub fn watch(
-    watch_dir: &PathBuf,
+    watch_dirs: &[PathBuf],
     usb_target_path: &Option<&str>,
     accepted_formats: &[&str],
     preferred_format: &str,
     inkscape: Option<Inkscape>,
+    options: &WatchOptions,
 ) {
-    // Set up signal handlers
+    // Ctrl-C only arrives as a signal while raw mode is off, e.g. during a
+    // conversion. The main loop notices and exits, restoring the terminal.
     let running = Arc::new(AtomicBool::new(true));
     let r = running.clone();
     ctrlc::set_handler(move || {
@@ -90,22 +178,69 @@ pub fn watch(
     })
     .expect("Error setting Ctrl-C handler");
 
-    if !watch_dir.exists() {
-        println!("Directory does not exist: {}", watch_dir.display());
+    if let Some(watch_dir) = watch_dirs.iter().find(|dir| !dir.exists()) {
+        print_message!("Directory does not exist: {}", watch_dir.display());
         return;
     }
 
     let (fs_tx, rx) = channel();
+    // Directories created below the watch directories, and how many levels
+    // below them to watch, when the depth is limited
+    let (dir_tx, dir_rx) = channel::<(PathBuf, usize)>();
 
     // Create watcher with simplified event sending
-    let mut watcher = match RecommendedWatcher::new(
-        move |res| {
-            if let Err(e) = fs_tx.send(WatcherEvent::File(res)) {
-                eprintln!("Error sending event through channel: {:?}", e);
+    let max_depth = options.max_depth.filter(|_| options.recursive);
+    let follow_symlinks = options.follow_symlinks;
+    let skip_symlinks = options.recursive && !follow_symlinks;
+    let roots: Vec<PathBuf> = watch_dirs
+        .iter()
+        .map(|dir| dir.canonicalize().unwrap_or_else(|_| dir.clone()))
+        .collect();
+    let handler = move |res: notify::Result<NotifyEvent>| {
+        let res = res.map(|mut event| {
+            let created = matches!(event.kind, notify::EventKind::Create(_));
+            if let Some(max_depth) = max_depth.filter(|_| created) {
+                for path in &event.paths {
+                    if !path.is_dir() || (skip_symlinks && path.is_symlink()) {
+                        continue;
+                    }
+                    let depth = roots
+                        .iter()
+                        .filter_map(|root| depth_below(root, path))
+                        .min();
+                    if let Some(depth) = depth.filter(|&d| d < max_depth) {
+                        let _ = dir_tx.send((path.clone(), max_depth - depth - 1));
+                    }
+                }
             }
-        },
-        Config::default(),
-    ) {
+            // The watcher descends into symlinked directories, so their files
+            // are filtered out here
+            if skip_symlinks {
+                event.paths.retain(|path| !in_symlinked_dir(&roots, path));
+            }
+            if let Some(max_depth) = max_depth {
+                event.paths.retain(|path| {
+                    roots
+                        .iter()
+                        .filter_map(|root| depth_below(root, path))
+                        .min()
+                        .is_some_and(|d| d <= max_depth)
+                });
+            }
+            event
+        });
+        if let Err(e) = fs_tx.send(WatcherEvent::File(res)) {
+            eprintln!("Error sending event through channel: {:?}", e);
+        }
+    };
+    let watcher: notify::Result<Box<dyn Watcher + Send>> = if options.scan_directory {
+        let config = Config::default().with_poll_interval(DIRECTORY_POLL_INTERVAL);
+        PollWatcher::new(handler, config).map(|w| Box::new(w) as Box<dyn Watcher + Send>)
+    } else {
+        RecommendedWatcher::new(handler, Config::default())
+            .map(|w| Box::new(w) as Box<dyn Watcher + Send>)
+    };
+    let mut watcher = match watcher {
         Ok(w) => w,
         Err(e) => {
             eprintln!("Failed to create watcher: {:?}", e);
@@ -113,49 +248,136 @@ pub fn watch(
         }
     };
 
-    // Set up watching with error handling
-    match watcher.watch(watch_dir, RecursiveMode::NonRecursive) {
-        Ok(_) => (),
-        Err(e) => {
-            eprintln!("Failed to watch directory: {:?}", e);
+    // Set up watching with error handling. A recursive watch would register
+    // every directory in the tree, so with a maximum depth each directory
+    // down to it is watched on its own.
+    let (dirs, mode) = match max_depth {
+        Some(max_depth) => (
+            watched_directories(watch_dirs, max_depth, follow_symlinks),
+            RecursiveMode::NonRecursive,
+        ),
+        None if options.recursive => (watch_dirs.to_vec(), RecursiveMode::Recursive),
+        None => (watch_dirs.to_vec(), RecursiveMode::NonRecursive),
+    };
+    // One watcher can watch several directories
+    for dir in &dirs {
+        if let Err(e) = watcher.watch(dir, mode) {
+            eprintln!("Failed to watch directory {}: {:?}", dir.display(), e);
             return;
         }
-    };
+    }
+    // The watcher can't be changed from its own event handler, so directories
+    // that are created later are registered from another thread. It stops
+    // once the watcher, and with it the sending end of the channel, is gone.
+    let watcher = Arc::new(Mutex::new(watcher));
+    let registrar = Arc::downgrade(&watcher);
+    thread::spawn(move || {
+        for (dir, max_depth) in dir_rx {
+            let Some(watcher) = registrar.upgrade() else {
+                break;
+            };
+            for dir in watched_directories(&[dir], max_depth, follow_symlinks) {
+                let _ = watcher
+                    .lock()
+                    .unwrap()
+                    .watch(&dir, RecursiveMode::NonRecursive);
+            }
+        }
+    });
 
     watch_directory(
-        watch_dir,
+        watch_dirs,
         rx,
         inkscape,
         usb_target_path,
         accepted_formats,
         preferred_format,
+        options,
+        &running,
     );
-    println!("File watcher stopped.");
+    drop(watcher);
+    print_message!("File watcher stopped.");
+}
+
+/// The number of directories between `root` and the file at `path`: 0 for a
+/// file directly in `root`, 1 for one in a subdirectory, and so on.
+fn depth_below(root: &Path, path: &Path) -> Option<usize> {
+    let dir = path.parent()?;
+    let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+    dir.strip_prefix(root)
+        .ok()
+        .map(|relative| relative.components().count())
+}
+
+/// Whether the file at `path` is in a directory, below one of the canonical
+/// `roots`, that is a symlink or is inside one. `path` may spell the root
+/// differently, e.g. /var for /private/var on macOS.
+fn in_symlinked_dir(roots: &[PathBuf], path: &Path) -> bool {
+    let Some(dir) = path.parent() else {
+        return false;
+    };
+    dir.ancestors()
+        .filter(|ancestor| ancestor.canonicalize().is_ok_and(|a| roots.contains(&a)))
+        .any(|root| {
+            dir.strip_prefix(root).is_ok_and(|relative| {
+                let mut dir = root.to_path_buf();
+                relative.components().any(|component| {
+                    dir.push(component);
+                    dir.is_symlink()
+                })
+            })
+        })
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn watch_directory(
-    _path: impl AsRef<Path>,
+    watch_dirs: &[PathBuf],
     event_rx: Receiver<WatcherEvent>,
     inkscape: Option<Inkscape>,
     usb_target_path: &Option<&str>,
     accepted_formats: &[&str],
     preferred_format: &str,
+    options: &WatchOptions,
+    running: &AtomicBool,
 ) {
     let mut file_cache = FileCache::new();
+    // The spinner can't advance faster than the loop polls
+    let frame_duration = FRAME_DURATION.max(options.poll_interval);
     let mut frame_index = 0;
     let mut last_frame = SystemTime::now();
+    let mut last_activity = SystemTime::now();
+    let started = SystemTime::now();
+    let mut last_file = started;
+    // When the loop last started waiting for input, to detect sleep
+    let mut last_poll = started;
+    let mut processed = 0;
+    let mut failed = 0;
+    let mut timed_out = false;
+    // Every file in the watch directories, after 'r' is pressed
+    let mut reprocess = None;
+
+    if !stdout_reserved() && options.transcript {
+        print_message!("👀 Watching for new stitch files...");
+    }
 
-    enable_raw_mode().unwrap();
+    // Raw mode isn't available if there's no terminal, e.g. when a
+    // supervising process reads the NDJSON output
+    let _ = enable_raw_mode();
     defer! {
-        disable_raw_mode().unwrap();
-        // Clear the cursor line when exiting
-        print!("\r\x1B[K");
-        let _ = io::stdout().flush();
+        let _ = disable_raw_mode();
+        if !stdout_reserved() {
+            // Clear the cursor line when exiting
+            print!("\r\x1B[K");
+            let _ = io::stdout().flush();
+        }
     }
 
-    'main: loop {
+    'main: while running.load(Ordering::SeqCst) {
         // Update spinner animation
-        if last_frame.elapsed().unwrap_or_default() >= FRAME_DURATION {
+        if !stdout_reserved()
+            && !options.transcript
+            && last_frame.elapsed().unwrap_or_default() >= frame_duration
+        {
             print!(
                 "\r👀 Watching for new stitch files... {}",
                 CURSOR_FRAMES[frame_index]
@@ -165,71 +387,607 @@ pub fn watch_directory(
             last_frame = SystemTime::now();
         }
 
+        if let Some(heartbeat) = options.heartbeat.filter(|_| !stdout_reserved()) {
+            if last_activity.elapsed().unwrap_or_default() >= heartbeat {
+                let _ = disable_raw_mode();
+                print_message!(
+                    "\r\x1B[KStill watching {} — {} file{} processed this session",
+                    watch_dirs
+                        .iter()
+                        .map(|dir| dir.display().to_string())
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    processed,
+                    if processed == 1 { "" } else { "s" }
+                );
+                let _ = enable_raw_mode();
+                last_activity = SystemTime::now();
+            }
+        }
+
+        if let Some(idle_timeout) = options.idle_timeout {
+            if last_file.elapsed().unwrap_or_default() >= idle_timeout {
+                timed_out = true;
+                break 'main;
+            }
+        }
+
+        let mut rescan = None;
+        let paused = last_poll.elapsed().unwrap_or_default();
+        if paused >= options.poll_interval + RESUME_GAP {
+            let _ = disable_raw_mode();
+            if !stdout_reserved() {
+                print!("\r\x1B[K");
+            }
+            print_message!(
+                "Resumed after {}; re-scanning for files that arrived in the meantime",
+                format_elapsed(paused)
+            );
+            let _ = enable_raw_mode();
+            rescan = Some(WatcherEvent::Rescan(files_modified_since(
+                watch_dirs, last_poll, options,
+            )));
+        }
+        if let Some(event) = reprocess.take() {
+            rescan = Some(event);
+        }
+
         // Check both keyboard and file events in each iteration
-        while let Ok(event) = event_rx.try_recv() {
-            disable_raw_mode().unwrap();
-            // Clear the cursor line before processing file
-            print!("\r\x1B[K");
-            let _ = io::stdout().flush();
+        while let Some(event) = rescan.take().or_else(|| event_rx.try_recv().ok()) {
+            let _ = disable_raw_mode();
+            if !stdout_reserved() {
+                // Clear the cursor line before processing file
+                print!("\r\x1B[K");
+                let _ = io::stdout().flush();
+            }
 
-            match event {
-                WatcherEvent::File(Ok(event)) => {
-                    let paths = match event.kind {
-                        notify::EventKind::Create(_) => event.paths,
-                        notify::EventKind::Modify(_) => {
-                            sleep(Duration::from_millis(150)); // give the file time to settle
-                            event.paths
-                        }
-                        _ => vec![],
+            let paths = match event {
+                WatcherEvent::File(Ok(event)) => match event.kind {
+                    notify::EventKind::Create(_) => event.paths,
+                    notify::EventKind::Modify(_) => {
+                        sleep(Duration::from_millis(150)); // give the file time to settle
+                        event.paths
+                    }
+                    _ => vec![],
+                },
+                WatcherEvent::Rescan(paths) => paths,
+                WatcherEvent::File(Err(e)) => {
+                    print_message!("Error receiving file event: {}", e);
+                    vec![]
+                }
+            };
+            let new_paths = file_cache
+                .filter_new_files(&paths)
+                .filter(|path| !options.conversion.is_own_output(path))
+                .cloned()
+                .collect::<Vec<_>>();
+            for path in &new_paths {
+                if inkscape.is_some() || options.conversion.no_convert {
+                    let cancel = Arc::new(AtomicBool::new(false));
+                    let conversion = ConversionOptions {
+                        cancel: Some(cancel.clone()),
+                        ..options.conversion.clone()
                     };
-
-                    for path in file_cache.filter_new_files(&paths) {
-                        if inkscape.is_some() {
-                            if let Err(e) = handle_file_detection(
+                    let (result, quit) =
+                        with_cancel_key(&cancel, running, options.poll_interval, || {
+                            handle_file_detection(
                                 path,
                                 &inkscape,
                                 usb_target_path,
                                 accepted_formats,
                                 preferred_format,
-                            ) {
-                                eprintln!("Error handling file creation: {}", e);
-                            }
-                        } else {
-                            println!("Warning: File {} cannot be converted without Inkscape and ink/stitch.", path.display());
+                                &conversion,
+                            )
+                        });
+                    last_activity = SystemTime::now();
+                    last_file = last_activity;
+                    match result {
+                        Ok(Some(output_path)) => {
+                            processed += 1;
+                            file_cache.mark_seen(&output_path);
+                        }
+                        // Nothing was converted or copied, e.g. for a cloud
+                        // placeholder that hasn't downloaded yet
+                        Ok(None) => (),
+                        Err(ConvertError::Cancelled) => {
+                            print_message!("\nCancelled conversion of {}", path.display())
+                        }
+                        Err(e) => {
+                            failed += 1;
+                            print_error!("{}", e)
                         }
                     }
+                    if quit {
+                        break 'main;
+                    }
+                } else {
+                    print_message!(
+                        "Warning: File {} cannot be converted without Inkscape and ink/stitch.",
+                        path.display()
+                    );
                 }
-                WatcherEvent::File(Err(e)) => println!("Error receiving file event: {}", e),
             }
-            enable_raw_mode().unwrap();
+            let _ = enable_raw_mode();
         }
 
         // Check for keyboard input
-        if event::poll(WATCH_POLL_INTERVAL).unwrap() {
-            if let Event::Key(key) = event::read().unwrap() {
-                disable_raw_mode().unwrap();
-                match handle_key_event(key) {
-                    Ok(true) => break 'main, // Exit requested
-                    Ok(false) => (),         // Continue watching
-                    Err(e) => {
-                        eprintln!("Error handling key event: {}", e);
-                        break 'main;
+        last_poll = SystemTime::now();
+        match event::poll(options.poll_interval) {
+            Ok(true) => (),
+            Ok(false) => continue,
+            // There's no terminal to read keys from
+            Err(_) => {
+                sleep(options.poll_interval);
+                continue;
+            }
+        }
+        if let Ok(Event::Key(key)) = event::read() {
+            let _ = disable_raw_mode();
+            match handle_key_event(key) {
+                KeyAction::Exit => break 'main,
+                KeyAction::Unmount => unmount_usb_volume(options.assume_yes),
+                KeyAction::Reprocess => {
+                    if !stdout_reserved() {
+                        print!("\r\x1B[K");
+                    }
+                    print_message!("Processing every file in the watch directory again");
+                    file_cache.clear();
+                    reprocess = Some(WatcherEvent::Rescan(files_to_reprocess(
+                        watch_dirs,
+                        &OutputManifest::load(),
+                        options,
+                    )));
+                }
+                // There's no conversion to cancel between files
+                KeyAction::Cancel | KeyAction::None => (),
+            }
+        }
+        let _ = enable_raw_mode();
+        // Waiting for an answer, e.g. to confirm ejecting, isn't sleep
+        last_poll = SystemTime::now();
+    }
+
+    let _ = disable_raw_mode();
+    if !stdout_reserved() {
+        print!("\r\x1B[K");
+    }
+    if let Some(idle_timeout) = options.idle_timeout.filter(|_| timed_out) {
+        print_message!(
+            "No new files for {}; stopping.",
+            format_elapsed(idle_timeout)
+        );
+    }
+    print_message!(
+        "Session summary: {} file{} processed{} in {}",
+        processed,
+        if processed == 1 { "" } else { "s" },
+        if failed > 0 {
+            format!(", {} failed", failed)
+        } else {
+            String::new()
+        },
+        format_elapsed(started.elapsed().unwrap_or_default())
+    );
+}
+
+/// Returns the files in `watch_dirs`, and with `options.recursive` their
+/// subdirectories down to `options.max_depth`, that were modified at or
+/// after `since`.
+fn files_modified_since(
+    watch_dirs: &[PathBuf],
+    since: SystemTime,
+    options: &WatchOptions,
+) -> Vec<PathBuf> {
+    let max_depth = if options.recursive {
+        options.max_depth.unwrap_or(usize::MAX)
+    } else {
+        0
+    };
+    let mut files = Vec::new();
+    for dir in watched_directories(watch_dirs, max_depth, options.follow_symlinks) {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            // This follows symlinks, and skips those whose target is missing
+            let Ok(metadata) = std::fs::metadata(entry.path()) else {
+                continue;
+            };
+            if metadata.is_file() && metadata.modified().is_ok_and(|modified| modified >= since) {
+                files.push(entry.path());
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+/// Returns every file in `watch_dirs`, for 'r', except those that stitch-sync
+/// wrote, such as converted files next to their sources. Those would
+/// otherwise be processed again, and copied to the drive a second time.
+fn files_to_reprocess(
+    watch_dirs: &[PathBuf],
+    manifest: &OutputManifest,
+    options: &WatchOptions,
+) -> Vec<PathBuf> {
+    files_modified_since(watch_dirs, SystemTime::UNIX_EPOCH, options)
+        .into_iter()
+        .filter(|path| !manifest.contains(path))
+        .collect()
+}
+
+/// Returns `watch_dirs` and their subdirectories down to `max_depth` levels
+/// below them. Symlinked subdirectories are only included with
+/// `follow_symlinks`.
+fn watched_directories(
+    watch_dirs: &[PathBuf],
+    max_depth: usize,
+    follow_symlinks: bool,
+) -> Vec<PathBuf> {
+    let mut found = Vec::new();
+    let mut dirs: Vec<(PathBuf, usize)> = watch_dirs.iter().map(|dir| (dir.clone(), 0)).collect();
+    // Symlinks can lead back to a directory that has already been found
+    let mut scanned = HashSet::new();
+    while let Some((dir, depth)) = dirs.pop() {
+        if !dir.canonicalize().is_ok_and(|dir| scanned.insert(dir)) {
+            continue;
+        }
+        if depth < max_depth {
+            if let Ok(entries) = std::fs::read_dir(&dir) {
+                for entry in entries.flatten() {
+                    let path = entry.path();
+                    let is_symlink = entry.file_type().is_ok_and(|t| t.is_symlink());
+                    if path.is_dir() && (follow_symlinks || !is_symlink) {
+                        dirs.push((path, depth + 1));
                     }
                 }
             }
-            enable_raw_mode().unwrap();
         }
+        found.push(dir);
+    }
+    found
+}
+
+/// Returns the `count` most recently modified files directly inside the watch
+/// directories, newest first. Hidden files and partial downloads are left
+/// out, since the watcher ignores them too.
+pub fn recent_files(watch_dirs: &[PathBuf], count: usize) -> Vec<PathBuf> {
+    let mut files = watch_dirs
+        .iter()
+        .filter_map(|dir| std::fs::read_dir(dir).ok())
+        .flat_map(|entries| entries.flatten())
+        .filter(|entry| !is_hidden_or_temp(&entry.path()))
+        .filter_map(|entry| {
+            let metadata = std::fs::metadata(entry.path())
+                .ok()
+                .filter(|m| m.is_file())?;
+            Some((metadata.modified().ok()?, entry.path()))
+        })
+        .collect::<Vec<_>>();
+    files.sort_by(|a, b| b.cmp(a));
+    files
+        .into_iter()
+        .take(count)
+        .map(|(_, path)| path)
+        .collect()
+}
+
+/// Whether `dir` directly contains a file with the extension of a known
+/// design format, such as `.dst` or `.svg`. Hidden files and partial
+/// downloads don't count.
+pub fn contains_design_files(dir: &Path) -> bool {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return false;
+    };
+    entries.flatten().any(|entry| {
+        let path = entry.path();
+        !is_hidden_or_temp(&path)
+            && path.is_file()
+            && path
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .and_then(FileFormat::find_by_extension)
+                .is_some()
+    })
+}
+
+/// Formats a duration in whole minutes, e.g. "5 minutes" or "2h 05m".
+fn format_elapsed(elapsed: Duration) -> String {
+    match elapsed.as_secs() / 60 {
+        0 => "less than a minute".to_string(),
+        1 => "1 minute".to_string(),
+        minutes if minutes < 60 => format!("{} minutes", minutes),
+        minutes => format!("{}h {:02}m", minutes / 60, minutes % 60),
     }
 }
 
-// Returns true if the program should exit
-fn handle_key_event(key: KeyEvent) -> Result<bool, io::Error> {
+#[derive(Debug, PartialEq)]
+enum KeyAction {
+    Exit,
+    Unmount,
+    /// Stop the conversion in progress
+    Cancel,
+    /// Forget which files have been processed, and process every file in
+    /// the watch directories again, e.g. once a full drive has been emptied
+    Reprocess,
+    None,
+}
+
+fn handle_key_event(key: KeyEvent) -> KeyAction {
     match (key.code, key.modifiers.contains(KeyModifiers::CONTROL)) {
-        (KeyCode::Char('q'), _) | (KeyCode::Char('c'), true) => Ok(true),
-        (KeyCode::Char('u'), _) => {
-            unmount_usb_volume();
-            Ok(false)
-        }
-        _ => Ok(false),
+        (KeyCode::Char('q'), _) | (KeyCode::Char('c'), true) => KeyAction::Exit,
+        (KeyCode::Char('u'), _) => KeyAction::Unmount,
+        (KeyCode::Char('x'), _) => KeyAction::Cancel,
+        (KeyCode::Char('r'), _) => KeyAction::Reprocess,
+        _ => KeyAction::None,
+    }
+}
+
+/// Runs `f` while another thread listens for keys that cancel it. Returns
+/// the result of `f`, and whether the user also asked to exit, either with
+/// 'q' or by sending Ctrl-C (which clears `running`).
+///
+/// Keys are read as they're typed while `f` runs. The listener stops while a
+/// prompt reads an answer, so that it doesn't take the answer's keys.
+fn with_cancel_key<T>(
+    cancel: &AtomicBool,
+    running: &AtomicBool,
+    poll_interval: Duration,
+    f: impl FnOnce() -> T,
+) -> (T, bool) {
+    let done = AtomicBool::new(false);
+    let exit = AtomicBool::new(false);
+    let _key_input = KeyInputMode::enable();
+    let result = thread::scope(|scope| {
+        scope.spawn(|| {
+            while !done.load(Ordering::SeqCst) {
+                // A Ctrl-C signal
+                if !running.load(Ordering::SeqCst) {
+                    exit.store(true, Ordering::SeqCst);
+                    cancel.store(true, Ordering::SeqCst);
+                }
+                // A prompt is reading from the terminal
+                let Some(_input) = terminal::try_lock_key_input() else {
+                    sleep(poll_interval);
+                    continue;
+                };
+                match event::poll(poll_interval) {
+                    Ok(true) => (),
+                    Ok(false) => continue,
+                    // e.g. stdin isn't a terminal
+                    Err(_) => {
+                        sleep(poll_interval);
+                        continue;
+                    }
+                }
+                if let Ok(Event::Key(key)) = event::read() {
+                    match handle_key_event(key) {
+                        KeyAction::Cancel => cancel.store(true, Ordering::SeqCst),
+                        KeyAction::Exit => {
+                            exit.store(true, Ordering::SeqCst);
+                            cancel.store(true, Ordering::SeqCst);
+                        }
+                        KeyAction::Unmount | KeyAction::Reprocess | KeyAction::None => (),
+                    }
+                }
+            }
+        });
+        let result = f();
+        done.store(true, Ordering::SeqCst);
+        result
+    });
+    let exit = exit.load(Ordering::SeqCst) || !running.load(Ordering::SeqCst);
+    (result, exit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_elapsed() {
+        assert_eq!(
+            format_elapsed(Duration::from_secs(59)),
+            "less than a minute"
+        );
+        assert_eq!(format_elapsed(Duration::from_secs(60)), "1 minute");
+        assert_eq!(format_elapsed(Duration::from_secs(45 * 60)), "45 minutes");
+        assert_eq!(format_elapsed(Duration::from_secs(125 * 60)), "2h 05m");
+    }
+
+    #[test]
+    fn test_files_modified_since() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("hats")).unwrap();
+        std::fs::write(dir.path().join("a.dst"), "").unwrap();
+        std::fs::write(dir.path().join("hats").join("b.dst"), "").unwrap();
+        let watch_dirs = [dir.path().to_path_buf()];
+        let an_hour_ago = SystemTime::now() - Duration::from_secs(60 * 60);
+
+        let options = WatchOptions::default();
+        assert_eq!(
+            files_modified_since(&watch_dirs, an_hour_ago, &options),
+            [dir.path().join("a.dst")]
+        );
+        let options = WatchOptions {
+            recursive: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            files_modified_since(&watch_dirs, an_hour_ago, &options),
+            [
+                dir.path().join("a.dst"),
+                dir.path().join("hats").join("b.dst")
+            ]
+        );
+        let in_an_hour = SystemTime::now() + Duration::from_secs(60 * 60);
+        assert!(files_modified_since(&watch_dirs, in_an_hour, &options).is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_files_modified_since_with_symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let dir = tempfile::tempdir().unwrap();
+        let elsewhere = tempfile::tempdir().unwrap();
+        std::fs::write(elsewhere.path().join("b.dst"), "").unwrap();
+        symlink(elsewhere.path(), dir.path().join("linked")).unwrap();
+        // A cycle back to the watch directory
+        symlink(dir.path(), dir.path().join("loop")).unwrap();
+        symlink(
+            dir.path().join("missing.dst"),
+            dir.path().join("broken.dst"),
+        )
+        .unwrap();
+        let watch_dirs = [dir.path().to_path_buf()];
+        let an_hour_ago = SystemTime::now() - Duration::from_secs(60 * 60);
+
+        let options = WatchOptions {
+            recursive: true,
+            ..Default::default()
+        };
+        assert!(files_modified_since(&watch_dirs, an_hour_ago, &options).is_empty());
+        let options = WatchOptions {
+            recursive: true,
+            follow_symlinks: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            files_modified_since(&watch_dirs, an_hour_ago, &options),
+            [dir.path().join("linked").join("b.dst")]
+        );
+
+        let roots = [dir.path().canonicalize().unwrap()];
+        assert!(in_symlinked_dir(
+            &roots,
+            &dir.path().join("linked").join("b.dst")
+        ));
+        assert!(!in_symlinked_dir(&roots, &dir.path().join("a.dst")));
+        // The same files, through another spelling of the watch directory
+        let alias = elsewhere.path().join("alias");
+        symlink(dir.path(), &alias).unwrap();
+        assert!(in_symlinked_dir(
+            &roots,
+            &alias.join("linked").join("b.dst")
+        ));
+        assert!(!in_symlinked_dir(&roots, &alias.join("a.dst")));
+    }
+
+    #[test]
+    fn test_file_cache_clear() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = [dir.path().join("design.dst")];
+        std::fs::write(&paths[0], "LA:design").unwrap();
+        let mut cache = FileCache::new();
+        assert_eq!(cache.filter_new_files(&paths).count(), 1);
+        assert_eq!(cache.filter_new_files(&paths).count(), 0);
+        cache.clear();
+        assert_eq!(cache.filter_new_files(&paths).count(), 1);
+    }
+
+    #[test]
+    fn test_handle_key_event() {
+        let key = |c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE);
+        assert_eq!(handle_key_event(key('q')), KeyAction::Exit);
+        assert_eq!(handle_key_event(key('r')), KeyAction::Reprocess);
+        assert_eq!(handle_key_event(key('z')), KeyAction::None);
+        assert_eq!(
+            handle_key_event(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL)),
+            KeyAction::Exit
+        );
+    }
+
+    #[test]
+    fn test_recent_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, age_secs: u64| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, "").unwrap();
+            let modified = SystemTime::now() - Duration::from_secs(age_secs);
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        };
+        write("old.dst", 300);
+        write("new.pes", 10);
+        write("middle.svg", 100);
+        write(".hidden.dst", 0);
+        write("design.dst.crdownload", 0);
+        std::fs::create_dir(dir.path().join("hats")).unwrap();
+        let watch_dirs = [dir.path().to_path_buf()];
+
+        assert_eq!(
+            recent_files(&watch_dirs, 2),
+            [dir.path().join("new.pes"), dir.path().join("middle.svg")]
+        );
+        assert_eq!(recent_files(&watch_dirs, 10).len(), 3);
+    }
+
+    #[test]
+    fn test_contains_design_files() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(!contains_design_files(dir.path()));
+        std::fs::write(dir.path().join("notes.txt"), "").unwrap();
+        std::fs::write(dir.path().join(".hidden.dst"), "").unwrap();
+        std::fs::write(dir.path().join("design.pes.crdownload"), "").unwrap();
+        std::fs::create_dir(dir.path().join("folder.dst")).unwrap();
+        assert!(!contains_design_files(dir.path()));
+        std::fs::write(dir.path().join("Rose.DST"), "").unwrap();
+        assert!(contains_design_files(dir.path()));
+        assert!(!contains_design_files(&dir.path().join("missing")));
+    }
+
+    #[test]
+    fn test_files_to_reprocess() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("design.dst");
+        let output = dir.path().join("design.jef");
+        std::fs::write(&source, "LA:design").unwrap();
+        std::fs::write(&output, "x").unwrap();
+        let cache_dir = tempfile::tempdir().unwrap();
+        let mut manifest = OutputManifest::load_from(cache_dir.path().join("outputs.json"));
+        manifest.add(&output);
+
+        // Only the source is converted again, so there's no "design (1).jef"
+        let watch_dirs = [dir.path().to_path_buf()];
+        assert_eq!(
+            files_to_reprocess(&watch_dirs, &manifest, &WatchOptions::default()),
+            [source]
+        );
+    }
+
+    #[test]
+    fn test_watched_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        let caps = dir.path().join("hats").join("caps");
+        std::fs::create_dir_all(caps.join("wool")).unwrap();
+        let watch_dirs = [dir.path().to_path_buf()];
+
+        let mut dirs = watched_directories(&watch_dirs, 2, false);
+        dirs.sort();
+        assert_eq!(
+            dirs,
+            [dir.path().to_path_buf(), dir.path().join("hats"), caps]
+        );
+        assert_eq!(
+            watched_directories(&watch_dirs, 0, false),
+            [dir.path().to_path_buf()]
+        );
+    }
+
+    #[test]
+    fn test_depth_below() {
+        let root = Path::new("/designs");
+        assert_eq!(depth_below(root, Path::new("/designs/a.dst")), Some(0));
+        assert_eq!(depth_below(root, Path::new("/designs/hats/a.dst")), Some(1));
+        assert_eq!(
+            depth_below(root, Path::new("/designs/hats/caps/a.dst")),
+            Some(2)
+        );
+        assert_eq!(depth_below(root, Path::new("/elsewhere/a.dst")), None);
     }
 }
@@ -0,0 +1,156 @@
+use notify::{Config, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::print_error;
+use crate::services::file_conversion::{handle_file_detection, ConversionOptions};
+use crate::services::inkscape::Inkscape;
+use crate::services::watch::FileCache;
+
+const SETTLE_DURATION: Duration = Duration::from_millis(150);
+
+/// New and changed files in a watched directory, delivered asynchronously.
+///
+/// The underlying filesystem watcher stops when this value is dropped.
+pub struct FileEvents {
+    _watcher: RecommendedWatcher,
+    rx: UnboundedReceiver<notify::Result<notify::Event>>,
+    file_cache: FileCache,
+    pending: VecDeque<PathBuf>,
+}
+
+/// Starts watching `watch_dir`. Unlike [`watch_dir`](super::watch_dir), this
+/// doesn't take over the terminal and doesn't block the calling thread.
+pub fn file_events(watch_dir: &Path) -> notify::Result<FileEvents> {
+    let (tx, rx) = unbounded_channel();
+    let mut watcher = RecommendedWatcher::new(
+        move |res| {
+            // The receiver is gone once FileEvents is dropped
+            let _ = tx.send(res);
+        },
+        Config::default(),
+    )?;
+    watcher.watch(watch_dir, RecursiveMode::NonRecursive)?;
+
+    Ok(FileEvents {
+        _watcher: watcher,
+        rx,
+        file_cache: FileCache::new(),
+        pending: VecDeque::new(),
+    })
+}
+
+impl FileEvents {
+    /// Waits for the next file that was created or changed since it was last
+    /// reported. Returns `None` once the watcher has shut down.
+    pub async fn next(&mut self) -> Option<notify::Result<PathBuf>> {
+        loop {
+            if let Some(path) = self.pending.pop_front() {
+                return Some(Ok(path));
+            }
+
+            let event = match self.rx.recv().await? {
+                Ok(event) => event,
+                Err(e) => return Some(Err(e)),
+            };
+            let paths = match event.kind {
+                notify::EventKind::Create(_) => event.paths,
+                notify::EventKind::Modify(_) => {
+                    // give the file time to settle
+                    tokio::time::sleep(SETTLE_DURATION).await;
+                    event.paths
+                }
+                _ => vec![],
+            };
+            self.pending
+                .extend(self.file_cache.filter_new_files(&paths).cloned());
+        }
+    }
+}
+
+/// The async counterpart of [`watch_dir`](super::watch_dir): converts and
+/// copies new files in `watch_dir` until the returned future is dropped.
+///
+/// Conversions run one at a time on tokio's blocking thread pool, so other
+/// tasks on the runtime keep running while Inkscape works.
+pub async fn watch_async(
+    watch_dir: PathBuf,
+    usb_target_path: Option<String>,
+    accepted_formats: Vec<String>,
+    preferred_format: String,
+    inkscape: Option<Inkscape>,
+    options: ConversionOptions,
+) -> notify::Result<()> {
+    let mut events = file_events(&watch_dir)?;
+    let settings = Arc::new((
+        inkscape,
+        usb_target_path,
+        accepted_formats,
+        preferred_format,
+        options,
+    ));
+
+    while let Some(event) = events.next().await {
+        let path = match event {
+            Ok(path) => path,
+            Err(e) => {
+                eprintln!("Error receiving file event: {}", e);
+                continue;
+            }
+        };
+
+        let settings = settings.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            let (inkscape, usb_target_path, accepted_formats, preferred_format, options) =
+                &*settings;
+            let accepted_formats: Vec<&str> = accepted_formats.iter().map(|s| s.as_str()).collect();
+            handle_file_detection(
+                &path,
+                inkscape,
+                &usb_target_path.as_deref(),
+                &accepted_formats,
+                preferred_format,
+                options,
+            )
+            .map_err(|e| e.to_string())
+        })
+        .await;
+
+        match result {
+            Ok(Ok(Some(output_path))) => events.file_cache.mark_seen(&output_path),
+            Ok(Ok(None)) => (),
+            Ok(Err(e)) => print_error!("{}", e),
+            Err(e) => print_error!("File handling task failed: {}", e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_events_reports_new_files() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let mut events = file_events(dir.path()).unwrap();
+
+        let path = dir.path().join("design.dst");
+        std::fs::write(&path, "LA:test").unwrap();
+
+        let reported = runtime.block_on(async {
+            tokio::time::timeout(Duration::from_secs(5), events.next())
+                .await
+                .expect("timed out waiting for a file event")
+        });
+        assert_eq!(reported.unwrap().unwrap(), path);
+    }
+}
@@ -0,0 +1,81 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SizeUnit {
+    Millimeters,
+    Inches,
+}
+
+/// The maximum design area of one hoop, e.g. "5x7 inch" or "360x260mm".
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DesignSize {
+    pub width: f64,
+    pub height: f64,
+    pub unit: SizeUnit,
+}
+
+lazy_static! {
+    static ref DESIGN_SIZE_RE: Regex =
+        Regex::new(r"(?i)^\s*(\d+(?:\.\d+)?)\s*x\s*(\d+(?:\.\d+)?)\s*(mm|in|inch|inches)\s*$")
+            .unwrap();
+}
+
+impl FromStr for DesignSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let captures = DESIGN_SIZE_RE
+            .captures(s)
+            .ok_or_else(|| format!("Invalid design size: '{}'", s))?;
+        let unit = match captures[3].to_lowercase().as_str() {
+            "mm" => SizeUnit::Millimeters,
+            _ => SizeUnit::Inches,
+        };
+        Ok(DesignSize {
+            width: captures[1].parse().unwrap(),
+            height: captures[2].parse().unwrap(),
+            unit,
+        })
+    }
+}
+
+impl fmt::Display for DesignSize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.unit {
+            SizeUnit::Millimeters => write!(f, "{}x{}mm", self.width, self.height),
+            SizeUnit::Inches => write!(f, "{}x{} inch", self.width, self.height),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_design_size() {
+        let size: DesignSize = "6x10 inch".parse().unwrap();
+        assert_eq!(size.width, 6.0);
+        assert_eq!(size.height, 10.0);
+        assert_eq!(size.unit, SizeUnit::Inches);
+
+        let size: DesignSize = "360x260mm".parse().unwrap();
+        assert_eq!(size.unit, SizeUnit::Millimeters);
+
+        let size: DesignSize = " 15.7 x 10.2 in ".parse().unwrap();
+        assert_eq!(size.width, 15.7);
+
+        assert!("large".parse::<DesignSize>().is_err());
+        assert!("6x10".parse::<DesignSize>().is_err());
+    }
+
+    #[test]
+    fn test_display_design_size() {
+        for s in ["6x10 inch", "15.7x10.2 inch", "360x260mm"] {
+            assert_eq!(s.parse::<DesignSize>().unwrap().to_string(), s);
+        }
+    }
+}
@@ -1,5 +1,8 @@
 use lazy_static::lazy_static;
 use serde::Deserialize;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct FileFormat {
@@ -12,17 +15,94 @@ pub struct FileFormat {
 
 lazy_static! {
     pub static ref FILE_FORMATS: Vec<FileFormat> = {
-        let yaml_content = include_str!("./formats.yaml");
-        serde_yaml::from_str(yaml_content).expect("Failed to parse formats.yaml")
+        let (formats, errors) = parse_formats(include_str!("./formats.yaml"));
+        for error in errors {
+            eprintln!("Warning: skipped a format in formats.yaml: {}", error);
+        }
+        formats
     };
 }
 
+/// Parses a YAML list of formats. Entries that can't be parsed are skipped,
+/// and described in the second list.
+fn parse_formats(yaml: &str) -> (Vec<FileFormat>, Vec<String>) {
+    let entries: Vec<serde_yaml::Value> = match serde_yaml::from_str(yaml) {
+        Ok(entries) => entries,
+        Err(e) => return (Vec::new(), vec![e.to_string()]),
+    };
+
+    let mut formats = Vec::new();
+    let mut errors = Vec::new();
+    for (index, entry) in entries.into_iter().enumerate() {
+        match serde_yaml::from_value::<FileFormat>(entry) {
+            Ok(format) => formats.push(format),
+            Err(e) => errors.push(format!("entry {}: {}", index + 1, e)),
+        }
+    }
+    (formats, errors)
+}
+
+/// Leading bytes that identify a format, regardless of the file's extension
+const MAGIC_NUMBERS: &[(&[u8], &str)] = &[
+    (b"LA:", "dst"),
+    (b"#PES", "pes"),
+    (b"#PEC", "pec"),
+    (b"%vsm%", "vp3"),
+];
+
 impl FileFormat {
-    #[allow(dead_code)]
     pub fn find_by_extension(extension: &str) -> Option<&'static FileFormat> {
         let extension = extension.to_lowercase();
         FILE_FORMATS.iter().find(|f| f.extension == extension)
     }
+
+    /// Returns the extension of the format that `header` (the first bytes of
+    /// a file) identifies, if any.
+    pub fn detect_from_header(header: &[u8]) -> Option<&'static str> {
+        MAGIC_NUMBERS
+            .iter()
+            .find(|(magic, _)| header.starts_with(magic))
+            .map(|(_, extension)| *extension)
+    }
+
+    /// Returns the number of stitches that `header`, the start of a file in
+    /// the format with `extension`, records. Only DST and JEF files are
+    /// supported.
+    pub fn stitch_count_from_header(extension: &str, header: &[u8]) -> Option<u32> {
+        match extension.to_lowercase().as_str() {
+            // A text header of fields such as "ST:  12345\r"
+            "dst" => {
+                let start = header.windows(3).position(|field| field == b"ST:")? + 3;
+                let digits = header.get(start..start + 7)?;
+                std::str::from_utf8(digits).ok()?.trim().parse().ok()
+            }
+            // A binary header, with the stitch count as a little-endian
+            // integer at offset 28
+            "jef" => {
+                let bytes = header.get(28..32)?;
+                Some(u32::from_le_bytes(bytes.try_into().ok()?))
+            }
+            _ => None,
+        }
+    }
+
+    /// Reads the number of stitches from the header of the file at `path`;
+    /// see [`stitch_count_from_header`](Self::stitch_count_from_header).
+    pub fn read_stitch_count(path: &Path, extension: &str) -> Option<u32> {
+        let mut header = Vec::with_capacity(512);
+        File::open(path)
+            .and_then(|file| file.take(512).read_to_end(&mut header))
+            .ok()?;
+        Self::stitch_count_from_header(extension, &header)
+    }
+
+    pub fn detect_from_content(path: &Path) -> Option<&'static str> {
+        let mut header = Vec::with_capacity(8);
+        File::open(path)
+            .and_then(|file| file.take(8).read_to_end(&mut header))
+            .ok()?;
+        Self::detect_from_header(&header)
+    }
 }
 
 #[cfg(test)]
@@ -30,6 +110,30 @@ mod tests {
     use super::*;
     use std::collections::HashMap;
 
+    #[test]
+    fn test_parse_formats_skips_bad_entries() {
+        let (formats, errors) = parse_formats(
+            r#"
+- name: "Tajima"
+  extension: "dst"
+  manufacturer: "Tajima"
+- name: "Missing extension"
+  manufacturer: "Nobody"
+- name: "Janome"
+  extension: "jef"
+  manufacturer: "Janome"
+"#,
+        );
+        let extensions: Vec<_> = formats.iter().map(|f| f.extension.as_str()).collect();
+        assert_eq!(extensions, ["dst", "jef"]);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("entry 2: "), "{}", errors[0]);
+
+        let (formats, errors) = parse_formats("not: a list");
+        assert!(formats.is_empty());
+        assert_eq!(errors.len(), 1);
+    }
+
     #[test]
     fn test_unique_extensions() {
         let mut ext_counts: HashMap<&str, usize> = HashMap::new();
@@ -70,9 +174,47 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_stitch_count_from_header() {
+        let dst = b"LA:design         \rST:  12345\rCO:  3\r";
+        assert_eq!(
+            FileFormat::stitch_count_from_header("dst", dst),
+            Some(12345)
+        );
+
+        let mut jef = vec![0u8; 48];
+        jef[28..32].copy_from_slice(&250_000u32.to_le_bytes());
+        assert_eq!(
+            FileFormat::stitch_count_from_header("JEF", &jef),
+            Some(250_000)
+        );
+
+        assert_eq!(
+            FileFormat::stitch_count_from_header("jef", &jef[..20]),
+            None
+        );
+        assert_eq!(
+            FileFormat::stitch_count_from_header("pes", b"#PES0001"),
+            None
+        );
+    }
+
     #[test]
     fn test_find_by_extension() {
         assert!(FileFormat::find_by_extension("dst").is_some());
         assert!(FileFormat::find_by_extension("nonexistent").is_none());
     }
+
+    #[test]
+    fn test_detect_from_header() {
+        assert_eq!(
+            FileFormat::detect_from_header(b"LA:design        "),
+            Some("dst")
+        );
+        assert_eq!(FileFormat::detect_from_header(b"#PES0001"), Some("pes"));
+        assert_eq!(FileFormat::detect_from_header(b"#PEC0001"), Some("pec"));
+        assert_eq!(FileFormat::detect_from_header(b"%vsm%\0"), Some("vp3"));
+        assert_eq!(FileFormat::detect_from_header(b"LA"), None);
+        assert_eq!(FileFormat::detect_from_header(b"hello"), None);
+    }
 }
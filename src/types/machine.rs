@@ -1,237 +1,594 @@
-    use lazy_static::lazy_static;
-    use strsim::jaro_winkler;
-
-    use crate::utils::{prompt_from_list, prompt_yes_no, CsvReader};
-
-    #[derive(Debug, Clone)]
-    pub struct Machine {
-        pub name: String,
-        pub synonyms: Vec<String>,
-        pub file_formats: Vec<String>,
-        pub usb_path: Option<String>,
-        pub notes: Option<String>,
-        pub design_size: Option<String>,
-    }
-
-    impl Machine {
-        #[cfg(test)]
-        pub fn new(
-            name: String,
-        ) -> Self {
-            Self {
-                name,
-                synonyms: Default::default(),
-                file_formats: Default::default(),
-                usb_path: Default::default(),
-                notes: Default::default(),
-                design_size: Default::default(),
-            }
-        }
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
 
-        #[cfg(test)]
-        pub fn with_file_formats(mut self, formats: Vec<String>) -> Self {
-            self.file_formats = formats;
-            self
-        }
+use colored::Colorize;
+use lazy_static::lazy_static;
+use strsim::jaro_winkler;
 
-        #[cfg(test)]
-        pub fn with_usb_path(mut self, path: Option<String>) -> Self {
-            self.usb_path = path;
-            self
-        }
+use crate::types::DesignSize;
+use crate::utils::{fat_safe_filename, prompt_from_list, prompt_yes_no, CsvReader, CsvRecord};
 
-        fn normalize_name(s: &str) -> String {
-            s.chars()
-                .filter(|c| c.is_alphanumeric())
-                .collect::<String>()
-                .to_lowercase()
-        }
+/// The default minimum similarity for a machine to be suggested when a name
+/// doesn't match exactly
+pub const DEFAULT_MATCH_THRESHOLD: f64 = 0.8;
 
-        pub fn find_by_name(name: &str) -> Option<Machine> {
-            let normalized_search = Self::normalize_name(name);
-            MACHINES
-                .iter()
-                .find(|machine| {
-                    let normalized_name = Self::normalize_name(&machine.name);
-                    let normalized_synonyms: Vec<String> = machine
-                        .synonyms
-                        .iter()
-                        .map(|s| Self::normalize_name(s))
-                        .collect();
-
-                    normalized_name == normalized_search
-                        || normalized_synonyms.contains(&normalized_search)
-                })
-                .cloned()
+/// Suggestions whose similarity is within this of the best one's are
+/// reported as equally likely, since the order between them is arbitrary
+const AMBIGUOUS_MATCH_MARGIN: f64 = 0.02;
+
+/// Controls how [`Machine::interactive_find_by_name`] handles names that
+/// don't match a machine exactly.
+#[derive(Debug, Clone)]
+pub struct MatchOptions {
+    /// Minimum Jaro-Winkler similarity, from 0.0 to 1.0, for a machine to be
+    /// suggested. Lower values suggest more machines.
+    pub threshold: f64,
+    /// Show the closest candidates and their scores, to explain which
+    /// machine was chosen
+    pub verbose: bool,
+}
+
+impl Default for MatchOptions {
+    fn default() -> Self {
+        Self {
+            threshold: DEFAULT_MATCH_THRESHOLD,
+            verbose: false,
         }
+    }
+}
 
-        /// Returns machines with names similar to the search term, sorted by similarity score
-        /// Threshold is between 0.0 and 1.0, where 1.0 is an exact match
-        pub fn find_similar_names(name: &str, threshold: f64) -> Vec<Machine> {
-            let normalized_search = Self::normalize_name(name);
-            let mut matches: Vec<(f64, Machine)> = MACHINES
-                .iter()
-                .filter_map(|machine| {
-                    // Check main name
-                    let name_score =
-                        jaro_winkler(&normalized_search, &Self::normalize_name(&machine.name));
-
-                    // Check synonyms
-                    let synonym_score = machine
-                        .synonyms
-                        .iter()
-                        .map(|s| jaro_winkler(&normalized_search, &Self::normalize_name(s)))
-                        .max_by(|a, b| a.partial_cmp(b).unwrap())
-                        .unwrap_or(0.0);
-
-                    // Use the better score between name and synonyms
-                    let best_score = name_score.max(synonym_score);
-
-                    if best_score >= threshold {
-                        Some((best_score, machine.clone()))
-                    } else {
-                        None
-                    }
-                })
-                .collect();
-
-            // Sort by similarity score in descending order
-            matches.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
-            // Return the top 20 matches
-            matches
-                .into_iter()
-                .map(|(_, machine)| machine)
-                .take(20)
-                .collect()
+#[derive(Debug, Clone)]
+pub struct Machine {
+    pub name: String,
+    /// The company that makes the machine, e.g. "Brother"
+    pub manufacturer: String,
+    pub synonyms: Vec<String>,
+    pub file_formats: Vec<String>,
+    /// The format to convert designs to, if it isn't the first of
+    /// `file_formats`, e.g. for a machine that keeps more of a design's
+    /// colors in one of its formats
+    pub preferred_format: Option<String>,
+    pub usb_path: Option<String>,
+    pub notes: Option<String>,
+    /// One entry per hoop that the machine supports
+    pub design_sizes: Vec<DesignSize>,
+}
+
+impl Machine {
+    #[cfg(test)]
+    pub fn new(name: String) -> Self {
+        Self {
+            manufacturer: default_manufacturer(&name),
+            name,
+            synonyms: Default::default(),
+            file_formats: Default::default(),
+            preferred_format: Default::default(),
+            usb_path: Default::default(),
+            notes: Default::default(),
+            design_sizes: Default::default(),
         }
+    }
+
+    #[cfg(test)]
+    pub fn with_file_formats(mut self, formats: Vec<String>) -> Self {
+        self.file_formats = formats;
+        self
+    }
+
+    #[cfg(test)]
+    pub fn with_usb_path(mut self, path: Option<String>) -> Self {
+        self.usb_path = path;
+        self
+    }
+
+    fn normalize_name(s: &str) -> String {
+        s.chars()
+            .filter(|c| c.is_alphanumeric())
+            .collect::<String>()
+            .to_lowercase()
+    }
+
+    /// Returns true if the machine is made by `manufacturer`, ignoring case.
+    /// A single word also matches a manufacturer with a longer name, e.g.
+    /// "husqvarna" matches "Husqvarna Viking".
+    pub fn is_made_by(&self, manufacturer: &str) -> bool {
+        self.manufacturer.eq_ignore_ascii_case(manufacturer)
+            || self
+                .manufacturer
+                .split_whitespace()
+                .any(|word| word.eq_ignore_ascii_case(manufacturer))
+    }
+
+    /// Returns true if `format` is one of the machine's file formats,
+    /// ignoring case. "jef" and "jef+" are treated as the same format.
+    pub fn reads_format(&self, format: &str) -> bool {
+        let format = format.trim_end_matches('+');
+        self.file_formats
+            .iter()
+            .any(|f| f.trim_end_matches('+').eq_ignore_ascii_case(format))
+    }
 
-        pub fn interactive_find_by_name(name: &str) -> Option<Machine> {
-            if let Some(machine) = Self::find_by_name(name) {
-                return Some(machine);
+    /// The format to convert designs to when no output format is given: the
+    /// machine's preferred format, or else the first of its formats.
+    pub fn default_format(&self) -> Option<&str> {
+        self.preferred_format
+            .as_deref()
+            .or_else(|| self.file_formats.first().map(String::as_str))
+    }
+
+    /// The name of the machine's folder on a USB drive shared by several
+    /// machines: its name, with characters that FAT32 doesn't allow replaced.
+    pub fn usb_subdir(&self) -> String {
+        fat_safe_filename(&self.name)
+    }
+
+    pub fn find_by_name(name: &str) -> Option<Machine> {
+        let normalized_search = Self::normalize_name(name);
+        MACHINES
+            .iter()
+            .find(|machine| {
+                let normalized_name = Self::normalize_name(&machine.name);
+                let normalized_synonyms: Vec<String> = machine
+                    .synonyms
+                    .iter()
+                    .map(|s| Self::normalize_name(s))
+                    .collect();
+
+                normalized_name == normalized_search
+                    || normalized_synonyms.contains(&normalized_search)
+            })
+            .cloned()
+    }
+
+    /// Returns machines with names similar to the search term, sorted by similarity score
+    /// Threshold is between 0.0 and 1.0, where 1.0 is an exact match
+    pub fn find_similar_names(name: &str, threshold: f64) -> Vec<Machine> {
+        Self::similar_names_with_scores(name, threshold)
+            .into_iter()
+            .map(|(_, machine)| machine)
+            .collect()
+    }
+
+    /// Like [`Machine::find_similar_names`], but with each machine's
+    /// similarity score
+    fn similar_names_with_scores(name: &str, threshold: f64) -> Vec<(f64, Machine)> {
+        Self::similarity_scores(name)
+            .into_iter()
+            .take_while(|(score, _)| *score >= threshold)
+            .take(20)
+            .collect()
+    }
+
+    /// Returns every machine with its similarity to the search term, the
+    /// better of its name's and its synonyms' scores, from most to least
+    /// similar
+    pub fn similarity_scores(name: &str) -> Vec<(f64, Machine)> {
+        let normalized_search = Self::normalize_name(name);
+        let mut matches: Vec<(f64, Machine)> = MACHINES
+            .iter()
+            .map(|machine| {
+                // Check main name
+                let name_score =
+                    jaro_winkler(&normalized_search, &Self::normalize_name(&machine.name));
+
+                // Check synonyms
+                let synonym_score = machine
+                    .synonyms
+                    .iter()
+                    .map(|s| jaro_winkler(&normalized_search, &Self::normalize_name(s)))
+                    .max_by(|a, b| a.partial_cmp(b).unwrap())
+                    .unwrap_or(0.0);
+
+                // Use the better score between name and synonyms
+                (name_score.max(synonym_score), machine.clone())
+            })
+            .collect();
+
+        // Sort by similarity score in descending order
+        matches.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+        matches
+    }
+
+    pub fn interactive_find_by_name(name: &str, options: &MatchOptions) -> Option<Machine> {
+        if let Some(machine) = Self::find_by_name(name) {
+            return Some(machine);
+        }
+        let scored_machines = Self::similar_names_with_scores(name, options.threshold);
+        let ambiguous = ambiguous_matches(&scored_machines);
+        let similar_machines: Vec<Machine> = scored_machines.into_iter().map(|(_, m)| m).collect();
+        match similar_machines.len() {
+            0 => None,
+            1 => {
+                println!(
+                    "I found one machine that might match: {}",
+                    similar_machines[0].name
+                );
+                if prompt_yes_no("Use this? [Y/n] ", Some(true)) {
+                    Some(similar_machines[0].clone())
+                } else {
+                    None
+                }
             }
-            let similar_machines = Self::find_similar_names(name, 0.8);
-            match similar_machines.len() {
-                0 => None,
-                1 => {
+            _ => {
+                if !ambiguous.is_empty() {
                     println!(
-                        "I found one machine that might match: {}",
-                        similar_machines[0].name
+                        "{} {}",
+                        "These machines are nearly equally likely:".yellow(),
+                        ambiguous.join(", ")
                     );
-                    if prompt_yes_no("Use this? ", Some(true)) {
-                        Some(similar_machines[0].clone())
-                    } else {
-                        None
-                    }
-                }
-                _ => {
-                    println!("Did you mean:");
-                    let names: Vec<String> = similar_machines.iter().map(|m| m.name.clone()).collect();
-                    let index = prompt_from_list(&names);
-                    index.map(|index| similar_machines[index].clone())
                 }
+                println!("Did you mean:");
+                let names: Vec<String> = similar_machines.iter().map(|m| m.name.clone()).collect();
+                let index = prompt_from_list(&names);
+                index.map(|index| similar_machines[index].clone())
             }
         }
     }
+}
 
-    lazy_static! {
-        pub static ref MACHINES: Vec<Machine> = {
-            let csv_data = include_str!("./machines.csv");
-            let mut reader = CsvReader::from_str(csv_data).unwrap();
-
-            reader
-                .iter_records()
-                .map(|result| {
-                    let record = result.unwrap();
-                    Machine {
-                        name: record.get("Machine Name").unwrap().to_string(),
-                        synonyms: record.get_vec("Synonyms", ',').unwrap_or_default(),
-                        file_formats: record.get_vec("File Formats", ',').unwrap(),
-                        usb_path: record.get("USB Path").map(ToString::to_string),
-                        notes: record.get("Notes").map(ToString::to_string),
-                        design_size: record.get("Design Size").map(ToString::to_string),
-                    }
-                })
-                .collect()
-        };
+/// The names of the best matches, if more than one of them scores within
+/// [`AMBIGUOUS_MATCH_MARGIN`] of the first, so that picking the first would
+/// be a guess. `matches` is sorted from most to least similar.
+fn ambiguous_matches(matches: &[(f64, Machine)]) -> Vec<String> {
+    let Some((best, _)) = matches.first() else {
+        return Vec::new();
+    };
+    let names: Vec<String> = matches
+        .iter()
+        .take_while(|(score, _)| best - score <= AMBIGUOUS_MATCH_MARGIN)
+        .map(|(_, machine)| machine.name.clone())
+        .collect();
+    if names.len() > 1 {
+        names
+    } else {
+        Vec::new()
     }
+}
 
-    #[cfg(test)]
-    mod tests {
-        use super::*;
-        use std::collections::HashMap;
-
-        #[test]
-        fn test_unique_machine_names() {
-            let mut name_groups: HashMap<String, Vec<String>> = HashMap::new();
-
-            MACHINES.iter().for_each(|m| {
-                let normalized_name: String = m
-                    .name
-                    .chars()
-                    .filter(|c| c.is_alphanumeric())
-                    .collect::<String>()
-                    .to_lowercase();
-
-                name_groups
-                    .entry(normalized_name)
-                    .or_default()
-                    .push(m.name.clone());
-            });
-
-            let duplicates: Vec<_> = name_groups
-                .into_values()
-                .filter(|names| names.len() > 1)
-                .collect();
-
-            assert!(
-                duplicates.is_empty(),
-                "Found equivalent machine names: {:?}",
-                duplicates
-            );
+/// The manufacturer of a machine whose listing doesn't name one: the first
+/// word of its name, e.g. "Brother" for "Brother PE800".
+fn default_manufacturer(name: &str) -> String {
+    name.split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Parses machine listings in the format of `machines.csv`. Rows that can't
+/// be parsed, such as a machine without a name or formats, are skipped;
+/// they're described in the second list, which also reports data that isn't
+/// CSV at all.
+pub fn parse_machines(csv_data: &str) -> (Vec<Machine>, Vec<String>) {
+    let mut reader = match csv_data.parse::<CsvReader>() {
+        Ok(reader) => reader,
+        Err(e) => return (Vec::new(), vec![e.to_string()]),
+    };
+
+    let mut machines = Vec::new();
+    let mut errors = Vec::new();
+    for (index, result) in reader.iter_records().enumerate() {
+        match result
+            .map_err(|e| e.to_string())
+            .and_then(|record| parse_machine(&record))
+        {
+            Ok(machine) => machines.push(machine),
+            // Line 1 is the header
+            Err(e) => errors.push(format!("line {}: {}", index + 2, e)),
+        }
+    }
+    (machines, errors)
+}
+
+fn parse_machine(record: &CsvRecord) -> Result<Machine, String> {
+    let name = record
+        .get("Machine Name")
+        .filter(|name| !name.is_empty())
+        .ok_or("missing Machine Name")?
+        .to_string();
+    let file_formats = record
+        .get_vec("File Formats", ',')
+        .ok_or_else(|| format!("{} has no File Formats", name))?;
+    let preferred_format = record
+        .get("Preferred Format")
+        .filter(|format| !format.is_empty())
+        .map(str::to_lowercase);
+    if let Some(format) = preferred_format
+        .as_ref()
+        .filter(|format| !file_formats.iter().any(|f| f.eq_ignore_ascii_case(format)))
+    {
+        return Err(format!(
+            "{}'s Preferred Format {} isn't one of its File Formats",
+            name, format
+        ));
+    }
+    Ok(Machine {
+        manufacturer: record
+            .get("Manufacturer")
+            .filter(|manufacturer| !manufacturer.is_empty())
+            .map_or_else(|| default_manufacturer(&name), ToString::to_string),
+        name,
+        synonyms: record.get_vec("Synonyms", ',').unwrap_or_default(),
+        file_formats,
+        preferred_format,
+        usb_path: record.get("USB Path").map(ToString::to_string),
+        notes: record.get("Notes").map(ToString::to_string),
+        design_sizes: record
+            .get_vec("Design Size", ',')
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|s| s.parse().ok())
+            .collect(),
+    })
+}
+
+/// Machines from outside the built-in list, such as a `machines_url`
+static EXTRA_MACHINES: OnceLock<Vec<Machine>> = OnceLock::new();
+
+/// Set once [`MACHINES`] has been built, after which it can't change
+static MACHINES_LOADED: AtomicBool = AtomicBool::new(false);
+
+/// Adds `machines` to [`MACHINES`], replacing built-in machines with the same
+/// name. This only has an effect if it's called once, before [`MACHINES`] is
+/// first used; otherwise it returns false.
+pub fn add_machines(machines: Vec<Machine>) -> bool {
+    !MACHINES_LOADED.load(Ordering::SeqCst) && EXTRA_MACHINES.set(machines).is_ok()
+}
+
+/// Returns `machines` with `extra` added. An extra machine replaces a
+/// machine with the same name, ignoring case.
+fn merge_machines(mut machines: Vec<Machine>, extra: &[Machine]) -> Vec<Machine> {
+    for machine in extra {
+        match machines
+            .iter_mut()
+            .find(|m| m.name.eq_ignore_ascii_case(&machine.name))
+        {
+            Some(existing) => *existing = machine.clone(),
+            None => machines.push(machine.clone()),
         }
+    }
+    machines
+}
 
-        #[test]
-        fn test_get_machine_info() {
-            assert!(Machine::find_by_name("Brother PE800").is_some());
-            assert!(Machine::find_by_name("Nonexistent Machine").is_none());
+const BUILT_IN_MACHINES_CSV: &str = include_str!("./machines.csv");
+
+/// The header row of the built-in machine list, for starting a new list
+pub fn machines_csv_header() -> &'static str {
+    BUILT_IN_MACHINES_CSV.lines().next().unwrap_or_default()
+}
+
+lazy_static! {
+    pub static ref MACHINES: Vec<Machine> = {
+        MACHINES_LOADED.store(true, Ordering::SeqCst);
+        let (machines, errors) = parse_machines(BUILT_IN_MACHINES_CSV);
+        for error in errors {
+            eprintln!("Warning: skipped a machine in the built-in list: {}", error);
         }
+        merge_machines(machines, EXTRA_MACHINES.get().map_or(&[], Vec::as_slice))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_ambiguous_matches() {
+        let machine = |name: &str| Machine::new(name.to_string());
+        assert!(ambiguous_matches(&[]).is_empty());
+        assert!(ambiguous_matches(&[(0.9, machine("Brother PE800"))]).is_empty());
+        assert!(ambiguous_matches(&[
+            (0.95, machine("Brother PE800")),
+            (0.85, machine("Brother PE535")),
+        ])
+        .is_empty());
+        assert_eq!(
+            ambiguous_matches(&[
+                (0.91, machine("Brother PE800")),
+                (0.90, machine("Brother PE900")),
+                (0.85, machine("Brother PE535")),
+            ]),
+            vec!["Brother PE800", "Brother PE900"]
+        );
+    }
+
+    #[test]
+    fn test_manufacturer() {
+        let viking = MACHINES
+            .iter()
+            .find(|m| m.name == "Husqvarna Designer Epic2")
+            .unwrap();
+        assert_eq!(viking.manufacturer, "Husqvarna Viking");
+        assert!(viking.is_made_by("husqvarna viking"));
+        assert!(viking.is_made_by("Husqvarna"));
+        assert!(!viking.is_made_by("Brother"));
+
+        // Listings without the column use the first word of the name
+        let (machines, _) = parse_machines("Machine Name,File Formats\nJanome MC500E,jef\n");
+        assert_eq!(machines[0].manufacturer, "Janome");
+    }
+
+    #[test]
+    fn test_reads_format() {
+        let machine = Machine::new("Janome MC500E".to_string())
+            .with_file_formats(vec!["jef+".to_string(), "dst".to_string()]);
+        assert!(machine.reads_format("DST"));
+        assert!(machine.reads_format("jef"));
+        assert!(!machine.reads_format("pes"));
+    }
+
+    #[test]
+    fn test_usb_subdir() {
+        assert_eq!(
+            Machine::new("Janome MC500E".to_string()).usb_subdir(),
+            "Janome MC500E"
+        );
+        assert_eq!(
+            Machine::new("Brother PE-800/PE-770".to_string()).usb_subdir(),
+            "Brother PE-800-PE-770"
+        );
+    }
+
+    #[test]
+    fn test_parse_machines_skips_bad_rows() {
+        let (machines, errors) = parse_machines(
+            "Machine Name,File Formats\n\
+             Janome MC500E,jef\n\
+             Brother PE800,\n\
+             Brother SE600,pes,extra\n\
+             Janome MB-4S,jef\n",
+        );
+        let names: Vec<_> = machines.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, ["Janome MC500E", "Janome MB-4S"]);
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0], "line 3: Brother PE800 has no File Formats");
+        assert!(errors[1].starts_with("line 4: "), "{}", errors[1]);
+
+        let (machines, errors) = parse_machines("Name,Formats\nJanome MC500E,jef\n");
+        assert!(machines.is_empty());
+        assert_eq!(errors, ["line 2: missing Machine Name"]);
+
+        let (machines, _) = parse_machines("<html>Not found</html>");
+        assert!(machines.is_empty());
+    }
+
+    #[test]
+    fn test_default_format() {
+        let (machines, errors) = parse_machines(
+            "Machine Name,File Formats,Preferred Format\n\
+             Singer Quantum XL-1000,\"xxx, dst\",DST\n\
+             Janome MC500E,jef,\n\
+             Brother PE800,pes,jef\n",
+        );
+        assert_eq!(machines[0].default_format(), Some("dst"));
+        assert_eq!(machines[1].default_format(), Some("jef"));
+        assert_eq!(
+            errors,
+            ["line 4: Brother PE800's Preferred Format jef isn't one of its File Formats"]
+        );
+    }
 
-        #[test]
-        fn test_get_machine_info_case_insensitive() {
-            assert!(Machine::find_by_name("brother pe800").is_some());
-            assert!(Machine::find_by_name("BROTHER PE800").is_some());
-            assert!(Machine::find_by_name("Brother-PE800").is_some());
-            assert!(Machine::find_by_name("Brother PE 800").is_some());
+    #[test]
+    fn test_merge_machines() {
+        let machines = vec![
+            Machine::new("Brother PE800".to_string()).with_file_formats(vec!["pes".to_string()]),
+            Machine::new("Janome MC500E".to_string()),
+        ];
+        let extra = vec![
+            Machine::new("brother pe800".to_string()).with_file_formats(vec!["dst".to_string()]),
+            Machine::new("Bernina 570".to_string()),
+        ];
+        let merged = merge_machines(machines, &extra);
+        let names = merged.iter().map(|m| m.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["brother pe800", "Janome MC500E", "Bernina 570"]);
+        assert_eq!(merged[0].file_formats, ["dst"]);
+    }
+
+    #[test]
+    fn test_unique_machine_names() {
+        let mut name_groups: HashMap<String, Vec<String>> = HashMap::new();
+
+        MACHINES.iter().for_each(|m| {
+            let normalized_name: String = m
+                .name
+                .chars()
+                .filter(|c| c.is_alphanumeric())
+                .collect::<String>()
+                .to_lowercase();
+
+            name_groups
+                .entry(normalized_name)
+                .or_default()
+                .push(m.name.clone());
+        });
+
+        let duplicates: Vec<_> = name_groups
+            .into_values()
+            .filter(|names| names.len() > 1)
+            .collect();
+
+        assert!(
+            duplicates.is_empty(),
+            "Found equivalent machine names: {:?}",
+            duplicates
+        );
+    }
+
+    #[test]
+    fn test_design_sizes_parse() {
+        let csv_data = include_str!("./machines.csv");
+        let mut reader = csv_data.parse::<CsvReader>().unwrap();
+        for record in reader.iter_records() {
+            let record = record.unwrap();
+            for size in record.get_vec("Design Size", ',').unwrap_or_default() {
+                assert!(
+                    size.parse::<DesignSize>().is_ok(),
+                    "Invalid design size for {}: {}",
+                    record.get("Machine Name").unwrap(),
+                    size
+                );
+            }
         }
+    }
 
-        #[test]
-        #[ignore]
-        fn test_find_similar_names() {
-            // Test exact match
-            let results = Machine::find_similar_names("Brother PE800", 0.9);
-            assert!(!results.is_empty());
-            assert_eq!(results[0].name, "Brother PE800");
+    #[test]
+    fn test_multiple_design_sizes() {
+        let csv_data = "Machine Name,File Formats,Design Size\n\
+            Test Machine,jef,\"100x100mm, 140x200mm\"\n";
+        let mut reader = csv_data.parse::<CsvReader>().unwrap();
+        let record = reader.iter_records().next().unwrap().unwrap();
+        let sizes: Vec<DesignSize> = record
+            .get_vec("Design Size", ',')
+            .unwrap()
+            .iter()
+            .map(|s| s.parse().unwrap())
+            .collect();
+        assert_eq!(sizes.len(), 2);
+        assert_eq!(sizes[1].to_string(), "140x200mm");
+    }
 
-            // Test close match
-            let results = Machine::find_similar_names("Brother PE 80", 0.8);
-            assert!(!results.is_empty());
-            assert!(results.iter().any(|m| m.name == "Brother PE800"));
+    #[test]
+    fn test_get_machine_info() {
+        assert!(Machine::find_by_name("Brother PE800").is_some());
+        assert!(Machine::find_by_name("Nonexistent Machine").is_none());
+    }
+
+    #[test]
+    fn test_get_machine_info_case_insensitive() {
+        assert!(Machine::find_by_name("brother pe800").is_some());
+        assert!(Machine::find_by_name("BROTHER PE800").is_some());
+        assert!(Machine::find_by_name("Brother-PE800").is_some());
+        assert!(Machine::find_by_name("Brother PE 800").is_some());
+    }
+
+    #[test]
+    #[ignore]
+    fn test_find_similar_names() {
+        // Test exact match
+        let results = Machine::find_similar_names("Brother PE800", 0.9);
+        assert!(!results.is_empty());
+        assert_eq!(results[0].name, "Brother PE800");
+
+        // Test close match
+        let results = Machine::find_similar_names("Brother PE 80", 0.8);
+        assert!(!results.is_empty());
+        assert!(results.iter().any(|m| m.name == "Brother PE800"));
 
-            // Test no matches with high threshold
-            let results = Machine::find_similar_names("XYZ123", 0.9);
-            assert!(results.is_empty());
+        // Test no matches with high threshold
+        let results = Machine::find_similar_names("XYZ123", 0.9);
+        assert!(results.is_empty());
 
-            // Test partial name
-            let results = Machine::find_similar_names("PE800", 0.7);
+        // Test partial name
+        let results = Machine::find_similar_names("PE800", 0.7);
+        assert!(!results.is_empty());
+        assert!(results.iter().any(|m| m.name == "Brother PE800"));
+
+        // Test with synonym
+        let pe800 = Machine::find_by_name("Brother PE800").unwrap();
+        if !pe800.synonyms.is_empty() {
+            let results = Machine::find_similar_names(&pe800.synonyms[0], 0.8);
             assert!(!results.is_empty());
             assert!(results.iter().any(|m| m.name == "Brother PE800"));
-
-            // Test with synonym
-            let pe800 = Machine::find_by_name("Brother PE800").unwrap();
-            if !pe800.synonyms.is_empty() {
-                let results = Machine::find_similar_names(&pe800.synonyms[0], 0.8);
-                assert!(!results.is_empty());
-                assert!(results.iter().any(|m| m.name == "Brother PE800"));
-            }
         }
     }
+}
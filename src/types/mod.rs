@@ -1,6 +1,10 @@
+pub mod design_size;
 pub mod format;
 pub mod machine;
 
+pub use design_size::DesignSize;
+pub use format::FileFormat;
 pub use format::FILE_FORMATS;
 pub use machine::Machine;
+pub use machine::MatchOptions;
 pub use machine::MACHINES;
@@ -0,0 +1,72 @@
+use std::path::PathBuf;
+
+/// The name of stitch-sync's directory inside each of the system's
+/// configuration, cache, and data directories
+const APP_DIR_NAME: &str = "stitch-sync";
+
+pub const CONFIG_FILE: &str = "config.toml";
+/// The user's own machines, which are added to the built-in list
+pub const USER_MACHINES_FILE: &str = "machines.csv";
+pub const VERSION_CACHE_FILE: &str = "version-cache.json";
+/// The list downloaded from `machines_url`
+pub const MACHINES_CACHE_FILE: &str = "machines-cache.json";
+/// The files that stitch-sync has written, for pruning and reprocessing
+pub const OUTPUTS_FILE: &str = "outputs.json";
+pub const HISTORY_FILE: &str = "history.jsonl";
+pub const BATCH_RUNS_FILE: &str = "batch-runs.json";
+
+/// The kinds of directory that stitch-sync keeps files in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AppDir {
+    Config,
+    Cache,
+    Data,
+}
+
+impl AppDir {
+    /// stitch-sync's directory of this kind, or `None` if the system doesn't
+    /// have one.
+    pub fn path(self) -> Option<PathBuf> {
+        let dir = match self {
+            AppDir::Config => dirs::config_dir(),
+            AppDir::Cache => dirs::cache_dir(),
+            AppDir::Data => dirs::data_dir(),
+        };
+        dir.map(|dir| dir.join(APP_DIR_NAME))
+    }
+
+    /// The names of the files that stitch-sync keeps in this directory.
+    pub fn files(self) -> &'static [&'static str] {
+        match self {
+            AppDir::Config => &[CONFIG_FILE, USER_MACHINES_FILE],
+            AppDir::Cache => &[VERSION_CACHE_FILE, MACHINES_CACHE_FILE, OUTPUTS_FILE],
+            AppDir::Data => &[HISTORY_FILE, BATCH_RUNS_FILE],
+        }
+    }
+
+    /// The path of `file` in this directory. If the system doesn't have a
+    /// directory of this kind, the file is kept under the current directory.
+    pub fn file(self, file: &str) -> PathBuf {
+        self.path()
+            .unwrap_or_else(|| PathBuf::from(".").join(APP_DIR_NAME))
+            .join(file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_files_are_in_their_directory() {
+        for dir in [AppDir::Config, AppDir::Cache, AppDir::Data] {
+            let Some(path) = dir.path() else {
+                continue;
+            };
+            assert!(path.ends_with(APP_DIR_NAME));
+            for file in dir.files() {
+                assert_eq!(dir.file(file), path.join(file));
+            }
+        }
+    }
+}
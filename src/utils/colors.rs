@@ -1,3 +1,64 @@
+use serde::{Deserialize, Serialize};
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+/// Whether the terminal has a dark or light background, for choosing
+/// readable colors. `Auto` guesses from the environment.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Theme {
+    #[default]
+    Auto,
+    Dark,
+    Light,
+}
+
+impl fmt::Display for Theme {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Theme::Auto => write!(f, "auto"),
+            Theme::Dark => write!(f, "dark"),
+            Theme::Light => write!(f, "light"),
+        }
+    }
+}
+
+impl FromStr for Theme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "auto" => Ok(Theme::Auto),
+            "dark" => Ok(Theme::Dark),
+            "light" => Ok(Theme::Light),
+            _ => Err(format!(
+                "Unknown theme '{}'; expected 'auto', 'dark', or 'light'",
+                s
+            )),
+        }
+    }
+}
+
+static THEME: OnceLock<Theme> = OnceLock::new();
+
+/// Sets the theme for the rest of the run. Only the first call has an effect.
+pub fn set_theme(theme: Theme) {
+    let _ = THEME.set(theme);
+}
+
+fn is_dark() -> bool {
+    match THEME.get().copied().unwrap_or_default() {
+        Theme::Dark => true,
+        Theme::Light => false,
+        Theme::Auto => match std::env::var("LS_COLORS") {
+            Ok(ls_colors) => is_dark_theme(&ls_colors),
+            Err(_) => true, // default to dark theme
+        },
+    }
+}
+
 /// Determine if the terminal likely has a dark background based on LS_COLORS
 pub fn is_dark_theme(ls_colors: &str) -> bool {
     let entries = ls_colors.split(':');
@@ -32,7 +93,7 @@ pub fn is_dark_theme(ls_colors: &str) -> bool {
         let codes = rs.split('=').nth(1).unwrap_or("").split(';');
         for code in codes {
             if let Ok(num) = code.parse::<u8>() {
-                if num >= 90 && num <= 97 {
+                if (90..=97).contains(&num) {
                     return true; // Bright default text suggests dark theme
                 }
             }
@@ -41,7 +102,7 @@ pub fn is_dark_theme(ls_colors: &str) -> bool {
 
     // If we can't determine, check terminal background color if available
     if let Ok(term_bg) = std::env::var("COLORFGBG") {
-        return term_bg.split(';').last().map_or(true, |bg| bg != "15");
+        return term_bg.split(';').next_back() != Some("15");
     }
 
     // Default based on system preference if available
@@ -70,11 +131,7 @@ pub enum MessageType {
 }
 
 pub fn get_contrasting_color(message_type: MessageType) -> crossterm::style::Color {
-    let is_dark = if let Ok(ls_colors) = std::env::var("LS_COLORS") {
-        is_dark_theme(&ls_colors)
-    } else {
-        true // default to dark theme
-    };
+    let is_dark = is_dark();
 
     use crossterm::style::Color::*;
     match (message_type, is_dark) {
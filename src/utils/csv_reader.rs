@@ -1,13 +1,16 @@
 use csv::ReaderBuilder;
 use std::io::Cursor;
+use std::str::FromStr;
 
 pub struct CsvReader {
     headers: csv::StringRecord,
     records: csv::StringRecordsIntoIter<Cursor<String>>,
 }
 
-impl CsvReader {
-    pub fn from_str(csv_data: &str) -> Result<Self, csv::Error> {
+impl FromStr for CsvReader {
+    type Err = csv::Error;
+
+    fn from_str(csv_data: &str) -> Result<Self, csv::Error> {
         let mut reader = ReaderBuilder::new()
             .has_headers(true)
             .from_reader(Cursor::new(csv_data.to_string()));
@@ -17,7 +20,9 @@ impl CsvReader {
 
         Ok(Self { headers, records })
     }
+}
 
+impl CsvReader {
     pub fn iter_records(&mut self) -> CsvRecordsIterator<'_> {
         CsvRecordsIterator {
             headers: &self.headers,
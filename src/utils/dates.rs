@@ -0,0 +1,136 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Formats `time` as "YYYY-MM-DD HH:MM" in UTC.
+pub fn format_utc(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let minutes = (secs % 86400) / 60;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        year,
+        month,
+        day,
+        minutes / 60,
+        minutes % 60
+    )
+}
+
+/// Parses a date, "YYYY-MM-DD", or a date and time in UTC, such as
+/// "YYYY-MM-DD HH:MM" or "YYYY-MM-DDTHH:MM:SSZ".
+pub fn parse_utc(s: &str) -> Option<SystemTime> {
+    let (date, time) = match s.split_once(['T', ' ']) {
+        Some((date, time)) => (date, Some(time.trim_end_matches('Z'))),
+        None => (s, None),
+    };
+    let mut date_parts = date.split('-').map(|part| part.parse::<u32>().ok());
+    let (year, month, day) = (
+        date_parts.next()??,
+        date_parts.next()??,
+        date_parts.next()??,
+    );
+    if date_parts.next().is_some() || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let mut secs = 0;
+    if let Some(time) = time {
+        let parts = time
+            .split(':')
+            .map(|part| part.parse::<u64>().ok())
+            .collect::<Option<Vec<_>>>()?;
+        let (hours, minutes, seconds) = match parts.as_slice() {
+            [hours, minutes] => (*hours, *minutes, 0),
+            [hours, minutes, seconds] => (*hours, *minutes, *seconds),
+            _ => return None,
+        };
+        if hours > 23 || minutes > 59 || seconds > 59 {
+            return None;
+        }
+        secs = hours * 3600 + minutes * 60 + seconds;
+    }
+    let days = u64::try_from(days_from_civil(year as i64, month, day)).ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(days * 86400 + secs))
+}
+
+/// Parses a duration such as "45s", "30m", "2h", "3d", or "1w".
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let unit = s.chars().last()?;
+    let count: u64 = s[..s.len() - unit.len_utf8()].parse().ok()?;
+    let unit_secs = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+    Some(Duration::from_secs(count.checked_mul(unit_secs)?))
+}
+
+/// Converts a (year, month, day) date to a count of days since 1970-01-01.
+/// This is Howard Hinnant's `days_from_civil` algorithm.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// Converts a count of days since 1970-01-01 to a (year, month, day) date.
+/// This is Howard Hinnant's `civil_from_days` algorithm.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_utc() {
+        assert_eq!(format_utc(UNIX_EPOCH), "1970-01-01 00:00");
+        let time = UNIX_EPOCH + Duration::from_secs(1_709_210_096);
+        assert_eq!(format_utc(time), "2024-02-29 12:34");
+    }
+
+    #[test]
+    fn test_parse_utc() {
+        let time = UNIX_EPOCH + Duration::from_secs(1_709_210_096);
+        assert_eq!(parse_utc("2024-02-29T12:34:56Z"), Some(time));
+        assert_eq!(
+            parse_utc("2024-02-29 12:34").map(format_utc).as_deref(),
+            Some("2024-02-29 12:34")
+        );
+        assert_eq!(parse_utc("1970-01-01"), Some(UNIX_EPOCH));
+        assert_eq!(parse_utc("2024-13-01"), None);
+        assert_eq!(parse_utc("2024-02-29 25:00"), None);
+        assert_eq!(parse_utc("yesterday"), None);
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("45s"), Some(Duration::from_secs(45)));
+        assert_eq!(parse_duration("2h"), Some(Duration::from_secs(7200)));
+        assert_eq!(
+            parse_duration("1w"),
+            Some(Duration::from_secs(7 * 24 * 3600))
+        );
+        assert_eq!(parse_duration("2"), None);
+        assert_eq!(parse_duration("h"), None);
+        assert_eq!(parse_duration("2y"), None);
+    }
+}
@@ -1,6 +1,56 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
-pub fn sanitize_filename(input: &Path) -> PathBuf {
+/// How `sanitize_filename` changes the case of letters. Names are lowercased
+/// by default, but some machines display lowercase names oddly.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SanitizeCase {
+    #[default]
+    Lower,
+    Preserve,
+    Upper,
+}
+
+impl SanitizeCase {
+    fn apply(self, c: char) -> char {
+        match self {
+            SanitizeCase::Lower => c.to_ascii_lowercase(),
+            SanitizeCase::Preserve => c,
+            SanitizeCase::Upper => c.to_ascii_uppercase(),
+        }
+    }
+}
+
+impl fmt::Display for SanitizeCase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SanitizeCase::Lower => write!(f, "lower"),
+            SanitizeCase::Preserve => write!(f, "preserve"),
+            SanitizeCase::Upper => write!(f, "upper"),
+        }
+    }
+}
+
+impl FromStr for SanitizeCase {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "lower" => Ok(SanitizeCase::Lower),
+            "preserve" => Ok(SanitizeCase::Preserve),
+            "upper" => Ok(SanitizeCase::Upper),
+            _ => Err(format!(
+                "Unknown case '{}'; expected 'lower', 'preserve', or 'upper'",
+                s
+            )),
+        }
+    }
+}
+
+pub fn sanitize_filename(input: &Path, case: SanitizeCase) -> PathBuf {
     let stem = input
         .file_stem()
         .and_then(|s| s.to_str())
@@ -11,7 +61,7 @@ pub fn sanitize_filename(input: &Path) -> PathBuf {
         .chars()
         .map(|c| {
             if c.is_alphanumeric() {
-                c.to_ascii_lowercase()
+                case.apply(c)
             } else {
                 '-'
             }
@@ -41,3 +91,206 @@ pub fn sanitize_filename(input: &Path) -> PathBuf {
         .unwrap_or(sanitized);
     input.with_file_name(output_name)
 }
+
+/// Characters that FAT32, the usual file system on embroidery USB drives,
+/// doesn't allow in filenames
+const FAT_ILLEGAL_CHARS: &[char] = &['"', '*', '/', ':', '<', '>', '?', '\\', '|'];
+
+/// The longest filename, in bytes, that is safe to write to a FAT32 drive
+const FAT_MAX_FILENAME_BYTES: usize = 255;
+
+/// Makes `filename` safe to write to a FAT32 drive: illegal and control
+/// characters are replaced with hyphens, trailing dots and spaces are
+/// removed, and the stem is shortened so that the name, including its
+/// extension, fits in 255 bytes.
+pub fn fat_safe_filename(filename: &str) -> String {
+    let replaced = filename
+        .chars()
+        .map(|c| {
+            if FAT_ILLEGAL_CHARS.contains(&c) || c.is_control() {
+                '-'
+            } else {
+                c
+            }
+        })
+        .collect::<String>();
+    let replaced = replaced.trim_end_matches(['.', ' ']);
+
+    let (stem, extension) = match replaced.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, Some(extension)),
+        _ => (replaced, None),
+    };
+    let extension_len = extension.map_or(0, |ext| ext.len() + 1);
+    let mut stem_len = stem
+        .len()
+        .min(FAT_MAX_FILENAME_BYTES.saturating_sub(extension_len));
+    while !stem.is_char_boundary(stem_len) {
+        stem_len -= 1;
+    }
+    let stem = match &stem[..stem_len] {
+        "" => "output",
+        stem => stem,
+    };
+    match extension {
+        Some(extension) => format!("{}.{}", stem, extension),
+        None => stem.to_string(),
+    }
+}
+
+/// Creates an empty file at `path` if nothing exists there yet, otherwise at
+/// the first free variant of the form "name (1).ext", "name (2).ext", and so
+/// on, and returns its path. The name is claimed by creating the file, so
+/// conversions that run at the same time can't choose the same one.
+pub fn reserve_unique_path(path: &Path) -> std::io::Result<PathBuf> {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let extension = path.extension().map(|e| e.to_string_lossy().into_owned());
+
+    let candidates = std::iter::once(path.to_path_buf()).chain((1..).map(|n| {
+        let name = match &extension {
+            Some(ext) => format!("{} ({}).{}", stem, n, ext),
+            None => format!("{} ({})", stem, n),
+        };
+        path.with_file_name(name)
+    }));
+    for candidate in candidates {
+        match std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&candidate)
+        {
+            Ok(_) => return Ok(candidate),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    unreachable!("there are infinitely many candidate names")
+}
+
+/// Whether `error` means that another program has the file open and doesn't
+/// allow it to be shared. Windows reports this while a browser is still
+/// writing a download; other systems don't lock files this way.
+pub fn is_sharing_violation(error: &std::io::Error) -> bool {
+    // ERROR_SHARING_VIOLATION and ERROR_LOCK_VIOLATION
+    cfg!(windows) && matches!(error.raw_os_error(), Some(32) | Some(33))
+}
+
+/// Formats a number of bytes for people, e.g. "1.5 GB", using the decimal
+/// units that drive manufacturers and file managers use.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["KB", "MB", "GB", "TB"];
+    if bytes < 1000 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1000.0;
+    let mut unit = 0;
+    while size >= 1000.0 && unit < UNITS.len() - 1 {
+        size /= 1000.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_is_sharing_violation() {
+        let error = std::io::Error::from_raw_os_error(32);
+        assert_eq!(is_sharing_violation(&error), cfg!(windows));
+        let error = std::io::Error::from(std::io::ErrorKind::NotFound);
+        assert!(!is_sharing_violation(&error));
+    }
+
+    #[test]
+    fn test_sanitize_filename() {
+        let input = Path::new("/designs/My Logo_v2.svg");
+        assert_eq!(
+            sanitize_filename(input, SanitizeCase::Lower),
+            Path::new("/designs/my-logo-v2.svg")
+        );
+        assert_eq!(
+            sanitize_filename(input, SanitizeCase::Preserve),
+            Path::new("/designs/My-Logo-v2.svg")
+        );
+        assert_eq!(
+            sanitize_filename(input, SanitizeCase::Upper),
+            Path::new("/designs/MY-LOGO-V2.svg")
+        );
+        assert_eq!(
+            sanitize_filename(Path::new("!!!.svg"), SanitizeCase::Lower),
+            Path::new("output.svg")
+        );
+    }
+
+    #[test]
+    fn test_fat_safe_filename() {
+        assert_eq!(fat_safe_filename("Design: v2?.jef"), "Design- v2-.jef");
+        assert_eq!(
+            fat_safe_filename("a\"b*c/d<e>f\\g|h\u{7}.dst"),
+            "a-b-c-d-e-f-g-h-.dst"
+        );
+        assert_eq!(fat_safe_filename("flower 🌸.pes"), "flower 🌸.pes");
+        assert_eq!(fat_safe_filename("design.jef. "), "design.jef");
+        assert_eq!(fat_safe_filename("???"), "---");
+
+        let long = format!("{}.jef", "🌸".repeat(100));
+        let safe = fat_safe_filename(&long);
+        assert!(safe.len() <= FAT_MAX_FILENAME_BYTES);
+        assert!(safe.ends_with("🌸.jef"));
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1500), "1.5 KB");
+        assert_eq!(format_size(15_500_000_000), "15.5 GB");
+        assert_eq!(format_size(2_000_000_000_000_000), "2000.0 TB");
+    }
+
+    #[test]
+    fn test_reserve_unique_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("design.jef");
+        assert_eq!(reserve_unique_path(&path).unwrap(), path);
+        assert!(path.exists());
+        assert_eq!(
+            reserve_unique_path(&path).unwrap(),
+            dir.path().join("design (1).jef")
+        );
+        assert_eq!(
+            reserve_unique_path(&path).unwrap(),
+            dir.path().join("design (2).jef")
+        );
+    }
+
+    #[test]
+    fn test_reserve_unique_path_without_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("design");
+        fs::write(&path, "").unwrap();
+        assert_eq!(
+            reserve_unique_path(&path).unwrap(),
+            dir.path().join("design (1)")
+        );
+    }
+
+    #[test]
+    fn test_reserve_unique_path_concurrently() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("design.jef");
+        let mut paths: Vec<PathBuf> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..8)
+                .map(|_| scope.spawn(|| reserve_unique_path(&path).unwrap()))
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        paths.sort();
+        paths.dedup();
+        assert_eq!(paths.len(), 8);
+    }
+}
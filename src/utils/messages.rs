@@ -1,15 +1,64 @@
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set while stdout carries machine-readable output, such as `watch
+/// --stdout-format ndjson`. Messages for the user then go to stderr.
+static STDOUT_RESERVED: AtomicBool = AtomicBool::new(false);
+
+/// Sends messages for the user to stderr from now on, leaving stdout to
+/// machine-readable output.
+pub fn reserve_stdout() {
+    STDOUT_RESERVED.store(true, Ordering::SeqCst);
+}
+
+pub fn stdout_reserved() -> bool {
+    STDOUT_RESERVED.load(Ordering::SeqCst)
+}
+
+/// Where messages for the user are written: stdout, or stderr once stdout
+/// is reserved.
+pub fn message_output() -> Box<dyn Write> {
+    if stdout_reserved() {
+        Box::new(io::stderr())
+    } else {
+        Box::new(io::stdout())
+    }
+}
+
+/// Whether `message_output` is a terminal.
+pub fn message_output_is_terminal() -> bool {
+    if stdout_reserved() {
+        io::stderr().is_terminal()
+    } else {
+        io::stdout().is_terminal()
+    }
+}
+
+/// Like `println!`, but writes to stderr once stdout is reserved for
+/// machine-readable output. See `reserve_stdout`.
+#[macro_export]
+macro_rules! print_message {
+    ($($arg:tt)*) => {
+        if $crate::utils::messages::stdout_reserved() {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+
 #[macro_export]
 macro_rules! print_error {
     ($fmt:literal, $($arg:tt)*) => {{
         use crossterm::style::Stylize;
-        use crate::utils::colors::{get_contrasting_color, MessageType};
+        use $crate::utils::colors::{get_contrasting_color, MessageType};
         let msg = format!($fmt, $($arg)*);
-        println!("{} ❌", msg.with(get_contrasting_color(MessageType::Error)))
+        $crate::print_message!("{} ❌", msg.with(get_contrasting_color(MessageType::Error)))
     }};
     ($fmt:literal) => {{
         use crossterm::style::Stylize;
-        use crate::utils::colors::{get_contrasting_color, MessageType};
-        println!("{} ❌", $fmt.with(get_contrasting_color(MessageType::Error)))
+        use $crate::utils::colors::{get_contrasting_color, MessageType};
+        $crate::print_message!("{} ❌", $fmt.with(get_contrasting_color(MessageType::Error)))
     }};
 }
 
@@ -17,14 +66,14 @@ macro_rules! print_error {
 macro_rules! print_notice {
     ($fmt:literal, $($arg:tt)*) => {{
         use crossterm::style::Stylize;
-        use crate::utils::colors::{get_contrasting_color, MessageType};
+        use $crate::utils::colors::{get_contrasting_color, MessageType};
         let msg = format!($fmt, $($arg)*);
-        println!("{}", msg.with(get_contrasting_color(MessageType::Info)))
+        $crate::print_message!("{}", msg.with(get_contrasting_color(MessageType::Info)))
     }};
     ($fmt:literal) => {{
         use crossterm::style::Stylize;
-        use crate::utils::colors::{get_contrasting_color, MessageType};
-        println!("{}", $fmt.with(get_contrasting_color(MessageType::Info)))
+        use $crate::utils::colors::{get_contrasting_color, MessageType};
+        $crate::print_message!("{}", $fmt.with(get_contrasting_color(MessageType::Info)))
     }};
 }
 
@@ -35,6 +84,13 @@ macro_rules! write_notice {
     };
 }
 
+#[macro_export]
+macro_rules! write_warning {
+    ($writer:expr, $($arg:tt)*) => {
+        writeln!($writer, "{} {}", "⚠️ ".bright_yellow(), format!($($arg)*)).unwrap();
+    };
+}
+
 #[macro_export]
 macro_rules! write_error {
     ($writer:expr, $($arg:tt)*) => {
@@ -1,13 +1,21 @@
+pub mod app_dirs;
 pub mod colors;
 pub mod messages;
+pub mod terminal;
 pub mod version;
 
 mod csv_reader;
+mod dates;
 mod files;
+mod network;
+mod process;
 mod progress;
 mod prompts;
 
-pub use csv_reader::CsvReader;
+pub use csv_reader::{CsvReader, CsvRecord};
+pub use dates::*;
 pub use files::*;
+pub use network::*;
+pub use process::*;
 pub use progress::*;
 pub use prompts::*;
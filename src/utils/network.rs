@@ -0,0 +1,197 @@
+use std::path::Path;
+
+/// Filesystem types of network shares, as reported by the mount table
+#[cfg_attr(target_os = "windows", allow(dead_code))]
+const NETWORK_FS_TYPES: &[&str] = &[
+    "afpfs",
+    "cifs",
+    "davfs",
+    "fuse.sshfs",
+    "nfs",
+    "nfs4",
+    "smb3",
+    "smbfs",
+    "webdav",
+];
+
+/// Returns true if `path` appears to be on a network share, such as SMB or
+/// NFS. File system events often aren't delivered for these.
+pub fn is_network_path(path: &Path) -> bool {
+    #[cfg(target_os = "windows")]
+    {
+        use std::ffi::OsStr;
+        use std::os::windows::ffi::OsStrExt;
+        use windows::{core::PCWSTR, Win32::Storage::FileSystem::GetDriveTypeW};
+
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        // canonicalize() writes UNC paths as \\?\UNC\server\share
+        let path_str = path.to_string_lossy();
+        if path_str.starts_with(r"\\?\UNC\")
+            || (path_str.starts_with(r"\\") && !path_str.starts_with(r"\\?\"))
+        {
+            return true;
+        }
+        let Some(root) = windows_root(&path) else {
+            return false;
+        };
+        let mut wide: Vec<u16> = OsStr::new(&root).encode_wide().collect();
+        wide.push(0);
+        // DRIVE_REMOTE
+        unsafe { GetDriveTypeW(PCWSTR::from_raw(wide.as_ptr())) == 4 }
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    {
+        filesystem_type(path).is_some_and(|fs_type| NETWORK_FS_TYPES.contains(&fs_type.as_str()))
+    }
+}
+
+/// Returns the type of the filesystem that contains `path`, such as `vfat`
+/// or `ext4` on Linux, `msdos` or `apfs` on macOS, and `FAT32` or `NTFS` on
+/// Windows.
+pub fn filesystem_type(path: &Path) -> Option<String> {
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+
+    #[cfg(target_os = "linux")]
+    {
+        let mounts = std::fs::read_to_string("/proc/mounts").ok()?;
+        containing_mount_type(&path, parse_proc_mounts(&mounts)).map(String::from)
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        let output = super::c_locale_command("mount").output().ok()?;
+        let mounts = String::from_utf8_lossy(&output.stdout);
+        containing_mount_type(&path, parse_mount_output(&mounts)).map(String::from)
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        use std::ffi::OsStr;
+        use std::os::windows::ffi::OsStrExt;
+        use windows::{core::PCWSTR, Win32::Storage::FileSystem::GetVolumeInformationW};
+
+        let root = windows_root(&path)?;
+        let mut wide: Vec<u16> = OsStr::new(&root).encode_wide().collect();
+        wide.push(0);
+        let mut name = [0u16; 32];
+        let ok = unsafe {
+            GetVolumeInformationW(
+                PCWSTR::from_raw(wide.as_ptr()),
+                None,
+                None,
+                None,
+                None,
+                Some(&mut name),
+            )
+        };
+        if !ok.as_bool() {
+            return None;
+        }
+        let len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+        Some(String::from_utf16_lossy(&name[..len]))
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+    {
+        let _ = path;
+        None
+    }
+}
+
+/// Returns the root directory, such as `C:\`, of the drive that contains
+/// the canonicalized `path`.
+#[cfg(target_os = "windows")]
+fn windows_root(path: &Path) -> Option<String> {
+    let prefix = path.components().next()?;
+    Some(format!(
+        "{}\\",
+        prefix
+            .as_os_str()
+            .to_string_lossy()
+            .trim_start_matches(r"\\?\")
+    ))
+}
+
+/// Returns the filesystem type of the mount that contains `path`: the one
+/// with the longest matching mount point.
+#[cfg_attr(
+    not(any(target_os = "linux", target_os = "macos", test)),
+    allow(dead_code)
+)]
+fn containing_mount_type<'a>(
+    path: &Path,
+    mounts: impl Iterator<Item = (String, &'a str)>,
+) -> Option<&'a str> {
+    mounts
+        .filter(|(mount_point, _)| path.starts_with(mount_point))
+        .max_by_key(|(mount_point, _)| mount_point.len())
+        .map(|(_, fs_type)| fs_type)
+}
+
+/// Parses lines such as `//server/share /mnt/share cifs rw,relatime 0 0` into
+/// (mount point, filesystem type) pairs.
+#[cfg_attr(not(any(target_os = "linux", test)), allow(dead_code))]
+fn parse_proc_mounts(mounts: &str) -> impl Iterator<Item = (String, &str)> {
+    mounts.lines().filter_map(|line| {
+        let mut fields = line.split_whitespace();
+        let _device = fields.next()?;
+        // Spaces in mount points are written as \040
+        let mount_point = fields.next()?.replace("\\040", " ");
+        Some((mount_point, fields.next()?))
+    })
+}
+
+/// Parses lines such as `//user@server/share on /Volumes/share (smbfs,
+/// nodev, nosuid)` from the macOS `mount` command.
+#[cfg_attr(not(any(target_os = "macos", test)), allow(dead_code))]
+fn parse_mount_output(mounts: &str) -> impl Iterator<Item = (String, &str)> {
+    mounts.lines().filter_map(|line| {
+        let (_, rest) = line.split_once(" on ")?;
+        let (mount_point, options) = rest.rsplit_once(" (")?;
+        let fs_type = options.split([',', ')']).next()?.trim();
+        Some((mount_point.to_string(), fs_type))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_containing_mount_type() {
+        let proc_mounts = "/dev/sda1 / ext4 rw 0 0\n\
+                           //nas/designs /mnt/my\\040designs cifs rw 0 0\n";
+        assert_eq!(
+            containing_mount_type(
+                Path::new("/mnt/my designs/new"),
+                parse_proc_mounts(proc_mounts)
+            ),
+            Some("cifs")
+        );
+        assert_eq!(
+            containing_mount_type(
+                Path::new("/home/me/Downloads"),
+                parse_proc_mounts(proc_mounts)
+            ),
+            Some("ext4")
+        );
+
+        let mount_output = "/dev/disk3s1s1 on / (apfs, sealed, local, read-only)\n\
+                            //me@nas/designs on /Volumes/designs (smbfs, nodev, nosuid)\n";
+        assert_eq!(
+            containing_mount_type(
+                Path::new("/Volumes/designs"),
+                parse_mount_output(mount_output)
+            ),
+            Some("smbfs")
+        );
+        assert_eq!(
+            containing_mount_type(
+                Path::new("/Users/me/Downloads"),
+                parse_mount_output(mount_output)
+            ),
+            Some("apfs")
+        );
+    }
+}
@@ -0,0 +1,25 @@
+use std::ffi::OsStr;
+use std::process::Command;
+
+/// Returns a `Command` for a tool whose output will be parsed, such as
+/// `diskutil` or `df`. It runs in the C locale, so that its labels and
+/// number formats are the English ones the parsers expect, whatever the
+/// user's system language.
+pub fn c_locale_command(program: impl AsRef<OsStr>) -> Command {
+    let mut command = Command::new(program);
+    command.env("LC_ALL", "C").env("LANG", "C");
+    command
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_c_locale_command() {
+        let command = c_locale_command("df");
+        let envs: Vec<_> = command.get_envs().collect();
+        assert!(envs.contains(&(OsStr::new("LC_ALL"), Some(OsStr::new("C")))));
+        assert!(envs.contains(&(OsStr::new("LANG"), Some(OsStr::new("C")))));
+    }
+}
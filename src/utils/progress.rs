@@ -1,31 +1,271 @@
-use std::io::{self, Write};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
 use std::process::Child;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::{Duration, Instant};
 
+use super::format_size;
+use super::messages::{message_output, message_output_is_terminal};
+use crate::print_message;
+
 pub const WATCH_POLL_INTERVAL: Duration = Duration::from_millis(100);
+pub const DIRECTORY_POLL_INTERVAL: Duration = Duration::from_secs(1);
 
 fn print_progress_dots(last_dot: Instant, dot_interval: Duration) -> Instant {
-    let mut stdout = io::stdout();
     if last_dot.elapsed() >= dot_interval {
-        print!(".");
-        stdout.flush().unwrap_or_default();
+        let mut output = message_output();
+        let _ = write!(output, ".");
+        output.flush().unwrap_or_default();
         Instant::now()
     } else {
         last_dot
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WaitStatus {
+    Exited,
+    /// The child was killed because `cancel` was set
+    Cancelled,
+}
+
+/// Waits for `child` to exit, printing a dot every `dot_interval`, or
+/// nothing if `dot_interval` is `None`. If `cancel` becomes true while the
+/// child is running, the child is killed.
 pub fn wait_with_progress(
     child: &mut Child,
-    dot_interval: Duration,
+    dot_interval: Option<Duration>,
     poll_interval: Duration,
-) -> io::Result<()> {
+    cancel: Option<&AtomicBool>,
+) -> io::Result<WaitStatus> {
     let mut last_dot = Instant::now();
 
     while child.try_wait()?.is_none() {
-        last_dot = print_progress_dots(last_dot, dot_interval);
+        if cancel.is_some_and(|cancel| cancel.load(Ordering::SeqCst)) {
+            child.kill()?;
+            child.wait()?;
+            return Ok(WaitStatus::Cancelled);
+        }
+        if let Some(dot_interval) = dot_interval {
+            last_dot = print_progress_dots(last_dot, dot_interval);
+        }
         std::thread::sleep(poll_interval);
     }
 
-    Ok(())
+    Ok(WaitStatus::Exited)
+}
+
+const BAR_WIDTH: usize = 30;
+
+/// Progress through a known number of steps, drawn as a bar such as
+/// "[=========>          ] 12/40 ETA 1m05s". When stdout isn't a terminal,
+/// a line of text is printed at every 10% instead.
+pub struct ProgressBar {
+    total: usize,
+    done: usize,
+    start: Instant,
+    is_terminal: bool,
+    /// Show the steps as sizes, e.g. "1.5 MB/15.5 MB"
+    bytes: bool,
+}
+
+impl ProgressBar {
+    pub fn new(total: usize) -> Self {
+        let bar = Self {
+            total,
+            done: 0,
+            start: Instant::now(),
+            is_terminal: message_output_is_terminal(),
+            bytes: false,
+        };
+        if bar.is_terminal {
+            bar.draw();
+        }
+        bar
+    }
+
+    /// A bar for `total` bytes, whose progress is shown as sizes.
+    pub fn bytes(total: u64) -> Self {
+        let bar = Self {
+            bytes: true,
+            ..Self::new(total as usize)
+        };
+        if bar.is_terminal {
+            bar.draw();
+        }
+        bar
+    }
+
+    /// Records that one more step has completed.
+    pub fn inc(&mut self) {
+        self.set(self.done + 1);
+    }
+
+    /// Records that `done` steps have completed.
+    pub fn set(&mut self, done: usize) {
+        let decile = self.decile();
+        self.done = done.min(self.total);
+        if self.is_terminal {
+            self.draw();
+        } else if self.decile() > decile {
+            print_message!("{}", self.status());
+        }
+    }
+
+    fn decile(&self) -> usize {
+        self.done * 10 / self.total.max(1)
+    }
+
+    pub fn finish(&self) {
+        if self.is_terminal {
+            print_message!();
+        }
+    }
+
+    fn draw(&self) {
+        let mut output = message_output();
+        let _ = write!(output, "\r\x1B[K{} {}", self.bar(), self.status());
+        output.flush().unwrap_or_default();
+    }
+
+    fn bar(&self) -> String {
+        let filled = BAR_WIDTH * self.done / self.total.max(1);
+        let head = if filled < BAR_WIDTH { ">" } else { "" };
+        format!(
+            "[{}{}{}]",
+            "=".repeat(filled),
+            head,
+            " ".repeat(BAR_WIDTH - filled - head.len())
+        )
+    }
+
+    fn status(&self) -> String {
+        let count = |n: usize| {
+            if self.bytes {
+                format_size(n as u64)
+            } else {
+                n.to_string()
+            }
+        };
+        match self.eta() {
+            Some(eta) if self.done < self.total => format!(
+                "{}/{} ETA {}",
+                count(self.done),
+                count(self.total),
+                format_duration(eta)
+            ),
+            _ => format!("{}/{}", count(self.done), count(self.total)),
+        }
+    }
+
+    fn eta(&self) -> Option<Duration> {
+        if self.done == 0 {
+            return None;
+        }
+        let per_step = self.start.elapsed() / self.done as u32;
+        Some(per_step * (self.total - self.done) as u32)
+    }
+}
+
+/// The size of the chunks that `copy_with_progress` writes
+const COPY_CHUNK_BYTES: usize = 1024 * 1024;
+
+/// Copies `source` to `dest`, as `fs::copy` does, while drawing a progress
+/// bar of the bytes written. Each chunk is flushed to the drive before the
+/// bar advances, so that on a slow USB drive the bar shows the drive's
+/// progress rather than the operating system's write cache.
+pub fn copy_with_progress(source: &Path, dest: &Path) -> io::Result<u64> {
+    let mut reader = File::open(source)?;
+    let metadata = reader.metadata()?;
+    let mut writer = File::create(dest)?;
+    let mut bar = ProgressBar::bytes(metadata.len());
+    let mut buffer = vec![0; COPY_CHUNK_BYTES];
+    let mut copied = 0;
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        writer.write_all(&buffer[..n])?;
+        writer.sync_data()?;
+        copied += n as u64;
+        bar.set(copied as usize);
+    }
+    bar.finish();
+    fs::set_permissions(dest, metadata.permissions())?;
+    Ok(copied)
+}
+
+/// Formats a duration as e.g. "45s", "3m05s", or "1h02m".
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_secs(45)), "45s");
+        assert_eq!(format_duration(Duration::from_secs(185)), "3m05s");
+        assert_eq!(format_duration(Duration::from_secs(3720)), "1h02m");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_wait_with_progress_cancel() {
+        let mut child = std::process::Command::new("sleep")
+            .arg("5")
+            .spawn()
+            .unwrap();
+        let cancel = AtomicBool::new(true);
+        let start = Instant::now();
+        let status =
+            wait_with_progress(&mut child, None, Duration::from_millis(10), Some(&cancel)).unwrap();
+        assert_eq!(status, WaitStatus::Cancelled);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_progress_bar() {
+        let mut bar = ProgressBar {
+            total: 4,
+            done: 0,
+            start: Instant::now(),
+            is_terminal: false,
+            bytes: false,
+        };
+        assert_eq!(bar.bar(), format!("[>{}]", " ".repeat(BAR_WIDTH - 1)));
+        assert_eq!(bar.status(), "0/4");
+
+        bar.done = 4;
+        assert_eq!(bar.bar(), format!("[{}]", "=".repeat(BAR_WIDTH)));
+        assert_eq!(bar.status(), "4/4");
+
+        bar.bytes = true;
+        bar.total = 15_500_000;
+        bar.done = 15_500_000;
+        assert_eq!(bar.status(), "15.5 MB/15.5 MB");
+    }
+
+    #[test]
+    fn test_copy_with_progress() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("design.dst");
+        let content = vec![7u8; COPY_CHUNK_BYTES * 2 + 100];
+        fs::write(&source, &content).unwrap();
+        let dest = dir.path().join("copy.dst");
+        assert_eq!(
+            copy_with_progress(&source, &dest).unwrap(),
+            content.len() as u64
+        );
+        assert_eq!(fs::read(&dest).unwrap(), content);
+    }
 }
@@ -1,29 +1,42 @@
 use colored::*;
-use std::io::{stdout, Write};
+use std::io::Write;
 
+use super::messages::message_output;
+use crate::print_message;
+
+/// Asks a yes/no question until the user answers. An empty answer selects
+/// `default`, if there is one.
 pub fn prompt_yes_no(prompt: &str, default: Option<bool>) -> bool {
     loop {
-        let input = prompt_input(&prompt);
-        match input.to_lowercase().trim() {
-            "y" => return true,
-            "n" => return false,
-            "" if default.is_some() => return default.unwrap(),
-            _ => {}
+        if let Some(answer) = parse_yes_no(&prompt_input(prompt), default) {
+            return answer;
         }
     }
 }
 
+fn parse_yes_no(input: &str, default: Option<bool>) -> Option<bool> {
+    match input.to_lowercase().trim() {
+        "y" | "yes" => Some(true),
+        "n" | "no" => Some(false),
+        "" => default,
+        _ => None,
+    }
+}
+
 pub fn prompt_input(prompt: &str) -> String {
-    print!("{}", prompt);
-    stdout().flush().unwrap();
-    let mut input = String::new();
-    std::io::stdin().read_line(&mut input).unwrap();
-    input.trim().to_string()
+    super::terminal::with_line_input(|| {
+        let mut output = message_output();
+        write!(output, "{}", prompt).unwrap();
+        output.flush().unwrap();
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input).unwrap();
+        input.trim().to_string()
+    })
 }
 
 pub fn prompt_from_list(list: &[String]) -> Option<usize> {
     for (i, machine) in list.iter().enumerate() {
-        println!("  {}. {}", (i + 1).to_string().cyan(), machine);
+        print_message!("  {}. {}", (i + 1).to_string().cyan(), machine);
     }
     loop {
         let input = prompt_input(&"Enter a number, or 'q' to cancel: ".cyan());
@@ -34,9 +47,29 @@ pub fn prompt_from_list(list: &[String]) -> Option<usize> {
         if index > 0 && index <= list.len() {
             return Some(index - 1);
         }
-        println!(
+        print_message!(
             "{}",
             format!("Please enter a number between 1 and {}", list.len()).yellow()
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_yes_no_default() {
+        assert_eq!(parse_yes_no("", Some(true)), Some(true));
+        assert_eq!(parse_yes_no("  ", Some(false)), Some(false));
+        assert_eq!(parse_yes_no("", None), None);
+    }
+
+    #[test]
+    fn test_parse_yes_no_answers() {
+        assert_eq!(parse_yes_no("y", None), Some(true));
+        assert_eq!(parse_yes_no("Yes", Some(false)), Some(true));
+        assert_eq!(parse_yes_no("N", Some(true)), Some(false));
+        assert_eq!(parse_yes_no("maybe", Some(true)), None);
+    }
+}
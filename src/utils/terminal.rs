@@ -0,0 +1,159 @@
+//! Sharing the terminal between prompts, which read a line, and a thread
+//! that listens for single key presses while a file is processed.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// Held by whatever is reading from the terminal, so that a key listener
+/// never takes the answer to a prompt
+static TERMINAL_INPUT: Mutex<()> = Mutex::new(());
+
+/// Set while a prompt is waiting for the key listener to let go of the
+/// terminal, so that the listener doesn't immediately take it back
+static PROMPT_WAITING: AtomicBool = AtomicBool::new(false);
+
+/// Claims the terminal for a key listener's next poll, unless a prompt is
+/// reading from it or waiting to.
+pub fn try_lock_key_input() -> Option<MutexGuard<'static, ()>> {
+    if PROMPT_WAITING.load(Ordering::SeqCst) {
+        return None;
+    }
+    TERMINAL_INPUT.try_lock().ok()
+}
+
+/// Runs `read`, which reads a line from the terminal, with line editing and
+/// echo turned on, even if a key listener has turned them off. The previous
+/// mode is restored afterwards.
+pub fn with_line_input<T>(read: impl FnOnce() -> T) -> T {
+    PROMPT_WAITING.store(true, Ordering::SeqCst);
+    let _input = TERMINAL_INPUT
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    PROMPT_WAITING.store(false, Ordering::SeqCst);
+
+    #[cfg(unix)]
+    {
+        let saved = unix::get_mode();
+        if let Some(mut mode) = saved {
+            unix::make_cooked(&mut mode);
+            unix::set_mode(&mode);
+        }
+        let result = read();
+        if let Some(mode) = saved {
+            unix::set_mode(&mode);
+        }
+        result
+    }
+
+    #[cfg(not(unix))]
+    {
+        use crossterm::terminal::{disable_raw_mode, enable_raw_mode, is_raw_mode_enabled};
+        let raw = is_raw_mode_enabled().unwrap_or(false);
+        if raw {
+            let _ = disable_raw_mode();
+        }
+        let result = read();
+        if raw {
+            let _ = enable_raw_mode();
+        }
+        result
+    }
+}
+
+/// While this is alive, key presses are delivered as they're typed, without
+/// waiting for Enter, and aren't echoed. Unlike raw mode, output and Ctrl-C
+/// work as usual, so that messages can be printed in the meantime.
+pub struct KeyInputMode {
+    #[cfg(unix)]
+    saved: Option<libc::termios>,
+}
+
+impl KeyInputMode {
+    pub fn enable() -> Self {
+        #[cfg(unix)]
+        {
+            let saved = unix::get_mode();
+            if let Some(mut mode) = saved {
+                mode.c_lflag &= !(libc::ICANON | libc::ECHO);
+                mode.c_cc[libc::VMIN] = 1;
+                mode.c_cc[libc::VTIME] = 0;
+                unix::set_mode(&mode);
+            }
+            Self { saved }
+        }
+
+        // Windows delivers key events without this
+        #[cfg(not(unix))]
+        Self {}
+    }
+}
+
+impl Drop for KeyInputMode {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Some(mode) = &self.saved {
+            unix::set_mode(mode);
+        }
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    /// The mode of the terminal on stdin, or `None` if stdin isn't one.
+    pub fn get_mode() -> Option<libc::termios> {
+        // SAFETY: tcgetattr only writes to the termios it's given
+        unsafe {
+            let mut mode = std::mem::zeroed();
+            (libc::tcgetattr(libc::STDIN_FILENO, &mut mode) == 0).then_some(mode)
+        }
+    }
+
+    pub fn set_mode(mode: &libc::termios) {
+        // SAFETY: tcsetattr only reads the termios it's given
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, mode);
+        }
+    }
+
+    /// Turns on line editing, echo, signals and output processing, which
+    /// raw mode and `KeyInputMode` turn off.
+    pub fn make_cooked(mode: &mut libc::termios) {
+        mode.c_lflag |= libc::ICANON | libc::ECHO | libc::ISIG | libc::IEXTEN;
+        mode.c_iflag |= libc::ICRNL;
+        mode.c_oflag |= libc::OPOST;
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_make_cooked() {
+        // SAFETY: an all-zero termios is a valid value
+        let mut mode: libc::termios = unsafe { std::mem::zeroed() };
+        unix::make_cooked(&mut mode);
+        assert_ne!(mode.c_lflag & libc::ICANON, 0);
+        assert_ne!(mode.c_lflag & libc::ECHO, 0);
+        assert_ne!(mode.c_oflag & libc::OPOST, 0);
+    }
+
+    #[test]
+    fn test_prompt_takes_input_from_key_listener() {
+        let listener = try_lock_key_input().unwrap();
+        let (answer_tx, answer_rx) = std::sync::mpsc::channel();
+        std::thread::scope(|scope| {
+            let prompt = scope.spawn(move || with_line_input(|| answer_rx.recv().unwrap()));
+            while !PROMPT_WAITING.load(Ordering::SeqCst) {
+                std::thread::yield_now();
+            }
+            // The listener doesn't take the terminal back while a prompt
+            // waits for it or reads its answer
+            drop(listener);
+            assert!(try_lock_key_input().is_none());
+            answer_tx.send("answer").unwrap();
+            assert_eq!(prompt.join().unwrap(), "answer");
+        });
+        assert!(try_lock_key_input().is_some());
+    }
+}
@@ -1,22 +1,70 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::{Duration, SystemTime};
 
+use super::app_dirs::{AppDir, VERSION_CACHE_FILE};
+
 const CHECK_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60); // 24 hours
 
+pub const DEFAULT_UPDATE_API_URL: &str =
+    "https://api.github.com/repos/osteele/stitch-sync/releases/latest";
+pub const DEFAULT_UPDATE_BASE_URL: &str =
+    "https://github.com/osteele/stitch-sync/releases/download";
+
+/// Where to look for releases. These can be changed to point at a mirror.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UpdateUrls {
+    /// Returns the latest release, in the format of GitHub's releases API
+    pub api_url: String,
+    /// Release assets are downloaded from `{base_url}/v{version}/{asset}`
+    pub base_url: String,
+}
+
+impl UpdateUrls {
+    /// Uses the configured URLs, or else the public GitHub URLs.
+    pub fn new(api_url: Option<&str>, base_url: Option<&str>) -> Self {
+        Self {
+            api_url: api_url.unwrap_or(DEFAULT_UPDATE_API_URL).to_string(),
+            base_url: base_url.unwrap_or(DEFAULT_UPDATE_BASE_URL).to_string(),
+        }
+    }
+
+    pub fn download_url(&self, version: &str, asset_name: &str) -> String {
+        format!(
+            "{}/v{}/{}",
+            self.base_url.trim_end_matches('/'),
+            version,
+            asset_name
+        )
+    }
+}
+
+impl Default for UpdateUrls {
+    fn default() -> Self {
+        Self::new(None, None)
+    }
+}
+
+/// Increment this when `VersionCache` changes, so that caches written by
+/// other versions are ignored rather than misread.
+const CACHE_SCHEMA_VERSION: u32 = 1;
+
 #[derive(Serialize, Deserialize)]
 struct VersionCache {
+    /// Missing from caches written before the field was added
+    #[serde(default)]
+    schema_version: u32,
     last_check: SystemTime,
     latest_version: String,
 }
 
-pub fn get_latest_version(force_check: bool) -> Result<Option<String>> {
+pub fn get_latest_version(force_check: bool, urls: &UpdateUrls) -> Result<Option<String>> {
     let current_version = env!("CARGO_PKG_VERSION");
 
     if !force_check {
-        if let Some(cached) = read_version_cache()? {
+        if let Some(cached) = read_version_cache() {
             if cached.last_check + CHECK_INTERVAL > SystemTime::now() {
                 if cached.latest_version != current_version {
                     return Ok(Some(cached.latest_version));
@@ -29,7 +77,7 @@ pub fn get_latest_version(force_check: bool) -> Result<Option<String>> {
     // Perform fresh check
     let client = reqwest::blocking::Client::new();
     let response = client
-        .get("https://api.github.com/repos/osteele/stitch-sync/releases/latest")
+        .get(&urls.api_url)
         .header("User-Agent", "stitch-sync")
         .send()?;
 
@@ -50,25 +98,51 @@ pub fn get_latest_version(force_check: bool) -> Result<Option<String>> {
     }
 }
 
+/// Checks that a release asset can be downloaded from `url`, without
+/// downloading it, and returns its size if the server reports one.
+pub fn check_download(url: &str) -> Result<Option<u64>> {
+    let client = reqwest::blocking::Client::new();
+    let response = client
+        .head(url)
+        .header("User-Agent", "stitch-sync")
+        .send()?
+        .error_for_status()?;
+    // Read the header itself, since a HEAD response has no body to measure
+    Ok(response
+        .headers()
+        .get(reqwest::header::CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok()?.parse().ok()))
+}
+
 fn get_cache_path() -> PathBuf {
-    dirs::cache_dir()
-        .unwrap_or_else(|| PathBuf::from("."))
-        .join("stitch-sync")
-        .join("version-cache.json")
+    AppDir::Cache.file(VERSION_CACHE_FILE)
 }
 
-fn read_version_cache() -> Result<Option<VersionCache>> {
-    let path = get_cache_path();
+fn read_version_cache() -> Option<VersionCache> {
+    read_version_cache_from(&get_cache_path())
+}
+
+/// Returns `None` if there's no usable cache. A cache that can't be read or
+/// was written in another format is deleted, so that it is replaced by the
+/// next check.
+fn read_version_cache_from(path: &Path) -> Option<VersionCache> {
     if !path.exists() {
-        return Ok(None);
+        return None;
     }
 
-    let content = fs::read_to_string(path)?;
-    Ok(Some(serde_json::from_str(&content)?))
+    let cache = fs::read_to_string(path)
+        .ok()
+        .and_then(|content| serde_json::from_str::<VersionCache>(&content).ok())
+        .filter(|cache| cache.schema_version == CACHE_SCHEMA_VERSION);
+    if cache.is_none() {
+        let _ = fs::remove_file(path);
+    }
+    cache
 }
 
 fn cache_version_check(latest_version: &str) -> Result<()> {
     let cache = VersionCache {
+        schema_version: CACHE_SCHEMA_VERSION,
         last_check: SystemTime::now(),
         latest_version: latest_version.to_string(),
     };
@@ -78,3 +152,45 @@ fn cache_version_check(latest_version: &str) -> Result<()> {
     fs::write(path, serde_json::to_string(&cache)?)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_update_urls() {
+        let urls = UpdateUrls::new(None, Some("https://mirror.example.com/releases/"));
+        assert_eq!(
+            urls.download_url("1.2.0", "stitch-sync.tar.gz"),
+            "https://mirror.example.com/releases/v1.2.0/stitch-sync.tar.gz"
+        );
+        assert_eq!(urls.api_url, DEFAULT_UPDATE_API_URL);
+    }
+
+    #[test]
+    fn test_read_version_cache_from() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("version-cache.json");
+        assert!(read_version_cache_from(&path).is_none());
+
+        // Malformed caches, and caches from before the schema version, are
+        // discarded
+        for content in [
+            "{not json",
+            r#"{"last_check":{"secs_since_epoch":0,"nanos_since_epoch":0},"latest_version":"1.0.0"}"#,
+        ] {
+            fs::write(&path, content).unwrap();
+            assert!(read_version_cache_from(&path).is_none());
+            assert!(!path.exists());
+        }
+
+        let cache = VersionCache {
+            schema_version: CACHE_SCHEMA_VERSION,
+            last_check: SystemTime::now(),
+            latest_version: "1.0.0".to_string(),
+        };
+        fs::write(&path, serde_json::to_string(&cache).unwrap()).unwrap();
+        let cache = read_version_cache_from(&path).unwrap();
+        assert_eq!(cache.latest_version, "1.0.0");
+    }
+}
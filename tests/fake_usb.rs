@@ -0,0 +1,305 @@
+// The history, output manifest and config are kept out of the user's
+// directories by pointing the XDG variables at a temporary directory. Only
+// these platforms read them, so elsewhere the test would write to the real
+// ones.
+#![cfg(all(unix, not(target_os = "macos")))]
+
+use std::fs;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use stitch_sync::cli::{Commands, DrivesCommand};
+use stitch_sync::{handle_file_detection, ConversionOptions, SourceAction, UsbDrive, WatchOptions};
+
+// The environment is process-wide, so everything that depends on it is in
+// this one test, and it's set before any other thread starts.
+#[test]
+fn test_copy_to_fake_usb_drive() {
+    let usb = tempfile::tempdir().unwrap();
+    let watch_dir = tempfile::tempdir().unwrap();
+    let data_dir = tempfile::tempdir().unwrap();
+    std::env::set_var("STITCH_SYNC_FAKE_USB", usb.path());
+    // Keep the history and output manifest out of the user's directories
+    std::env::set_var("XDG_DATA_HOME", data_dir.path());
+    std::env::set_var("XDG_CACHE_HOME", data_dir.path());
+    std::env::set_var("XDG_CONFIG_HOME", data_dir.path());
+
+    let source = watch_dir.path().join("design.dst");
+    fs::write(&source, "LA:design").unwrap();
+    let options = ConversionOptions {
+        source_action: SourceAction::Archive,
+        ..Default::default()
+    };
+
+    // The machine's directory doesn't exist on the drive, so nothing is
+    // copied, and the source is left alone
+    let copy = handle_file_detection(&source, &None, &Some("EMB"), &["dst"], "dst", &options);
+    assert_eq!(copy.unwrap(), None);
+    assert!(source.exists());
+
+    fs::create_dir(usb.path().join("EMB")).unwrap();
+    let copy = handle_file_detection(&source, &None, &Some("EMB"), &["dst"], "dst", &options);
+    assert_eq!(
+        copy.unwrap(),
+        Some(usb.path().join("EMB").join("design.dst"))
+    );
+    assert_eq!(
+        fs::read_to_string(usb.path().join("EMB").join("design.dst")).unwrap(),
+        "LA:design"
+    );
+    assert!(!source.exists());
+    assert!(watch_dir.path().join("archive").join("design.dst").exists());
+
+    // The drive's manifest shows that an identical file is already there, so
+    // it isn't copied again
+    fs::write(&source, "LA:design").unwrap();
+    let options = ConversionOptions::default();
+    handle_file_detection(&source, &None, &Some("EMB"), &["dst"], "dst", &options).unwrap();
+    assert!(!usb.path().join("EMB").join("design (1).dst").exists());
+    let options = ConversionOptions {
+        force_copy: true,
+        ..Default::default()
+    };
+    handle_file_detection(&source, &None, &Some("EMB"), &["dst"], "dst", &options).unwrap();
+    assert!(usb.path().join("EMB").join("design (1).dst").exists());
+
+    // With a source root, a file in a subdirectory is copied into the same
+    // subdirectory on the drive
+    let subdir = watch_dir.path().join("hats");
+    fs::create_dir(&subdir).unwrap();
+    let source = subdir.join("cap.dst");
+    fs::write(&source, "LA:cap").unwrap();
+    let options = ConversionOptions {
+        source_roots: vec![watch_dir.path().to_path_buf()],
+        ..Default::default()
+    };
+    handle_file_detection(&source, &None, &Some("EMB"), &["dst"], "dst", &options).unwrap();
+    assert!(usb.path().join("EMB").join("hats").join("cap.dst").exists());
+
+    // The same goes for the design in an archive, whose subdirectory doesn't
+    // exist on the drive yet
+    let subdir = watch_dir.path().join("shirts");
+    fs::create_dir(&subdir).unwrap();
+    fs::write(subdir.join("tee.dst"), "LA:tee").unwrap();
+    let zipped = Command::new("zip")
+        .current_dir(&subdir)
+        .args(["-q", "tee.zip", "tee.dst"])
+        .status()
+        .unwrap();
+    assert!(zipped.success());
+    fs::remove_file(subdir.join("tee.dst")).unwrap();
+    let options = ConversionOptions {
+        source_roots: vec![watch_dir.path().to_path_buf()],
+        extract_archives: true,
+        ..Default::default()
+    };
+    let copy = handle_file_detection(
+        &subdir.join("tee.zip"),
+        &None,
+        &Some("EMB"),
+        &["dst"],
+        "dst",
+        &options,
+    );
+    let copy_path = usb.path().join("EMB").join("shirts").join("tee.dst");
+    assert_eq!(copy.unwrap(), Some(copy_path.clone()));
+    assert_eq!(fs::read_to_string(copy_path).unwrap(), "LA:tee");
+
+    // An image is a preview, so it's saved but not copied to the drive, and
+    // the source is kept
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        use stitch_sync::services::inkscape::{SUPPORTED_READ_FORMATS, SUPPORTED_WRITE_FORMATS};
+        use stitch_sync::Inkscape;
+
+        // Called as `inkscape INPUT --export-filename OUTPUT`. Each output is
+        // logged.
+        let fake_inkscape = data_dir.path().join("inkscape");
+        fs::write(
+            &fake_inkscape,
+            "#!/bin/sh\necho \"$3\" >> \"$0.log\"\ncp \"$1\" \"$3\"\n",
+        )
+        .unwrap();
+        fs::set_permissions(&fake_inkscape, fs::Permissions::from_mode(0o755)).unwrap();
+        let inkscape = Some(Inkscape {
+            path: fake_inkscape,
+            version: None,
+            has_inkstitch: true,
+            supported_read_formats: &SUPPORTED_READ_FORMATS,
+            supported_write_formats: &SUPPORTED_WRITE_FORMATS,
+        });
+        let source = watch_dir.path().join("rose.dst");
+        fs::write(&source, "LA:rose").unwrap();
+        let options = ConversionOptions {
+            quiet: true,
+            source_action: SourceAction::Archive,
+            ..Default::default()
+        };
+        let output =
+            handle_file_detection(&source, &inkscape, &Some("EMB"), &["jef"], "png", &options)
+                .unwrap();
+        assert_eq!(output, Some(watch_dir.path().join("rose.png")));
+        assert!(watch_dir.path().join("rose.png").exists());
+        assert!(!usb.path().join("EMB").join("rose.png").exists());
+        assert!(source.exists());
+
+        // With copy_source_too, the original is copied alongside the
+        // converted file
+        let options = ConversionOptions {
+            quiet: true,
+            copy_source_too: true,
+            ..Default::default()
+        };
+        handle_file_detection(&source, &inkscape, &Some("EMB"), &["jef"], "jef", &options).unwrap();
+        assert!(usb.path().join("EMB").join("rose.jef").exists());
+        assert_eq!(
+            fs::read_to_string(usb.path().join("EMB").join("source").join("rose.dst")).unwrap(),
+            "LA:rose"
+        );
+
+        // Vector artwork is ignored unless import_vectors is set. A PDF is
+        // then imported into an SVG, which is converted.
+        let source = watch_dir.path().join("artwork.pdf");
+        fs::write(&source, "%PDF-1.4").unwrap();
+        let options = ConversionOptions {
+            quiet: true,
+            ..Default::default()
+        };
+        let output =
+            handle_file_detection(&source, &inkscape, &Some("EMB"), &["jef"], "jef", &options)
+                .unwrap();
+        assert_eq!(output, None);
+        assert!(!watch_dir.path().join("artwork.jef").exists());
+        let options = ConversionOptions {
+            quiet: true,
+            import_vectors: true,
+            ..Default::default()
+        };
+        handle_file_detection(&source, &inkscape, &Some("EMB"), &["jef"], "jef", &options).unwrap();
+        assert_eq!(
+            fs::read_to_string(usb.path().join("EMB").join("artwork.jef")).unwrap(),
+            "%PDF-1.4"
+        );
+        let log = fs::read_to_string(data_dir.path().join("inkscape.log")).unwrap();
+        let exports: Vec<_> = log.lines().rev().take(2).collect();
+        assert!(exports[1].ends_with(".svg"), "{}", log);
+        assert!(exports[0].ends_with("artwork.jef"), "{}", log);
+    }
+
+    // The watcher copies a design that arrives in the watch directory. It's
+    // written under a hidden name and renamed, as browsers do, so that it's
+    // complete when it's noticed.
+    let arrivals = tempfile::tempdir().unwrap();
+    let arrivals_dir = arrivals.path().to_path_buf();
+    let download = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(500));
+        let partial = arrivals_dir.join(".arrived.dst.part");
+        fs::write(&partial, "LA:arrived").unwrap();
+        fs::rename(&partial, arrivals_dir.join("arrived.dst")).unwrap();
+    });
+    let options = WatchOptions {
+        conversion: ConversionOptions {
+            no_convert: true,
+            quiet: true,
+            ..Default::default()
+        },
+        assume_yes: true,
+        poll_interval: Duration::from_millis(50),
+        heartbeat: None,
+        idle_timeout: Some(Duration::from_secs(3)),
+        ..Default::default()
+    };
+    stitch_sync::watch_dir(
+        &[arrivals.path().to_path_buf()],
+        &Some("EMB"),
+        &["dst"],
+        "dst",
+        None,
+        &options,
+    );
+    download.join().unwrap();
+    assert_eq!(
+        fs::read_to_string(usb.path().join("EMB").join("arrived.dst")).unwrap(),
+        "LA:arrived"
+    );
+
+    // The `drives` command describes the fake drive
+    let drives = UsbDrive::list();
+    assert_eq!(drives.len(), 1);
+    assert!(drives[0].filesystem_type().is_some());
+    assert!(drives[0].free_space().is_some());
+
+    // `drives test` writes, reads back, and deletes a file on the drive
+    let mut output = Vec::new();
+    Commands::Drives {
+        command: Some(DrivesCommand::Test { drive: None }),
+    }
+    .execute(&mut output)
+    .unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("Read back"), "{}", output);
+    assert!(!usb.path().join(".stitch-sync-test").exists());
+    assert!(Commands::Drives {
+        command: Some(DrivesCommand::Test {
+            drive: Some("NOT-A-DRIVE".to_string()),
+        }),
+    }
+    .execute(&mut Vec::new())
+    .is_err());
+}
+
+// With NDJSON output, stdout carries only events, even when the drive is
+// ejected after a copy. This runs the program, so it has its own environment.
+#[test]
+fn test_ndjson_stdout_has_only_events() {
+    let usb = tempfile::tempdir().unwrap();
+    let watch_dir = tempfile::tempdir().unwrap();
+    let data_dir = tempfile::tempdir().unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_stitch-sync"))
+        .args(["watch", "--machine", "Brother PE800", "--no-convert"])
+        .args([
+            "--eject-after",
+            "--yes",
+            "--stdout-format",
+            "ndjson",
+            "--dir",
+        ])
+        .arg(watch_dir.path())
+        .env("STITCH_SYNC_FAKE_USB", usb.path())
+        .env("HOME", data_dir.path())
+        .env("XDG_DATA_HOME", data_dir.path())
+        .env("XDG_CACHE_HOME", data_dir.path())
+        .env("XDG_CONFIG_HOME", data_dir.path())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    std::thread::sleep(Duration::from_secs(1));
+    let partial = watch_dir.path().join(".design.dst.part");
+    fs::write(&partial, "LA:design").unwrap();
+    fs::rename(&partial, watch_dir.path().join("design.dst")).unwrap();
+    // This machine's designs go in the root of the drive
+    let copy = usb.path().join("design.dst");
+    let start = Instant::now();
+    while !copy.exists() && start.elapsed() < Duration::from_secs(10) {
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    // Let the eject finish
+    std::thread::sleep(Duration::from_millis(500));
+    child.kill().unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert!(copy.exists());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("copied"), "{}", stdout);
+    for line in stdout.lines() {
+        assert!(
+            serde_json::from_str::<serde_json::Value>(line).is_ok(),
+            "Not JSON: {}",
+            line
+        );
+    }
+}